@@ -1,5 +1,5 @@
 use std::any::TypeId;
-use std::rc::Rc;
+use std::sync::Arc;
 use crate::object::{Object};
 use crate::error::{Result, PdfError};
 
@@ -19,23 +19,23 @@ impl<T> AnyObject for T
 }
 
 #[derive(Clone)]
-pub struct Any(Rc<dyn AnyObject>);
+pub struct Any(Arc<dyn AnyObject + Send + Sync>);
 
 impl Any {
-    pub fn downcast<T>(self) -> Result<Rc<T>> 
+    pub fn downcast<T>(self) -> Result<Arc<T>> 
         where T: AnyObject + 'static
     {
         if TypeId::of::<T>() == self.0.type_id() {
             unsafe {
-                let raw: *const dyn AnyObject = Rc::into_raw(self.0);
-                Ok(Rc::from_raw(raw as *const T))
+                let raw: *const (dyn AnyObject + Send + Sync) = Arc::into_raw(self.0);
+                Ok(Arc::from_raw(raw as *const T))
             }
         } else {
             Err(type_mismatch::<T>(&self))
         }
     }
-    pub fn new<T>(rc: Rc<T>) -> Any
-        where T: AnyObject + 'static
+    pub fn new<T>(rc: Arc<T>) -> Any
+        where T: AnyObject + Send + Sync + 'static
     {
         Any(rc as _)
     }
@@ -5,6 +5,12 @@ use crate::xref::XRefTable;
 use crate::primitive::Dictionary;
 use crate::object::*;
 use std::ops::Deref;
+use std::borrow::Cow;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::fs;
 
 use std::ops::{
     RangeFull,
@@ -16,13 +22,22 @@ use std::ops::{
 pub const MAX_ID: u32 = 1_000_000;
 
 pub trait Backend: Sized {
-    fn read<T: IndexRange>(&self, range: T) -> Result<&[u8]>;
+    fn read<T: IndexRange>(&self, range: T) -> Result<Cow<'_, [u8]>>;
     //fn write<T: IndexRange>(&mut self, range: T) -> Result<&mut [u8]>;
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    /// Calls `f` with the data from `pos` on, for things whose length is not known in advance.
+    ///
+    /// This passes everything up to the end of the file. Backends that don't have the whole file
+    /// at hand can pass less, and have to call `f` again with more data if it fails.
+    fn with_data_from<R>(&self, pos: usize, mut f: impl FnMut(&[u8]) -> Result<R>) -> Result<R> {
+        let data = t!(self.read(pos ..));
+        f(&data)
+    }
+
     /// Returns the offset of the beginning of the file, i.e., where the `%PDF-1.5` header is.
     /// (currently only used internally!)
     fn locate_start_offset(&self) -> Result<usize> {
@@ -43,10 +58,19 @@ pub trait Backend: Sized {
         // `\nPOS\n%%EOF` where POS is the position encoded as base 10 integer.
         // u64::MAX has 20 digits + \n\n(2) + %%EOF(5) = 27 bytes max.
 
-        let mut lexer = Lexer::new(t!(self.read(..)));
-        lexer.set_pos_from_end(0);
-        t!(lexer.seek_substr_back(b"startxref"));
-        t!(lexer.next()).to::<usize>()
+        // Usually it is in the last kilobyte, but there may be garbage after %%EOF.
+        let mut tail = 1024;
+        loop {
+            let start = self.len().saturating_sub(tail);
+            let data = t!(self.read(start ..));
+            let mut lexer = Lexer::new(&data);
+            lexer.set_pos_from_end(0);
+            match lexer.seek_substr_back(b"startxref") {
+                Ok(_) => return t!(lexer.next()).to::<usize>(),
+                Err(e) if start == 0 => return Err(e),
+                Err(_) => tail *= 16
+            }
+        }
     }
 
     /// Used internally by File, but could also be useful for applications that want to look at the raw PDF objects.
@@ -57,9 +81,9 @@ pub trait Backend: Sized {
             bail!("XRef offset outside file bounds");
         }
 
-        let mut lexer = Lexer::new(t!(self.read(pos ..)));
-        
-        let (xref_sections, trailer) = t!(read_xref_and_trailer_at(&mut lexer, &NoResolve));
+        let (xref_sections, trailer) = t!(self.with_data_from(pos, |data| {
+            read_xref_and_trailer_at(&mut Lexer::new(data), &NoResolve)
+        }));
         
         let highest_id = t!(trailer.get("Size")
             .ok_or_else(|| PdfError::MissingEntry {field: "Size".into(), typ: "XRefTable"})?
@@ -82,8 +106,9 @@ pub trait Backend: Sized {
         trace!("READ XREF AND TABLE");
         while let Some(prev_xref_offset) = prev_trailer {
             let pos = t!(start_offset.checked_add(prev_xref_offset as usize).ok_or(PdfError::Invalid));
-            let mut lexer = Lexer::new(t!(self.read(pos..)));
-            let (xref_sections, trailer) = t!(read_xref_and_trailer_at(&mut lexer, &NoResolve));
+            let (xref_sections, trailer) = t!(self.with_data_from(pos, |data| {
+                read_xref_and_trailer_at(&mut Lexer::new(data), &NoResolve)
+            }));
             
             for section in xref_sections {
                 refs.add_entries_from(section);
@@ -108,9 +133,9 @@ pub trait Backend: Sized {
 
 
 impl<T> Backend for T where T: Deref<Target=[u8]> { //+ DerefMut<Target=[u8]> {
-    fn read<R: IndexRange>(&self, range: R) -> Result<&[u8]> {
+    fn read<R: IndexRange>(&self, range: R) -> Result<Cow<'_, [u8]>> {
         let r = t!(range.to_range(self.len()));
-        Ok(&self[r])
+        Ok(Cow::Borrowed(&self[r]))
     }
     /*
     fn write<R: IndexRange>(&mut self, range: R) -> Result<&mut [u8]> {
@@ -123,6 +148,132 @@ impl<T> Backend for T where T: Deref<Target=[u8]> { //+ DerefMut<Target=[u8]> {
     }
 }
 
+/// Size of the blocks that `LazyBackend` reads and caches.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// A backend that reads from a `Read + Seek` source on demand, instead of holding the whole file.
+///
+/// The most recently used blocks are kept in memory, up to the capacity given on creation.
+/// Reads that are larger than the cache go directly to the source.
+pub struct LazyBackend<R> {
+    source: Mutex<R>,
+    len: usize,
+    cache: Mutex<BlockCache>,
+}
+
+struct BlockCache {
+    // block index -> (data, last use)
+    blocks: HashMap<usize, (Vec<u8>, u64)>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl<R: Read + Seek> LazyBackend<R> {
+    /// Default number of bytes kept in the cache.
+    pub const DEFAULT_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+    pub fn new(source: R) -> Result<Self> {
+        Self::with_cache_size(source, Self::DEFAULT_CACHE_SIZE)
+    }
+
+    /// Keep at most `cache_size` bytes (rounded up to whole blocks) in memory.
+    pub fn with_cache_size(mut source: R, cache_size: usize) -> Result<Self> {
+        let len = source.seek(SeekFrom::End(0))?;
+        Ok(LazyBackend {
+            source: Mutex::new(source),
+            len: len as usize,
+            cache: Mutex::new(BlockCache {
+                blocks: HashMap::new(),
+                capacity: std::cmp::max(cache_size.div_ceil(BLOCK_SIZE), 1),
+                clock: 0,
+            }),
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source.into_inner().unwrap()
+    }
+
+    fn read_exact_at(&self, pos: usize, buf: &mut [u8]) -> io::Result<()> {
+        let mut source = self.source.lock().unwrap();
+        source.seek(SeekFrom::Start(pos as u64))?;
+        source.read_exact(buf)
+    }
+
+    fn read_block(&self, index: usize, out: &mut Vec<u8>, range: std::ops::Range<usize>) -> Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let clock = cache.clock;
+        if let Some((data, last_use)) = cache.blocks.get_mut(&index) {
+            *last_use = clock;
+            out.extend_from_slice(&data[range]);
+            return Ok(());
+        }
+
+        let start = index * BLOCK_SIZE;
+        let mut data = vec![0; std::cmp::min(BLOCK_SIZE, self.len - start)];
+        self.read_exact_at(start, &mut data)?;
+        out.extend_from_slice(&data[range]);
+
+        if cache.blocks.len() >= cache.capacity {
+            let oldest = cache.blocks.iter().min_by_key(|(_, &(_, last_use))| last_use).map(|(&i, _)| i);
+            if let Some(oldest) = oldest {
+                cache.blocks.remove(&oldest);
+            }
+        }
+        cache.blocks.insert(index, (data, clock));
+        Ok(())
+    }
+}
+impl LazyBackend<fs::File> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(fs::File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> Backend for LazyBackend<R> {
+    fn read<T: IndexRange>(&self, range: T) -> Result<Cow<'_, [u8]>> {
+        let r = t!(range.to_range(self.len));
+        let mut out = Vec::with_capacity(r.len());
+        if r.len() > self.cache.lock().unwrap().capacity * BLOCK_SIZE {
+            out.resize(r.len(), 0);
+            self.read_exact_at(r.start, &mut out)?;
+            return Ok(Cow::Owned(out));
+        }
+
+        let mut pos = r.start;
+        while pos < r.end {
+            let index = pos / BLOCK_SIZE;
+            let block_start = index * BLOCK_SIZE;
+            let end = std::cmp::min(r.end, block_start + BLOCK_SIZE);
+            t!(self.read_block(index, &mut out, pos - block_start .. end - block_start));
+            pos = end;
+        }
+        Ok(Cow::Owned(out))
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // start with one block and double the window while `f` runs out of data, until the end of the file is reached
+    fn with_data_from<T>(&self, pos: usize, mut f: impl FnMut(&[u8]) -> Result<T>) -> Result<T> {
+        let mut window = BLOCK_SIZE;
+        loop {
+            let end = std::cmp::min(pos.saturating_add(window), self.len);
+            let data = t!(self.read(pos .. end));
+            // end the window after whitespace, so the last token isn't cut short
+            let data = match data.iter().rposition(|&b| b" \t\r\n\x0c\0".contains(&b)) {
+                Some(n) if end < self.len => &data[.. n + 1],
+                _ => &data[..]
+            };
+            match f(data) {
+                Err(e) if e.is_eof() && end < self.len => window *= 2,
+                result => return result
+            }
+        }
+    }
+}
+
 /// `IndexRange` is implemented by Rust's built-in range types, produced
 /// by range syntax like `..`, `a..`, `..b` or `c..d`.
 pub trait IndexRange
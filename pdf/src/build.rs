@@ -1,6 +1,16 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::sync::Arc;
+
 use crate::object::*;
+use crate::object::Rect;
 use crate::content::*;
 use crate::error::Result;
+use crate::cmap::CMap;
+use crate::font::{Font, FontData, FontMetrics, FontProgram, TrueTypeFont, flags};
+use crate::primitive::{Dictionary, PdfString, Primitive};
 
 #[derive(Default)]
 pub struct PageBuilder {
@@ -37,6 +47,344 @@ impl PageBuilder {
     pub fn resources(&mut self, resources: MaybeRef<Resources>) {
         self.resources = Some(resources);
     }
+    /// Add `font` to the resources of the page and return its name for `Op::TextFont`.
+    pub fn add_font(&mut self, font: Ref<Font>) -> String {
+        // resources shared with other pages are copied
+        let mut resources = match self.resources.take() {
+            Some(MaybeRef::Direct(r)) => r,
+            Some(MaybeRef::Indirect(r)) => Arc::new((*r).clone()),
+            None => Arc::new(Resources::default())
+        };
+        let fonts = &mut Arc::make_mut(&mut resources).fonts;
+        let name = match fonts.iter().find(|&(_, &f)| f == font) {
+            Some((name, _)) => name.clone(),
+            None => {
+                let name = (1 ..).map(|i| format!("F{}", i)).find(|n| !fonts.contains_key(n)).unwrap();
+                fonts.insert(name.clone(), font);
+                name
+            }
+        };
+        self.resources = Some(MaybeRef::Direct(resources));
+        name
+    }
+}
+
+/// Embeds a TrueType font as a `CIDFontType2` font with only the glyphs that were used.
+///
+/// Text is encoded with [`FontBuilder::encode`] and the font written with
+/// [`FontBuilder::build`] once all text is known.
+pub struct FontBuilder {
+    font: TrueTypeFont,
+    /// the glyphs used and the text they stand for
+    glyphs: BTreeMap<u16, String>,
+}
+impl FontBuilder {
+    /// Only fonts with TrueType outlines are supported, not OpenType fonts with a `CFF` table.
+    pub fn from_data(data: &[u8]) -> Result<FontBuilder> {
+        let font = t!(TrueTypeFont::parse(data));
+        if font.table(b"glyf").is_none() {
+            bail!("font has no TrueType outlines");
+        }
+        Ok(FontBuilder { font, glyphs: BTreeMap::new() })
+    }
+
+    /// Encode `text` for `Op::TextDraw`. Characters missing from the font become `.notdef`.
+    pub fn encode(&mut self, text: &str) -> PdfString {
+        let mut data = Vec::with_capacity(2 * text.len());
+        for c in text.chars() {
+            let gid = match self.font.gid_for_unicode(c) {
+                Some(gid) if (gid as usize) < self.font.glyph_count() => gid as u16,
+                _ => {
+                    warn!("no glyph for {:?}", c);
+                    0
+                }
+            };
+            if gid != 0 {
+                self.glyphs.entry(gid).or_insert_with(|| c.to_string());
+            }
+            data.extend_from_slice(&gid.to_be_bytes());
+        }
+        PdfString::new(data)
+    }
+
+    /// Write the font and its subset. Add it to a page with [`PageBuilder::add_font`].
+    pub fn build(self, update: &mut impl Updater) -> Result<Ref<Font>> {
+        let font = &self.font;
+        let glyphs: BTreeSet<u16> = self.glyphs.keys().cloned().collect();
+        let data = t!(font.subset(&glyphs));
+        let name = format!("{}+{}", subset_tag(&glyphs), font.postscript_name().unwrap_or_else(|| "Font".into()));
+        let scale = 1000. / font.units_per_em() as f32;
+        let scaled = |v: f32| Primitive::Integer((v * scale).round() as i32);
+
+        let mut info = Dictionary::new();
+        info.insert("Length1", Primitive::Integer(data.len() as i32));
+        let font_file = t!(update.create(t!(Stream::new(info, data).flate_encode())));
+
+        let bbox = font.bbox().unwrap_or(Rect { left: 0., bottom: 0., right: 0., top: 0. });
+        let (ascent, descent) = font.ascent_descent().unwrap_or((bbox.top, bbox.bottom));
+        let mut descriptor = Dictionary::new();
+        descriptor.insert("Type", Primitive::name("FontDescriptor"));
+        descriptor.insert("FontName", Primitive::name(name.clone()));
+        // glyphs are selected by id, not by a standard encoding
+        let fixed = if font.is_fixed_pitch() { flags::FixedPitch } else { 0 };
+        descriptor.insert("Flags", Primitive::Integer((flags::Symbolic | fixed) as i32));
+        descriptor.insert("FontBBox", Primitive::Array(
+            [bbox.left, bbox.bottom, bbox.right, bbox.top].iter().map(|&v| scaled(v)).collect()
+        ));
+        descriptor.insert("ItalicAngle", Primitive::Number(font.italic_angle()));
+        descriptor.insert("Ascent", scaled(ascent));
+        descriptor.insert("Descent", scaled(descent));
+        descriptor.insert("CapHeight", scaled(font.cap_height().unwrap_or(ascent)));
+        // not in the font, a common estimate
+        descriptor.insert("StemV", Primitive::Integer(80));
+        descriptor.insert("FontFile2", Primitive::Reference(font_file.get_ref().get_inner()));
+        let descriptor = t!(update.create(descriptor));
+
+        // runs of consecutive glyphs: first [w1 w2 …]
+        let mut widths = vec![];
+        let mut run: Vec<Primitive> = vec![];
+        let mut last = None;
+        for &gid in &glyphs {
+            if last.is_some_and(|last| last + 1 != gid) {
+                widths.push(Primitive::Array(std::mem::take(&mut run)));
+            }
+            if run.is_empty() {
+                widths.push(Primitive::Integer(gid as i32));
+            }
+            run.push(scaled(font.advance(gid as u32).unwrap_or(0) as f32));
+            last = Some(gid);
+        }
+        if !run.is_empty() {
+            widths.push(Primitive::Array(run));
+        }
+
+        let mut system_info = Dictionary::new();
+        system_info.insert("Registry", Primitive::String(PdfString::new(b"Adobe".to_vec())));
+        system_info.insert("Ordering", Primitive::String(PdfString::new(b"Identity".to_vec())));
+        system_info.insert("Supplement", Primitive::Integer(0));
+
+        let mut cid_font = Dictionary::new();
+        cid_font.insert("Type", Primitive::name("Font"));
+        cid_font.insert("Subtype", Primitive::name("CIDFontType2"));
+        cid_font.insert("BaseFont", Primitive::name(name.clone()));
+        cid_font.insert("CIDSystemInfo", Primitive::Dictionary(system_info));
+        cid_font.insert("FontDescriptor", Primitive::Reference(descriptor.get_ref().get_inner()));
+        cid_font.insert("DW", scaled(font.advance(0).unwrap_or(0) as f32));
+        cid_font.insert("W", Primitive::Array(widths));
+        // glyph ids are kept in the subset
+        cid_font.insert("CIDToGIDMap", Primitive::name("Identity"));
+        let cid_font = t!(update.create(cid_font));
+
+        let to_unicode = t!(Stream::new((), to_unicode(&self.glyphs).into_bytes()).flate_encode());
+        let to_unicode = t!(update.create(to_unicode));
+
+        let mut type0 = Dictionary::new();
+        type0.insert("Type", Primitive::name("Font"));
+        type0.insert("Subtype", Primitive::name("Type0"));
+        type0.insert("BaseFont", Primitive::name(name));
+        type0.insert("Encoding", Primitive::name("Identity-H"));
+        type0.insert("DescendantFonts", Primitive::Array(vec![Primitive::Reference(cid_font.get_ref().get_inner())]));
+        type0.insert("ToUnicode", Primitive::Reference(to_unicode.get_ref().get_inner()));
+        let type0 = t!(update.create(type0));
+        Ok(Ref::new(type0.get_ref().get_inner()))
+    }
+}
+
+/// A character code of a font, its glyph name in simple fonts and its glyph in the embedded font.
+#[derive(Clone)]
+struct Code {
+    bytes: Vec<u8>,
+    name: Option<String>,
+    gid: Option<u16>,
+}
+
+/// Encodes text for a font, for `Op::TextDrawAdjusted`.
+///
+/// Characters are looked up in the `/ToUnicode` map of the font and, for simple fonts, in its
+/// encoding. Composite fonts with an embedded TrueType program, `Identity` encoding and
+/// `/CIDToGIDMap` also find the characters in the `cmap` of the program. Only codes that stand for
+/// a single character are used, so there are no ligatures.
+///
+/// The standard 14 fonts are kerned if their metrics are bundled, embedded TrueType fonts with
+/// their `kern` table. Other embedded fonts and the `GPOS` kerning of OpenType fonts are not kerned.
+pub struct TextEncoder {
+    codes: HashMap<char, Code>,
+    metrics: Option<&'static FontMetrics>,
+    /// the embedded TrueType program, if codes are glyph ids
+    identity: Option<TrueTypeFont>,
+    /// kerning of the embedded font by glyph ids, in 1/1000 em
+    kerning: HashMap<(u16, u16), f32>,
+}
+
+/// The result of [`TextEncoder::encode`].
+#[derive(Debug)]
+pub struct EncodedText {
+    pub array: Vec<TextDrawAdjusted>,
+    /// The characters that the font can't show, which are left out of `array`.
+    pub missing: Vec<char>,
+}
+impl EncodedText {
+    pub fn into_op(self) -> Op {
+        Op::TextDrawAdjusted { array: self.array }
+    }
+}
+
+impl TextEncoder {
+    pub fn new(font: &Font) -> Result<TextEncoder> {
+        let to_unicode = match font.to_unicode() {
+            Some(map) => Some(t!(map)),
+            None => None
+        };
+        let program = match font.program() {
+            Some(Ok(program)) => Some(program),
+            Some(Err(e)) => {
+                warn!("can't read the program of {}: {}", font.name, e);
+                None
+            }
+            None => None
+        };
+        let gid = |code: u32, len: usize| {
+            program.as_ref().and_then(|p| font.gid(p, code, len)).and_then(|gid| u16::try_from(gid).ok())
+        };
+        let mut codes = HashMap::new();
+        let mut add = |text: &str, bytes: Vec<u8>, name: Option<&str>, gid: Option<u16>| {
+            let mut chars = text.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                codes.entry(c).or_insert_with(|| Code { bytes, name: name.map(String::from), gid });
+            }
+        };
+        if let Ok(FontData::Type0(_)) = font.data {
+            let identity = CMap::identity(false);
+            let cmap = font.cmap().unwrap_or(&identity);
+            // lowest codes first, for characters with several
+            let mut entries: Vec<_> = to_unicode.iter().flat_map(|map| map.iter()).collect();
+            entries.sort_unstable();
+            for (code, text) in entries {
+                if let Some(len) = cmap.code_length(code) {
+                    add(text, code.to_be_bytes()[4 - len ..].to_vec(), None, gid(code, len));
+                }
+            }
+        } else {
+            let encoding = font.simple_encoding();
+            for code in 0 ..= 255 {
+                let name = encoding.as_ref().and_then(|e| e.glyph_name(code));
+                if let Some(text) = to_unicode.as_ref().and_then(|map| map.get_code(code as u32)) {
+                    add(text, vec![code], name, gid(code as u32, 1));
+                }
+            }
+        }
+
+        let truetype = match program {
+            Some(FontProgram::TrueType(font)) => Some(font),
+            _ => None
+        };
+        let kerning = match truetype {
+            Some(ref tt) => {
+                let scale = 1000. / tt.units_per_em() as f32;
+                tt.kerning().unwrap_or_else(|e| {
+                    warn!("can't read the kerning of {}: {}", font.name, e);
+                    HashMap::new()
+                }).into_iter().map(|(pair, value)| (pair, value as f32 * scale)).collect()
+            }
+            None => HashMap::new()
+        };
+        // two byte codes that are CIDs, which are glyph ids
+        let identity_codes = matches!(font.data, Ok(FontData::Type0(_)))
+            && matches!(font.cmap().map(CMap::name), None | Some(Some("Identity-H")) | Some(Some("Identity-V")))
+            && font.cid_to_gid_map().is_none();
+        Ok(TextEncoder {
+            codes,
+            metrics: font.standard_metrics(),
+            identity: truetype.filter(|_| identity_codes),
+            kerning,
+        })
+    }
+
+    /// The code of `c`, from the `ToUnicode` map or else the `cmap` of the embedded font.
+    fn code(&self, c: char) -> Option<Cow<'_, Code>> {
+        if let Some(code) = self.codes.get(&c) {
+            return Some(Cow::Borrowed(code));
+        }
+        let gid = u16::try_from(self.identity.as_ref()?.gid_for_unicode(c)?).ok()?;
+        Some(Cow::Owned(Code { bytes: gid.to_be_bytes().to_vec(), name: None, gid: Some(gid) }))
+    }
+
+    /// The kerning between two codes in 1/1000 em, positive values move them apart.
+    fn kerning(&self, left: &Code, right: &Code) -> f32 {
+        if let (Some(metrics), Some(l), Some(r)) = (self.metrics, left.name.as_deref(), right.name.as_deref()) {
+            return metrics.kerning(l, r);
+        }
+        match (left.gid, right.gid) {
+            (Some(l), Some(r)) => self.kerning.get(&(l, r)).cloned().unwrap_or(0.),
+            _ => 0.
+        }
+    }
+
+    /// Encode `text`, with kerning as the spacing between the strings.
+    pub fn encode(&self, text: &str) -> EncodedText {
+        let mut array = vec![];
+        let mut missing = vec![];
+        let mut current = vec![];
+        let mut last: Option<Cow<Code>> = None;
+        for c in text.chars() {
+            let code = match self.code(c) {
+                Some(code) => code,
+                None => {
+                    missing.push(c);
+                    last = None;
+                    continue;
+                }
+            };
+            let kerning = match last {
+                Some(ref left) => self.kerning(left, &code),
+                None => 0.
+            };
+            if kerning != 0. {
+                array.push(TextDrawAdjusted::Text(PdfString::new(std::mem::take(&mut current))));
+                // positive values move to the left
+                array.push(TextDrawAdjusted::Spacing(-kerning));
+            }
+            current.extend_from_slice(&code.bytes);
+            last = Some(code);
+        }
+        if !current.is_empty() {
+            array.push(TextDrawAdjusted::Text(PdfString::new(current)));
+        }
+        EncodedText { array, missing }
+    }
+}
+
+/// Six capital letters that differ between subsets.
+fn subset_tag(glyphs: &BTreeSet<u16>) -> String {
+    let mut hash = glyphs.iter().fold(0u32, |h, &gid| h.wrapping_mul(31).wrapping_add(gid as u32));
+    (0 .. 6).map(|_| {
+        let c = (b'A' + (hash % 26) as u8) as char;
+        hash /= 26;
+        c
+    }).collect()
+}
+
+/// A `ToUnicode` CMap for two byte glyph ids.
+fn to_unicode(glyphs: &BTreeMap<u16, String>) -> String {
+    let mut cmap = String::from("/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+        /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+        /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+        1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n");
+    let entries: Vec<_> = glyphs.iter().collect();
+    // at most 100 entries per block
+    for block in entries.chunks(100) {
+        writeln!(cmap, "{} beginbfchar", block.len()).unwrap();
+        for &(gid, text) in block {
+            write!(cmap, "<{:04X}> <", gid).unwrap();
+            for unit in text.encode_utf16() {
+                write!(cmap, "{:04X}", unit).unwrap();
+            }
+            cmap.push_str(">\n");
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    cmap
 }
 
 pub struct CatalogBuilder {
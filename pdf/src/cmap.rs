@@ -0,0 +1,520 @@
+//! CMaps
+//!
+//! A CMap splits the byte strings shown with a composite font into character codes and maps
+//! those to CIDs, or, for `/ToUnicode` CMaps, to Unicode text.
+//!
+//! The predefined CMaps that are referenced by name are not bundled. `Identity-H` and `Identity-V`
+//! are always available; the others are read from the directory set with [`CMap::set_resource_dir`]
+//! (a checkout of Adobe's `cmap-resources`, or a directory with the CMap files themselves).
+//! Without the files, the codespace of the CMap is still known, and the
+//! Unicode and the CJK legacy CMaps (Shift-JIS, EUC, GBK, Big5 and UHC) can still be decoded to text,
+//! but [`CMap::cid`] fails with [`PdfError::MissingCMap`].
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::encoding::glyph_name_to_unicode;
+use crate::error::{PdfError, Result};
+use crate::object::{NoResolve, Object, Resolve, Stream};
+use crate::parser::{Lexer, parse_with_lexer};
+use crate::primitive::{Dictionary, Primitive};
+
+mod charsets;
+use self::charsets::Charset;
+
+/// Nesting limit for `usecmap`.
+const MAX_DEPTH: usize = 8;
+
+/// The directory of the predefined CMap files, see [`CMap::set_resource_dir`].
+static RESOURCE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CodespaceRange {
+    len: usize,
+    low: [u8; 4],
+    high: [u8; 4],
+}
+impl CodespaceRange {
+    fn new(low: &[u8], high: &[u8]) -> Option<CodespaceRange> {
+        if low.len() != high.len() || low.is_empty() || low.len() > 4 {
+            return None;
+        }
+        let mut range = CodespaceRange { len: low.len(), low: [0; 4], high: [0; 4] };
+        range.low[.. low.len()].copy_from_slice(low);
+        range.high[.. high.len()].copy_from_slice(high);
+        Some(range)
+    }
+    fn contains(&self, bytes: &[u8]) -> bool {
+        bytes.len() == self.len && bytes.iter().enumerate().all(|(i, &b)| self.low[i] <= b && b <= self.high[i])
+    }
+}
+
+/// Consecutive codes that map to consecutive CIDs
+#[derive(Debug, Clone, Copy)]
+struct CidRange {
+    len: usize,
+    low: u32,
+    high: u32,
+    cid: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CMap {
+    name: Option<String>,
+    vertical: bool,
+    codespace: Vec<CodespaceRange>,
+    cids: Vec<CidRange>,
+    notdef: Vec<CidRange>,
+    unicode: HashMap<u32, String>,
+    charset: Option<Charset>,
+    /// the CID mappings of a predefined CMap that couldn't be found
+    missing: bool,
+}
+
+impl CMap {
+    /// Parse a CMap program. `usecmap` only works for predefined CMaps.
+    pub fn parse(data: &[u8]) -> Result<CMap> {
+        CMap::parse_with_depth(data, 0)
+    }
+
+    fn parse_with_depth(data: &[u8], depth: usize) -> Result<CMap> {
+        let mut cmap = CMap::default();
+        let mut lexer = Lexer::new(data);
+        let mut last_name: Option<String> = None;
+        // the lengths of the codes seen in mappings, for CMaps without codespace
+        let mut code_lengths = [false; 5];
+
+        while let Ok(token) = lexer.next() {
+            match token.as_slice() {
+                b"begincodespacerange" => while let Some(entry) = t!(next_entry(&mut lexer, 2)) {
+                    let (low, high) = (t!(entry[0].as_string()).as_bytes(), t!(entry[1].as_string()).as_bytes());
+                    match CodespaceRange::new(low, high) {
+                        Some(range) => cmap.codespace.push(range),
+                        None => bail!("invalid codespace range {:?} {:?}", low, high)
+                    }
+                },
+                b"begincidrange" | b"beginnotdefrange" => {
+                    let notdef = token.as_slice() == b"beginnotdefrange";
+                    while let Some(entry) = t!(next_entry(&mut lexer, 3)) {
+                        let (low, high) = (t!(entry[0].as_string()).as_bytes(), t!(entry[1].as_string()).as_bytes());
+                        let range = CidRange { len: low.len(), low: t!(code(low)), high: t!(code(high)), cid: t!(entry[2].as_u32()) };
+                        code_lengths[range.len] = true;
+                        if notdef {
+                            cmap.notdef.push(range);
+                        } else {
+                            cmap.cids.push(range);
+                        }
+                    }
+                }
+                b"begincidchar" | b"beginnotdefchar" => {
+                    let notdef = token.as_slice() == b"beginnotdefchar";
+                    while let Some(entry) = t!(next_entry(&mut lexer, 2)) {
+                        let src = t!(entry[0].as_string()).as_bytes();
+                        let code = t!(code(src));
+                        let range = CidRange { len: src.len(), low: code, high: code, cid: t!(entry[1].as_u32()) };
+                        code_lengths[range.len] = true;
+                        if notdef {
+                            cmap.notdef.push(range);
+                        } else {
+                            cmap.cids.push(range);
+                        }
+                    }
+                }
+                b"beginbfchar" => while let Some(entry) = t!(next_entry(&mut lexer, 2)) {
+                    let src = t!(entry[0].as_string()).as_bytes();
+                    code_lengths[src.len().min(4)] = true;
+                    if let Some(text) = t!(unicode(&entry[1])) {
+                        cmap.unicode.insert(t!(code(src)), text);
+                    }
+                },
+                b"beginbfrange" => while let Some(entry) = t!(next_entry(&mut lexer, 3)) {
+                    let src = t!(entry[0].as_string()).as_bytes();
+                    let (low, high) = (t!(code(src)), t!(code(t!(entry[1].as_string()).as_bytes())));
+                    code_lengths[src.len().min(4)] = true;
+                    if high < low || high - low > 0xFFFF {
+                        bail!("invalid bfrange {:x} - {:x}", low, high);
+                    }
+                    match entry[2] {
+                        Primitive::Array(ref targets) => {
+                            for (code, target) in (low ..= high).zip(targets) {
+                                if let Some(text) = t!(unicode(target)) {
+                                    cmap.unicode.insert(code, text);
+                                }
+                            }
+                        }
+                        Primitive::String(ref s) => {
+                            // the last UTF-16 unit is incremented for each code
+                            let mut units = match utf16_units(s.as_bytes()) {
+                                Some(units) if !units.is_empty() => units,
+                                _ => continue
+                            };
+                            for code in low ..= high {
+                                if let Ok(text) = String::from_utf16(&units) {
+                                    cmap.unicode.insert(code, text);
+                                }
+                                let last = units.last_mut().unwrap();
+                                *last = last.wrapping_add(1);
+                            }
+                        }
+                        ref p => bail!("unexpected bfrange target {:?}", p)
+                    }
+                },
+                b"usecmap" => match last_name.take() {
+                    Some(name) if depth < MAX_DEPTH => cmap.merge(CMap::predefined_with_depth(&name, depth + 1)),
+                    Some(name) => bail!("usecmap {} nested too deep", name),
+                    None => bail!("usecmap without name")
+                },
+                b"/WMode" => cmap.vertical = t!(lexer.next()).equals(b"1"),
+                b"/CMapName" => {
+                    let name = t!(lexer.next());
+                    if let Some(name) = name.as_slice().strip_prefix(b"/") {
+                        cmap.name = Some(String::from_utf8_lossy(name).into_owned());
+                    }
+                }
+                b"endcmap" => break,
+                name if name.starts_with(b"/") => last_name = Some(String::from_utf8_lossy(&name[1 ..]).into_owned()),
+                _ => {}
+            }
+        }
+
+        if cmap.codespace.is_empty() {
+            // broken ToUnicode CMaps often lack it, assume all codes of the lengths used are valid
+            for len in (1 ..= 4).filter(|&len| code_lengths[len]) {
+                cmap.codespace.push(CodespaceRange { len, low: [0; 4], high: [0xff; 4] });
+            }
+        }
+        Ok(cmap)
+    }
+
+    /// Add the mappings of `other`, which precede those of `self`.
+    fn merge(&mut self, other: CMap) {
+        self.codespace.extend(other.codespace);
+        self.cids.splice(0 .. 0, other.cids);
+        self.notdef.splice(0 .. 0, other.notdef);
+        for (code, text) in other.unicode {
+            self.unicode.entry(code).or_insert(text);
+        }
+        if self.charset.is_none() {
+            self.charset = other.charset;
+        }
+        self.missing |= other.missing;
+    }
+
+    /// The predefined CMap `name`.
+    ///
+    /// See the module documentation for the CMaps that are available.
+    pub fn predefined(name: &str) -> CMap {
+        CMap::predefined_with_depth(name, 0)
+    }
+
+    /// Sets the directory the files of the predefined CMaps are read from, for all CMaps
+    /// created afterwards.
+    ///
+    /// `dir` is either a checkout of Adobe's `cmap-resources` (the files are found in
+    /// `*/CMap/`) or a directory with the CMap files themselves.
+    pub fn set_resource_dir(dir: impl Into<PathBuf>) {
+        *RESOURCE_DIR.write().unwrap_or_else(|e| e.into_inner()) = Some(dir.into());
+    }
+
+    fn predefined_with_depth(name: &str, depth: usize) -> CMap {
+        let vertical = name.ends_with("-V") || name == "V";
+        if name == "Identity-H" || name == "Identity-V" {
+            return CMap::identity(vertical);
+        }
+        let charset = Charset::for_cmap(name);
+        if let Some(path) = find_resource(name) {
+            match std::fs::read(&path).map_err(PdfError::from).and_then(|data| CMap::parse_with_depth(&data, depth)) {
+                Ok(mut cmap) => {
+                    cmap.name = Some(name.into());
+                    cmap.charset = charset;
+                    return cmap;
+                }
+                Err(e) => warn!("can't read CMap {:?}: {}", path, e)
+            }
+        } else {
+            warn!("CMap {} not found, use CMap::set_resource_dir to set the directory of the CMap files", name);
+        }
+        CMap {
+            name: Some(name.into()),
+            vertical,
+            codespace: match charset {
+                Some(charset) => charset.codespace(),
+                None => vec![CodespaceRange { len: 2, low: [0; 4], high: [0xff; 4] }]
+            },
+            charset,
+            missing: true,
+            .. CMap::default()
+        }
+    }
+
+    /// `Identity-H` or `Identity-V`: two byte codes, which are the CIDs.
+    pub fn identity(vertical: bool) -> CMap {
+        CMap {
+            name: Some(if vertical { "Identity-V" } else { "Identity-H" }.into()),
+            vertical,
+            codespace: vec![CodespaceRange { len: 2, low: [0; 4], high: [0xff; 4] }],
+            cids: vec![CidRange { len: 2, low: 0, high: 0xffff, cid: 0 }],
+            .. CMap::default()
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Whether the CMap is for vertical writing (`/WMode 1`).
+    pub fn is_vertical(&self) -> bool {
+        self.vertical
+    }
+
+    /// Split `data` into codes. Yields the code and its length in bytes.
+    pub fn codes<'a>(&'a self, mut data: &'a [u8]) -> impl Iterator<Item=(u32, usize)> + 'a {
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+            let len = self.code_len(data);
+            let code = data[.. len].iter().fold(0, |c, &b| c << 8 | b as u32);
+            data = &data[len ..];
+            Some((code, len))
+        })
+    }
+
+    fn code_len(&self, data: &[u8]) -> usize {
+        for len in 1 ..= data.len().min(4) {
+            if self.codespace.iter().any(|r| r.contains(&data[.. len])) {
+                return len;
+            }
+        }
+        // not in any codespace: skip as many bytes as the shortest code that starts the same way
+        self.codespace.iter()
+            .filter(|r| r.low[0] <= data[0] && data[0] <= r.high[0])
+            .map(|r| r.len)
+            .min()
+            .or_else(|| self.codespace.iter().map(|r| r.len).min())
+            .unwrap_or(1)
+            .min(data.len())
+    }
+
+    /// The length in bytes of `code`, `None` if it isn't in the codespace of the CMap.
+    pub fn code_length(&self, code: u32) -> Option<usize> {
+        let bytes = code.to_be_bytes();
+        (1 ..= 4)
+            .filter(|&len| len == 4 || code >> (8 * len) == 0)
+            .find(|&len| self.codespace.iter().any(|r| r.contains(&bytes[4 - len ..])))
+    }
+
+    /// The CID of the code, `None` if the CMap doesn't map it.
+    ///
+    /// Fails if the code isn't mapped because the file of a predefined CMap isn't available.
+    pub fn cid(&self, code: u32, len: usize) -> Result<Option<u32>> {
+        let lookup = |ranges: &[CidRange]| ranges.iter().rev()
+            .find(|r| r.len == len && r.low <= code && code <= r.high)
+            .map(|r| r.cid + (code - r.low));
+        match lookup(&self.cids).or_else(|| lookup(&self.notdef)) {
+            None if self.missing => Err(PdfError::MissingCMap { name: self.name.clone().unwrap_or_default() }),
+            cid => Ok(cid)
+        }
+    }
+
+    /// The text the code stands for, from the bfchar/bfrange mappings or the character set of
+    /// a predefined CMap.
+    pub fn unicode(&self, code: u32, len: usize) -> Option<String> {
+        if let Some(text) = self.unicode.get(&code) {
+            return Some(text.clone());
+        }
+        self.charset.and_then(|charset| charset.decode(code, len))
+    }
+
+    /// The bfchar/bfrange mappings.
+    pub fn unicode_map(&self) -> &HashMap<u32, String> {
+        &self.unicode
+    }
+
+    pub(crate) fn into_unicode_map(self) -> HashMap<u32, String> {
+        self.unicode
+    }
+}
+
+impl Object for CMap {
+    fn from_primitive(p: Primitive, resolve: &impl Resolve) -> Result<Self> {
+        match p {
+            Primitive::Name(name) => Ok(CMap::predefined(&name)),
+            Primitive::Reference(r) => CMap::from_primitive(resolve.resolve(r)?, resolve),
+            p @ Primitive::Stream(_) => {
+                let stream: Stream<Dictionary> = t!(Stream::from_primitive(p, resolve));
+                let mut cmap = t!(CMap::parse(t!(stream.data())));
+                if let Some(base) = stream.info.info.get("UseCMap") {
+                    cmap.merge(t!(CMap::from_primitive(base.clone(), resolve)));
+                }
+                if let Some(&Primitive::Integer(mode)) = stream.info.info.get("WMode") {
+                    cmap.vertical = mode == 1;
+                }
+                Ok(cmap)
+            }
+            p => Err(PdfError::UnexpectedPrimitive { expected: "Name or Stream", found: p.get_debug_name() })
+        }
+    }
+}
+
+/// The next entry of a `begin…`/`end…` block with `n` objects, or `None` at the end of the block.
+fn next_entry(lexer: &mut Lexer, n: usize) -> Result<Option<Vec<Primitive>>> {
+    let mut entry = Vec::with_capacity(n);
+    for _ in 0 .. n {
+        if t!(lexer.peek()).as_slice().starts_with(b"end") {
+            t!(lexer.next());
+            return Ok(None);
+        }
+        entry.push(t!(parse_with_lexer(lexer, &NoResolve)));
+    }
+    Ok(Some(entry))
+}
+
+fn code(bytes: &[u8]) -> Result<u32> {
+    if bytes.is_empty() || bytes.len() > 4 {
+        bail!("invalid code length {}", bytes.len());
+    }
+    Ok(bytes.iter().fold(0, |c, &b| c << 8 | b as u32))
+}
+
+fn utf16_units(bytes: &[u8]) -> Option<Vec<u16>> {
+    let chunks = bytes.chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    Some(chunks.map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+}
+
+/// The destination of a bfchar or bfrange: UTF-16BE, or (rarely) a glyph name.
+fn unicode(p: &Primitive) -> Result<Option<String>> {
+    match *p {
+        Primitive::String(ref s) => Ok(utf16_units(s.as_bytes()).and_then(|units| String::from_utf16(&units).ok())),
+        Primitive::Name(ref name) => Ok(glyph_name_to_unicode(name)),
+        ref p => Err(PdfError::UnexpectedPrimitive { expected: "String or Name", found: p.get_debug_name() })
+    }
+}
+
+fn find_resource(name: &str) -> Option<PathBuf> {
+    // the name ends up in a path
+    if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.') || name.starts_with('.') {
+        return None;
+    }
+    let dir = RESOURCE_DIR.read().unwrap_or_else(|e| e.into_inner()).clone()?;
+    let path = dir.join(name);
+    if path.is_file() {
+        return Some(path);
+    }
+    // cmap-resources layout: Adobe-Japan1-7/CMap/90ms-RKSJ-H
+    std::fs::read_dir(&dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join("CMap").join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TO_UNICODE: &[u8] = b"/CIDInit /ProcSet findresource begin
+12 dict begin
+begincmap
+/CMapName /Adobe-Identity-UCS def
+/CMapType 2 def
+1 begincodespacerange
+<0000> <FFFF>
+endcodespacerange
+2 beginbfchar
+<0003> <0020>
+<0011> <D83DDE00>
+endbfchar
+2 beginbfrange
+<0024> <0026> <0041>
+<0030> <0031> [<00660069> <0066006C>]
+endbfrange
+endcmap
+CMapName currentdict /CMap defineresource pop
+end
+end";
+
+    #[test]
+    fn to_unicode() {
+        let cmap = CMap::parse(TO_UNICODE).unwrap();
+        assert_eq!(cmap.name(), Some("Adobe-Identity-UCS"));
+        let text: Vec<_> = cmap.codes(&[0, 0x24, 0, 0x26, 0, 3, 0, 0x11, 0, 0x31])
+            .map(|(code, len)| cmap.unicode(code, len).unwrap())
+            .collect();
+        assert_eq!(text, ["A", "C", " ", "😀", "fl"]);
+    }
+
+    #[test]
+    fn codespace() {
+        let data = b"begincmap
+/WMode 1 def
+2 begincodespacerange
+<00> <80>
+<8140> <9FFC>
+endcodespacerange
+1 begincidrange
+<8140> <817e> 633
+endcidrange
+1 begincidchar
+<41> 34
+endcidchar
+endcmap";
+        let cmap = CMap::parse(data).unwrap();
+        assert!(cmap.is_vertical());
+        let codes: Vec<_> = cmap.codes(&[0x41, 0x81, 0x42, 0x20]).collect();
+        assert_eq!(codes, [(0x41, 1), (0x8142, 2), (0x20, 1)]);
+        assert_eq!(cmap.cid(0x41, 1).unwrap(), Some(34));
+        assert_eq!(cmap.cid(0x8142, 2).unwrap(), Some(635));
+        assert_eq!(cmap.cid(0x20, 1).unwrap(), None);
+        assert_eq!(cmap.code_length(0x41), Some(1));
+        assert_eq!(cmap.code_length(0x8142), Some(2));
+        assert_eq!(cmap.code_length(0x90), None);
+    }
+
+    #[test]
+    fn predefined() {
+        let cmap = CMap::predefined("90ms-RKSJ-H");
+        let data = [0x82, 0xa0, b'a', 0xb1, 0x88, 0x9f];
+        let text: String = cmap.codes(&data).filter_map(|(code, len)| cmap.unicode(code, len)).collect();
+        assert_eq!(text, "あaｱ亜");
+
+        let cmap = CMap::predefined("UniGB-UCS2-H");
+        let text: String = cmap.codes(&[0x55, 0x4a, 0, 0x41]).filter_map(|(code, len)| cmap.unicode(code, len)).collect();
+        assert_eq!(text, "啊A");
+
+        let cmap = CMap::predefined("GBK-EUC-H");
+        let text: String = cmap.codes(&[0xb0, 0xa1, b'!']).filter_map(|(code, len)| cmap.unicode(code, len)).collect();
+        assert_eq!(text, "啊!");
+
+        let cmap = CMap::predefined("Identity-V");
+        assert!(cmap.is_vertical());
+        assert_eq!(cmap.cid(0x1234, 2).unwrap(), Some(0x1234));
+
+        let cmap = CMap::predefined("Missing-H");
+        assert!(matches!(cmap.cid(0x20, 1), Err(PdfError::MissingCMap { ref name }) if name == "Missing-H"));
+    }
+
+    #[test]
+    fn resource_dir() {
+        let dir = std::env::temp_dir().join(format!("pdf-cmap-resources-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Test-Japan1-0").join("CMap")).unwrap();
+        std::fs::write(dir.join("Test-Japan1-0").join("CMap").join("Test-H"), b"begincmap
+1 begincodespacerange
+<8140> <9FFC>
+endcodespacerange
+1 begincidrange
+<8140> <817e> 633
+endcidrange
+endcmap
+").unwrap();
+        CMap::set_resource_dir(&dir);
+        let cmap = CMap::predefined("Test-H");
+        assert_eq!(cmap.name(), Some("Test-H"));
+        assert_eq!(cmap.cid(0x8142, 2).unwrap(), Some(635));
+        assert!(CMap::predefined("Missing-H").cid(0x20, 1).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
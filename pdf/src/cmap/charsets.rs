@@ -0,0 +1,186 @@
+//! Character sets of the predefined CMaps, used to decode text when the CMap files aren't available.
+//!
+//! The double byte tables are the Windows code pages (932, 936, 949, 950), which are supersets of
+//! the national standards. Each is stored as big endian UTF-16 for lead bytes 0x81..=0xFE and
+//! trail bytes 0x40..=0xFE, with 0 for undefined codes.
+
+use once_cell::sync::Lazy;
+
+use super::CodespaceRange;
+
+static CP932: Lazy<Vec<u16>> = Lazy::new(|| table(include_bytes!("cp932.zlib")));
+static CP936: Lazy<Vec<u16>> = Lazy::new(|| table(include_bytes!("cp936.zlib")));
+static CP949: Lazy<Vec<u16>> = Lazy::new(|| table(include_bytes!("cp949.zlib")));
+static CP950: Lazy<Vec<u16>> = Lazy::new(|| table(include_bytes!("cp950.zlib")));
+
+const TRAIL_BYTES: usize = 0xFE - 0x40 + 1;
+
+fn table(data: &[u8]) -> Vec<u16> {
+    match inflate::inflate_bytes_zlib(data) {
+        Ok(data) => data.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect(),
+        Err(e) => {
+            warn!("can't inflate charset table: {}", e);
+            vec![]
+        }
+    }
+}
+
+fn lookup(table: &[u16], code: u32) -> Option<char> {
+    let (lead, trail) = ((code >> 8) as usize, (code & 0xff) as usize);
+    if !(0x81 ..= 0xFE).contains(&lead) || !(0x40 ..= 0xFE).contains(&trail) {
+        return None;
+    }
+    match table.get((lead - 0x81) * TRAIL_BYTES + trail - 0x40) {
+        Some(&0) | None => None,
+        Some(&c) => std::char::from_u32(c as u32)
+    }
+}
+
+/// JIS X 0208 row and cell (0x21..=0x7E each) to Shift-JIS, 0 for other codes.
+fn jis_to_sjis(code: u32) -> u32 {
+    let (j1, j2) = (code >> 8, code & 0xff);
+    if !(0x21 ..= 0x7E).contains(&j1) || !(0x21 ..= 0x7E).contains(&j2) {
+        return 0;
+    }
+    let s1 = j1.div_ceil(2) + if j1 <= 0x5E { 0x70 } else { 0xB0 };
+    let s2 = if j1 % 2 == 1 {
+        j2 + if j2 >= 0x60 { 0x20 } else { 0x1F }
+    } else {
+        j2 + 0x7E
+    };
+    s1 << 8 | s2
+}
+
+fn halfwidth_katakana(b: u32) -> Option<char> {
+    if (0xA1 ..= 0xDF).contains(&b) {
+        std::char::from_u32(0xFF61 + b - 0xA1)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Ucs2,
+    Utf16,
+    Utf8,
+    Utf32,
+    /// Shift-JIS (`RKSJ`)
+    ShiftJis,
+    EucJp,
+    /// ISO-2022 style JIS X 0208 codes (`H`, `V`)
+    Jis,
+    /// GB 2312 in EUC, and GBK
+    Gbk,
+    /// ISO-2022 style GB 2312 codes (`GB-H`)
+    Gb,
+    Big5,
+    /// KS X 1001 in EUC, and UHC
+    Uhc,
+    /// ISO-2022 style KS X 1001 codes (`KSC-H`)
+    Ksc,
+}
+
+impl Charset {
+    pub fn for_cmap(name: &str) -> Option<Charset> {
+        let base = name.trim_end_matches("-H").trim_end_matches("-V");
+        let charset = if name.starts_with("Uni") {
+            if name.contains("UCS2") {
+                Charset::Ucs2
+            } else if name.contains("UTF16") {
+                Charset::Utf16
+            } else if name.contains("UTF8") {
+                Charset::Utf8
+            } else if name.contains("UTF32") {
+                Charset::Utf32
+            } else {
+                return None;
+            }
+        } else if name.contains("RKSJ") {
+            Charset::ShiftJis
+        } else if base == "EUC" {
+            Charset::EucJp
+        } else if matches!(base, "" | "H" | "V" | "Add" | "Ext" | "NWP") {
+            Charset::Jis
+        } else if base == "GB" {
+            Charset::Gb
+        } else if base.starts_with("GB") {
+            Charset::Gbk
+        } else if base.contains("B5") {
+            Charset::Big5
+        } else if base == "KSC" {
+            Charset::Ksc
+        } else if base.starts_with("KSC") {
+            Charset::Uhc
+        } else {
+            return None;
+        };
+        Some(charset)
+    }
+
+    pub fn codespace(self) -> Vec<CodespaceRange> {
+        let range = |low: &[u8], high: &[u8]| CodespaceRange::new(low, high).unwrap();
+        match self {
+            Charset::Ucs2 | Charset::Jis | Charset::Gb | Charset::Ksc => vec![range(&[0, 0], &[0xff, 0xff])],
+            Charset::Utf16 => vec![
+                range(&[0, 0], &[0xd7, 0xff]),
+                range(&[0xe0, 0], &[0xff, 0xff]),
+                range(&[0xd8, 0, 0xdc, 0], &[0xdb, 0xff, 0xdf, 0xff]),
+            ],
+            Charset::Utf8 => vec![
+                range(&[0], &[0x7f]),
+                range(&[0xc2, 0x80], &[0xdf, 0xbf]),
+                range(&[0xe0, 0x80, 0x80], &[0xef, 0xbf, 0xbf]),
+                range(&[0xf0, 0x80, 0x80, 0x80], &[0xf4, 0xbf, 0xbf, 0xbf]),
+            ],
+            Charset::Utf32 => vec![range(&[0, 0, 0, 0], &[0, 0x10, 0xff, 0xff])],
+            Charset::ShiftJis => vec![
+                range(&[0], &[0x80]),
+                range(&[0xa0], &[0xdf]),
+                range(&[0xfd], &[0xff]),
+                range(&[0x81, 0x40], &[0x9f, 0xfc]),
+                range(&[0xe0, 0x40], &[0xfc, 0xfc]),
+            ],
+            Charset::EucJp => vec![
+                range(&[0], &[0x80]),
+                range(&[0x8e, 0xa0], &[0x8e, 0xfe]),
+                range(&[0xa1, 0xa1], &[0xfe, 0xfe]),
+                range(&[0x8f, 0xa1, 0xa1], &[0x8f, 0xfe, 0xfe]),
+            ],
+            Charset::Gbk | Charset::Big5 | Charset::Uhc => vec![
+                range(&[0], &[0x80]),
+                range(&[0x81, 0x40], &[0xfe, 0xfe]),
+            ],
+        }
+    }
+
+    pub fn decode(self, code: u32, len: usize) -> Option<String> {
+        let c = match (self, len) {
+            (Charset::Ucs2, 2) | (Charset::Utf32, 4) => std::char::from_u32(code),
+            (Charset::Utf16, 2) => std::char::from_u32(code),
+            (Charset::Utf16, 4) => {
+                return String::from_utf16(&[(code >> 16) as u16, code as u16]).ok();
+            }
+            (Charset::Utf8, _) => {
+                let bytes = code.to_be_bytes();
+                return std::str::from_utf8(&bytes[4 - len ..]).ok().map(|s| s.into());
+            }
+            (Charset::ShiftJis, 1) => match code {
+                0 ..= 0x7f => std::char::from_u32(code),
+                _ => halfwidth_katakana(code),
+            },
+            (Charset::ShiftJis, 2) => lookup(&CP932, code),
+            (Charset::EucJp, 1) | (Charset::Gbk, 1) | (Charset::Big5, 1) | (Charset::Uhc, 1) if code < 0x80 => std::char::from_u32(code),
+            (Charset::EucJp, 2) if code >> 8 == 0x8e => halfwidth_katakana(code & 0xff),
+            (Charset::EucJp, 2) => lookup(&CP932, jis_to_sjis(code & 0x7f7f)),
+            (Charset::Jis, 2) => lookup(&CP932, jis_to_sjis(code)),
+            (Charset::Gbk, 2) => lookup(&CP936, code),
+            (Charset::Gb, 2) => lookup(&CP936, code | 0x8080),
+            (Charset::Big5, 2) => lookup(&CP950, code),
+            (Charset::Uhc, 2) => lookup(&CP949, code),
+            (Charset::Ksc, 2) => lookup(&CP949, code | 0x8080),
+            _ => None
+        };
+        c.map(String::from)
+    }
+}
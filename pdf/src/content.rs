@@ -2,7 +2,7 @@
 use std::fmt::{self, Display};
 use std::cmp::Ordering;
 use itertools::Itertools;
-use once_cell::unsync::OnceCell;
+use once_cell::sync::OnceCell;
 
 use crate::error::*;
 use crate::object::*;
@@ -154,8 +154,9 @@ fn inline_image(lexer: &mut Lexer, resolve: &impl Resolve) -> Result<Stream<Imag
     let data_start = lexer.get_pos() + 1;
 
     // ugh
-    let bits_per_component = dict.require("InlineImage", "BitsPerComponent")?.as_integer()?;
-    let color_space = dict.get("InlineImage").map(|p| ColorSpace::from_primitive(expand_abbr(p.clone(), 
+    // image masks may leave it out
+    let bits_per_component = dict.get("BitsPerComponent").map(|p| p.as_integer()).transpose()?;
+    let color_space = dict.get("ColorSpace").map(|p| ColorSpace::from_primitive(expand_abbr(p.clone(), 
         &[
             ("G", "DeviceGray"),
             ("RGB", "DeviceRGB"),
@@ -166,7 +167,7 @@ fn inline_image(lexer: &mut Lexer, resolve: &impl Resolve) -> Result<Stream<Imag
     let decode = dict.get("Decode").map(|p| Object::from_primitive(p.clone(), resolve)).transpose()?;
     let decode_parms = dict.get("DecodeParms").map(|p| p.clone().into_dictionary(resolve)).transpose()?.unwrap_or_default();
     let filter = expand_abbr(
-        dict.require("InlineImage", "Filter")?,
+        dict.get("Filter").cloned().unwrap_or(Primitive::Array(vec![])),
         &[
             ("AHx", "ASCIIHexDecode"),
             ("A85", "ASCII85Decode"),
@@ -338,8 +339,15 @@ impl OpBuilder {
                 let phase = args.next().ok_or(PdfError::NoOpArg)?.as_number()?;
                 push(Op::Dash { pattern, phase });
             }
-            "d0"  => {}
-            "d1"  => {}
+            "d0"  => {
+                points!(args, width);
+                push(Op::CharWidth { width });
+            }
+            "d1"  => {
+                points!(args, width, min, max);
+                let bbox = Rect { x: min.x, y: min.y, width: max.x - min.x, height: max.y - min.y };
+                push(Op::CacheDevice { width, bbox });
+            }
             "Do"  => {
                 names!(args, name);
                 push(Op::XObject { name });
@@ -728,6 +736,10 @@ fn serialize_ops(mut ops: &[Op]) -> Result<Vec<u8>> {
             TextDrawAdjusted { ref array } => {
                 writeln!(f, "[{}] TJ", array.iter().format(" "))?;
             },
+            CharWidth { width } => writeln!(f, "{} d0", width)?,
+            CacheDevice { width, bbox } => {
+                writeln!(f, "{} {} {} {} {} d1", width, bbox.x, bbox.y, bbox.x + bbox.width, bbox.y + bbox.height)?;
+            }
             InlineImage { image: _ } => unimplemented!(),
             XObject { ref name } => {
                 serialize_name(name, f)?;
@@ -1070,4 +1082,15 @@ pub enum Op {
     XObject { name: String },
 
     InlineImage { image: Stream<ImageDict> },
+
+    /// The width of a Type 3 glyph that sets its own colors.
+    ///
+    /// operator: `d0`
+    CharWidth { width: Point },
+
+    /// The width and bounding box of a Type 3 glyph that is a shape only, to be painted in the
+    /// color of the text.
+    ///
+    /// operator: `d1`
+    CacheDevice { width: Point, bbox: Rect },
 }
\ No newline at end of file
@@ -2,16 +2,21 @@
 
 use crate as pdf;
 use aes::cipher::generic_array::{sequence::Split, GenericArray};
-use aes::{Aes128, Aes256, NewBlockCipher};
-use block_modes::block_padding::{NoPadding, Pkcs7};
-use block_modes::{BlockMode, Cbc};
+use aes::cipher::block_padding::{NoPadding, Pkcs7};
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use aes::{Aes128, Aes256};
 use sha2::{Digest, Sha256, Sha384, Sha512};
 use std::fmt;
 use std::collections::HashMap;
 use crate::object::PlainRef;
-use crate::primitive::{Dictionary, PdfString};
+use crate::primitive::{Primitive, Dictionary, PdfString, PdfStream};
 use crate::error::{PdfError, Result};
 
+#[cfg(feature="pubsec")]
+mod pubsec;
+#[cfg(feature="pubsec")]
+pub use self::pubsec::PubSecKey;
+
 const PADDING: [u8; 32] = [
     0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41,
     0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
@@ -57,18 +62,25 @@ impl Rc4 {
 }
 
 /// 7.6.1 Table 20 + 7.6.3.2 Table 21
-#[derive(Object, Debug, Clone)]
+#[derive(Object, ObjectWrite, Debug, Clone)]
 pub struct CryptDict {
-    #[pdf(key="O")]
+    #[pdf(key="Filter", default="String::from(\"Standard\")")]
+    filter: String,
+
+    #[pdf(key="SubFilter")]
+    sub_filter: Option<String>,
+
+    // O, U, R and P are only present for the standard security handler
+    #[pdf(key="O", default="PdfString::new(Vec::new())")]
     o: PdfString,
 
-    #[pdf(key="U")]
+    #[pdf(key="U", default="PdfString::new(Vec::new())")]
     u: PdfString,
 
-    #[pdf(key="R")]
+    #[pdf(key="R", default="0")]
     r: u32,
 
-    #[pdf(key="P")]
+    #[pdf(key="P", default="-1")]
     p: i32,
 
     #[pdf(key="V")]
@@ -83,6 +95,12 @@ pub struct CryptDict {
     #[pdf(key="StmF")]
     default_crypt_filter: Option<String>,
 
+    #[pdf(key="StrF")]
+    string_crypt_filter: Option<String>,
+
+    #[pdf(key="EFF")]
+    embedded_file_filter: Option<String>,
+
     #[pdf(key="EncryptMetadata", default="true")]
     encrypt_metadata: bool,
 
@@ -92,11 +110,65 @@ pub struct CryptDict {
     #[pdf(key = "UE")]
     ue: Option<PdfString>,
 
+    #[pdf(key = "Perms")]
+    perms: Option<PdfString>,
+
+    /// PKCS#7 enveloped data for the public-key security handler (V < 4).
+    #[pdf(key = "Recipients")]
+    recipients: Option<Vec<PdfString>>,
+
     #[pdf(other)]
     _other: Dictionary
 }
 
-#[derive(Object, Debug, Clone, Copy)]
+impl CryptDict {
+    /// The permissions granted to a user who opened the file with the user password.
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from_p(self.p)
+    }
+}
+
+bitflags! {
+    /// User access permissions, stored in `/P` (7.6.3.2 Table 22).
+    pub struct Permissions: u32 {
+        /// Print the document, in degraded quality unless `PRINT_HIGH_QUALITY` is set as well (bit 3).
+        const PRINT = 1 << 2;
+        /// Modify the contents in ways not covered by the other flags (bit 4).
+        const MODIFY = 1 << 3;
+        /// Copy or otherwise extract text and graphics (bit 5).
+        const COPY = 1 << 4;
+        /// Add or modify annotations and fill in form fields (bit 6).
+        const ANNOTATE = 1 << 5;
+        /// Fill in form fields, even if `ANNOTATE` is not set (bit 9).
+        const FILL_FORMS = 1 << 8;
+        /// Extract text and graphics for accessibility (bit 10).
+        const EXTRACT = 1 << 9;
+        /// Insert, rotate or delete pages, create bookmarks and thumbnails (bit 11).
+        const ASSEMBLE = 1 << 10;
+        /// Print at full quality (bit 12).
+        const PRINT_HIGH_QUALITY = 1 << 11;
+    }
+}
+impl Permissions {
+    pub fn from_p(p: i32) -> Permissions {
+        Permissions::from_bits_truncate(p as u32)
+    }
+    /// The value for `/P`, with the reserved bits set as required.
+    pub fn to_p(self) -> i32 {
+        (self.bits() | 0xFFFF_F0C0) as i32
+    }
+}
+
+/// Which password was used to open an encrypted file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PasswordType {
+    /// Access is restricted to the `Permissions` of the file.
+    User,
+    /// Full access.
+    Owner,
+}
+
+#[derive(Object, ObjectWrite, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CryptMethod {
     None,
     V2,
@@ -104,13 +176,13 @@ pub enum CryptMethod {
     AESV3,
 }
 
-#[derive(Object, Debug, Clone, Copy)]
+#[derive(Object, ObjectWrite, Debug, Clone, Copy)]
 pub enum AuthEvent {
     DocOpen,
     EFOpen
 }
 
-#[derive(Object, Debug, Clone)]
+#[derive(Object, ObjectWrite, Debug, Clone)]
 #[pdf(Type="CryptFilter?")]
 pub struct CryptFilter {
     #[pdf(key="CFM", default="CryptMethod::None")]
@@ -122,10 +194,249 @@ pub struct CryptFilter {
     #[pdf(key="Length")]
     pub length: Option<u32>,
 
+    /// PKCS#7 enveloped data for the public-key security handler.
+    #[pdf(key="Recipients")]
+    pub recipients: Option<Vec<PdfString>>,
+
+    /// Only used by the public-key security handler.
+    #[pdf(key="EncryptMetadata")]
+    pub encrypt_metadata: Option<bool>,
+
     #[pdf(other)]
     _other: Dictionary
 }
 
+/// Which crypt method applies to what, as selected by `/StrF`, `/StmF`, `/EFF` and the
+/// `/Crypt` stream filter. `CryptMethod::None` means the data is not encrypted (`/Identity`).
+#[derive(Debug, Clone)]
+struct CryptFilters {
+    strings: CryptMethod,
+    streams: CryptMethod,
+    embedded_files: CryptMethod,
+    named: HashMap<String, CryptMethod>,
+}
+impl CryptFilters {
+    /// Everything is encrypted with `method` (V < 4).
+    fn uniform(method: CryptMethod) -> CryptFilters {
+        CryptFilters {
+            strings: method,
+            streams: method,
+            embedded_files: method,
+            named: HashMap::new(),
+        }
+    }
+    fn from_dict(dict: &CryptDict) -> Result<CryptFilters> {
+        let lookup = |name: Option<&String>| -> Result<CryptMethod> {
+            match name.map(|s| s.as_str()) {
+                None | Some("Identity") => Ok(CryptMethod::None),
+                Some(name) => match dict.crypt_filters.get(name) {
+                    Some(filter) => Ok(filter.method),
+                    None => Err(other!("missing crypt filter entry {:?}", name)),
+                }
+            }
+        };
+        let streams = t!(lookup(dict.default_crypt_filter.as_ref()));
+        Ok(CryptFilters {
+            strings: t!(lookup(dict.string_crypt_filter.as_ref())),
+            streams,
+            embedded_files: match dict.embedded_file_filter {
+                Some(ref name) => t!(lookup(Some(name))),
+                None => streams,
+            },
+            named: dict.crypt_filters.iter().map(|(name, filter)| (name.clone(), filter.method)).collect(),
+        })
+    }
+    /// The method for a stream with the dictionary `info`.
+    fn for_stream(&self, info: &Dictionary) -> Result<CryptMethod> {
+        let first_filter = match info.get("Filter") {
+            Some(Primitive::Name(ref name)) => Some(name.as_str()),
+            Some(Primitive::Array(ref parts)) => parts.first().and_then(|p| p.as_name().ok()),
+            _ => None
+        };
+        if first_filter == Some("Crypt") {
+            let params = match info.get("DecodeParms") {
+                Some(Primitive::Dictionary(ref dict)) => Some(dict),
+                Some(Primitive::Array(ref parts)) => match parts.first() {
+                    Some(Primitive::Dictionary(ref dict)) => Some(dict),
+                    _ => None
+                },
+                _ => None
+            };
+            return match params.and_then(|dict| dict.get("Name")) {
+                None => Ok(CryptMethod::None),
+                Some(Primitive::Name(ref name)) if name == "Identity" => Ok(CryptMethod::None),
+                Some(Primitive::Name(ref name)) => match self.named.get(name.as_str()) {
+                    Some(&method) => Ok(method),
+                    None => Err(other!("missing crypt filter entry {:?}", name)),
+                },
+                Some(p) => Err(PdfError::UnexpectedPrimitive { expected: "Name", found: p.get_debug_name() }),
+            };
+        }
+        match info.get("Type") {
+            Some(Primitive::Name(ref ty)) if ty == "EmbeddedFile" => Ok(self.embedded_files),
+            _ => Ok(self.streams)
+        }
+    }
+}
+
+fn compute_u_rev_2(key: &[u8]) -> Vec<u8> {
+    // algorithm 4
+    let mut data = PADDING.to_vec();
+    Rc4::encrypt(key, &mut data);
+    data
+}
+
+fn check_password_rev_2(document_u: &[u8], key: &[u8]) -> bool {
+    compute_u_rev_2(key) == document_u
+}
+
+fn compute_u_rev_3_4(id: &[u8], key: &[u8]) -> Vec<u8> {
+    // algorithm 5
+    // a) we derived the key already.
+
+    // b)
+    let mut hash = md5::Context::new();
+    hash.consume(&PADDING);
+
+    // c)
+    hash.consume(id);
+
+    // d)
+    let mut data = *hash.compute();
+    Rc4::encrypt(key, &mut data);
+
+    // e)
+    for i in 1u8..=19 {
+        let mut key = key.to_owned();
+        for b in &mut key {
+            *b ^= i;
+        }
+        Rc4::encrypt(&key, &mut data);
+    }
+
+    // f)
+    data.to_vec()
+}
+
+fn check_password_rev_3_4(document_u: &[u8], id: &[u8], key: &[u8]) -> bool {
+    compute_u_rev_3_4(id, key) == &document_u[..16]
+}
+
+fn check_password_rc4(revision: u32, document_u: &[u8], id: &[u8], key: &[u8]) -> bool {
+    if revision == 2 {
+        check_password_rev_2(document_u, key)
+    } else {
+        check_password_rev_3_4(document_u, id, key)
+    }
+}
+
+fn key_derivation_user_password_rc4(
+    revision: u32,
+    key_size: usize,
+    dict: &CryptDict,
+    id: &[u8],
+    pass: &[u8],
+) -> [u8; 32] {
+    let o = dict.o.as_bytes();
+    let p = dict.p;
+    // 7.6.3.3 - Algorithm 2
+    // a) and b)
+    let mut hash = md5::Context::new();
+    if pass.len() < 32 {
+        hash.consume(pass);
+        hash.consume(&PADDING[..32 - pass.len()]);
+    } else {
+        hash.consume(&pass[..32]);
+    }
+
+    // c)
+    hash.consume(o);
+
+    // d)
+    hash.consume(p.to_le_bytes());
+
+    // e)
+    hash.consume(id);
+
+    // f)
+    if revision >= 4 && !dict.encrypt_metadata {
+        hash.consume([0xff, 0xff, 0xff, 0xff]);
+    }
+
+    // g)
+    let mut data = *hash.compute();
+
+    // h)
+    if revision >= 3 {
+        for _ in 0..50 {
+            data = *md5::compute(&data[..key_size]);
+        }
+    }
+
+    let mut key = [0u8; 32];
+    (&mut key[..16]).copy_from_slice(&data);
+    key
+}
+
+fn key_derivation_owner_password_rc4(
+    revision: u32,
+    key_size: usize,
+    pass: &[u8],
+) -> Result<Vec<u8>> {
+    let mut hash = md5::Context::new();
+    if pass.len() < 32 {
+        hash.consume(pass);
+        hash.consume(&PADDING[..32 - pass.len()]);
+    } else {
+        hash.consume(&pass[..32]);
+    }
+
+    if revision >= 3 {
+        for _ in 0..50 {
+            let digest = *std::mem::replace(&mut hash, md5::Context::new()).compute();
+            hash.consume(digest);
+        }
+    }
+
+    let digest = &hash.compute()[..key_size];
+    Ok(digest.to_vec())
+}
+
+/// Algorithm 1 b) - d): the key for the strings and streams of object `id` with generation `gen`.
+/// Returns the key buffer and the number of bytes used.
+fn object_key(key: &[u8], method: CryptMethod, id: u64, gen: u16) -> ([u8; 16], usize) {
+    let n = key.len();
+    let mut buf = [0; 16 + 5 + 4];
+    buf[..n].copy_from_slice(key);
+    buf[n..n + 3].copy_from_slice(&id.to_le_bytes()[..3]);
+    buf[n + 3..n + 5].copy_from_slice(&gen.to_le_bytes()[..2]);
+    let len = match method {
+        CryptMethod::AESV2 => {
+            buf[n + 5..n + 9].copy_from_slice(b"sAlT");
+            n + 9
+        }
+        _ => n + 5
+    };
+    (*md5::compute(&buf[..len]), (n + 5).min(16))
+}
+
+/// Passwords for revision 5 and 6 are SASLprep'ed UTF-8, truncated to 127 bytes.
+fn prepare_password_r5(pass: &[u8]) -> Result<Vec<u8>> {
+    let password_unicode =
+        t!(String::from_utf8(pass.to_vec()).map_err(|_| PdfError::InvalidPassword));
+    let password_prepped =
+        t!(stringprep::saslprep(&password_unicode).map_err(|_| PdfError::InvalidPassword));
+    let mut password_encoded = password_prepped.as_bytes().to_vec();
+    password_encoded.truncate(127);
+    Ok(password_encoded)
+}
+
+pub(crate) fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    t!(getrandom::getrandom(&mut buf).map_err(|e| other!("no random numbers available: {}", e)));
+    Ok(buf)
+}
+
 pub struct Decoder {
     key_size: usize,
     key: [u8; 32], // maximum length
@@ -142,6 +453,11 @@ pub struct Decoder {
     /// Whether the metadata is encrypted, as indicated by /EncryptMetadata
     /// in the /Encrypt dictionary.
     encrypt_metadata: bool,
+    /// Which of the passwords was used to open the file.
+    password: PasswordType,
+    /// Set by the public-key security handler, which stores them per recipient.
+    permissions: Option<Permissions>,
+    filters: CryptFilters,
 }
 impl Decoder {
     pub fn default(dict: &CryptDict, id: &[u8]) -> Result<Decoder> {
@@ -152,6 +468,24 @@ impl Decoder {
         &self.key[.. self.key_size]
     }
 
+    /// Which password matched in `from_password`.
+    pub fn password_type(&self) -> PasswordType {
+        self.password
+    }
+
+    /// The permissions of the recipient, if the file was opened with the public-key
+    /// security handler. Otherwise they are in the `CryptDict`.
+    pub fn permissions(&self) -> Option<Permissions> {
+        self.permissions
+    }
+
+    /// An `Encoder` using the same key, for writing changes back to the file.
+    pub fn encoder(&self) -> Encoder {
+        let mut encoder = Encoder::new(self.key, self.key_size, self.method, self.encrypt_metadata);
+        encoder.filters = self.filters.clone();
+        encoder
+    }
+
     pub fn new(key: [u8; 32], key_size: usize, method: CryptMethod, encrypt_metadata: bool) -> Decoder {
         Decoder {
             key_size,
@@ -160,142 +494,43 @@ impl Decoder {
             encrypt_indirect_object: None,
             metadata_indirect_object: None,
             encrypt_metadata,
+            password: PasswordType::User,
+            permissions: None,
+            filters: CryptFilters::uniform(method),
         }
     }
 
     pub fn from_password(dict: &CryptDict, id: &[u8], pass: &[u8]) -> Result<Decoder> {
-        fn compute_u_rev_2(key: &[u8]) -> Vec<u8> {
-            // algorithm 4
-            let mut data = PADDING.to_vec();
-            Rc4::encrypt(key, &mut data);
-            data
-        }
-
-        fn check_password_rev_2(document_u: &[u8], key: &[u8]) -> bool {
-            compute_u_rev_2(key) == document_u
-        }
-
-        fn compute_u_rev_3_4(id: &[u8], key: &[u8]) -> Vec<u8> {
-            // algorithm 5
-            // a) we derived the key already.
-
-            // b)
-            let mut hash = md5::Context::new();
-            hash.consume(&PADDING);
-
-            // c)
-            hash.consume(id);
-
-            // d)
-            let mut data = *hash.compute();
-            Rc4::encrypt(key, &mut data);
-
-            // e)
-            for i in 1u8..=19 {
-                let mut key = key.to_owned();
-                for b in &mut key {
-                    *b ^= i;
-                }
-                Rc4::encrypt(&key, &mut data);
-            }
-
-            // f)
-            data.to_vec()
+        if dict.filter != "Standard" {
+            bail!("unsupported security handler {:?}", dict.filter);
         }
-
-        fn check_password_rev_3_4(document_u: &[u8], id: &[u8], key: &[u8]) -> bool {
-            compute_u_rev_3_4(id, key) == &document_u[..16]
-        }
-
-        fn check_password_rc4(revision: u32, document_u: &[u8], id: &[u8], key: &[u8]) -> bool {
-            if revision == 2 {
-                check_password_rev_2(document_u, key)
-            } else {
-                check_password_rev_3_4(document_u, id, key)
-            }
-        }
-
-        fn key_derivation_user_password_rc4(
-            revision: u32,
-            key_size: usize,
-            dict: &CryptDict,
-            id: &[u8],
-            pass: &[u8],
-        ) -> [u8; 32] {
-            let o = dict.o.as_bytes();
-            let p = dict.p;
-            // 7.6.3.3 - Algorithm 2
-            // a) and b)
-            let mut hash = md5::Context::new();
-            if pass.len() < 32 {
-                hash.consume(pass);
-                hash.consume(&PADDING[..32 - pass.len()]);
-            } else {
-                hash.consume(&pass[..32]);
-            }
-
-            // c)
-            hash.consume(o);
-
-            // d)
-            hash.consume(p.to_le_bytes());
-
-            // e)
-            hash.consume(id);
-
-            // f)
-            if revision >= 4 && !dict.encrypt_metadata {
-                hash.consume([0xff, 0xff, 0xff, 0xff]);
-            }
-
-            // g)
-            let mut data = *hash.compute();
-
-            // h)
-            if revision >= 3 {
-                for _ in 0..50 {
-                    data = *md5::compute(&data[..key_size]);
-                }
-            }
-
-            let mut key = [0u8; 32];
-            (&mut key[..16]).copy_from_slice(&data);
-            key
-        }
-
-        fn key_derivation_owner_password_rc4(
-            revision: u32,
-            key_size: usize,
-            pass: &[u8],
-        ) -> Result<Vec<u8>> {
-            let mut hash = md5::Context::new();
-            if pass.len() < 32 {
-                hash.consume(pass);
-                hash.consume(&PADDING[..32 - pass.len()]);
-            } else {
-                hash.consume(&pass[..32]);
-            }
-
-            if revision >= 3 {
-                for _ in 0..50 {
-                    let digest = *std::mem::replace(&mut hash, md5::Context::new()).compute();
-                    hash.consume(digest);
-                }
-            }
-
-            let digest = &hash.compute()[..key_size];
-            Ok(digest.to_vec())
+        let mut decoder = t!(Decoder::check_password(dict, id, pass));
+        if dict.v >= 4 {
+            decoder.filters = t!(CryptFilters::from_dict(dict));
         }
+        Ok(decoder)
+    }
 
+    fn check_password(dict: &CryptDict, id: &[u8], pass: &[u8]) -> Result<Decoder> {
         let (key_bits, method) = match dict.v {
             1 => (40, CryptMethod::V2),
             2 => (dict.bits, CryptMethod::V2),
             4 | 5 | 6 => {
-                let default = dict
-                    .crypt_filters
-                    .get(try_opt!(dict.default_crypt_filter.as_ref()).as_str())
-                    .ok_or_else(|| other!("missing crypt filter entry {:?}", dict.default_crypt_filter.as_ref()))?;
-                
+                // The key is shared by all crypt filters, its length is taken from the first
+                // one that is not /Identity.
+                let names = [&dict.default_crypt_filter, &dict.string_crypt_filter, &dict.embedded_file_filter];
+                let default = names.iter()
+                    .filter_map(|name| name.as_ref())
+                    .filter(|name| name.as_str() != "Identity")
+                    .map(|name| dict.crypt_filters.get(name.as_str())
+                        .ok_or_else(|| other!("missing crypt filter entry {:?}", name)))
+                    .next();
+                let default = match default {
+                    Some(filter) => t!(filter),
+                    // nothing is encrypted
+                    None => return Ok(Decoder::new([0; 32], dict.bits as usize / 8, CryptMethod::None, dict.encrypt_metadata)),
+                };
+
                 match default.method {
                     CryptMethod::V2 | CryptMethod::AESV2 => (
                         default.length.map(|n| 8 * n).unwrap_or(dict.bits),
@@ -319,7 +554,8 @@ impl Decoder {
             let key = key_derivation_user_password_rc4(level, key_size, dict, id, pass);
 
             if check_password_rc4(level, dict.u.as_bytes(), id, &key[..key_size]) {
-                let decoder = Decoder::new(key, key_size, method, dict.encrypt_metadata);
+                let mut decoder = Decoder::new(key, key_size, method, dict.encrypt_metadata);
+                decoder.password = PasswordType::User;
                 Ok(decoder)
             } else {
                 let password_wrap_key = key_derivation_owner_password_rc4(level, key_size, pass)?;
@@ -343,7 +579,8 @@ impl Decoder {
                 );
 
                 if check_password_rc4(level, dict.u.as_bytes(), id, &key[..key_size]) {
-                    let decoder = Decoder::new(key, key_size, method, dict.encrypt_metadata);
+                    let mut decoder = Decoder::new(key, key_size, method, dict.encrypt_metadata);
+                    decoder.password = PasswordType::Owner;
                     Ok(decoder)
                 } else {
                     Err(PdfError::InvalidPassword)
@@ -374,15 +611,7 @@ impl Decoder {
             let owner_validation_salt = &o[32..40];
             let owner_key_salt = &o[40..48];
 
-            let password_unicode =
-                t!(String::from_utf8(pass.to_vec()).map_err(|_| PdfError::InvalidPassword));
-            let password_prepped =
-                t!(stringprep::saslprep(&password_unicode).map_err(|_| PdfError::InvalidPassword));
-            let mut password_encoded = password_prepped.as_bytes();
-
-            if password_encoded.len() > 127 {
-                password_encoded = &password_encoded[..127];
-            }
+            let password_encoded = &*t!(prepare_password_r5(pass));
 
             let ue = t!(dict.ue.as_ref().ok_or_else(|| PdfError::MissingEntry {
                 typ: "Encrypt",
@@ -397,13 +626,14 @@ impl Decoder {
             .as_bytes()
             .to_vec();
 
-            let (intermediate_key, mut wrapped_key) = if level == 6 {
+            let (intermediate_key, mut wrapped_key, password) = if level == 6 {
                 let user_hash_computed =
                     Self::revision_6_kdf(password_encoded, user_validation_salt, b"");
                 if user_hash_computed == user_hash {
                     (
                         Self::revision_6_kdf(password_encoded, user_key_salt, b"").into(),
                         ue,
+                        PasswordType::User,
                     )
                 } else {
                     let owner_hash_computed =
@@ -412,6 +642,7 @@ impl Decoder {
                         (
                             Self::revision_6_kdf(password_encoded, owner_key_salt, u).into(),
                             oe,
+                            PasswordType::Owner,
                         )
                     } else {
                         err!(PdfError::InvalidPassword);
@@ -428,7 +659,7 @@ impl Decoder {
                     let mut intermediate_kdf_hash = Sha256::new();
                     intermediate_kdf_hash.update(password_encoded);
                     intermediate_kdf_hash.update(user_key_salt);
-                    (intermediate_kdf_hash.finalize(), ue)
+                    (intermediate_kdf_hash.finalize(), ue, PasswordType::User)
                 } else {
                     let mut owner_check_hash = Sha256::new();
                     owner_check_hash.update(password_encoded);
@@ -440,7 +671,7 @@ impl Decoder {
                         intermediate_kdf_hash.update(password_encoded);
                         intermediate_kdf_hash.update(owner_key_salt);
                         intermediate_kdf_hash.update(u);
-                        (intermediate_kdf_hash.finalize(), oe)
+                        (intermediate_kdf_hash.finalize(), oe, PasswordType::Owner)
                     } else {
                         err!(PdfError::InvalidPassword);
                     }
@@ -448,15 +679,16 @@ impl Decoder {
             };
 
             let zero_iv = GenericArray::from_slice(&[0u8; 16]);
-            let key_unwrap_cipher: Cbc<Aes256, NoPadding> =
-                Cbc::new(Aes256::new(&intermediate_key), zero_iv);
+            let key_unwrap_cipher =
+                cbc::Decryptor::<Aes256>::new(&intermediate_key, zero_iv);
             let key_slice = t!(key_unwrap_cipher
-                .decrypt(&mut wrapped_key)
+                .decrypt_padded_mut::<NoPadding>(&mut wrapped_key)
                 .map_err(|_| PdfError::InvalidPassword));
             let mut key = [0u8; 32];
             key.copy_from_slice(key_slice);
 
-            let decoder = Decoder::new(key,  32, method, dict.encrypt_metadata);
+            let mut decoder = Decoder::new(key,  32, method, dict.encrypt_metadata);
+            decoder.password = password;
             Ok(decoder)
         } else {
             err!(format!("unsupported V value {}", level).into())
@@ -484,7 +716,7 @@ impl Decoder {
 
         let mut i = 0;
         while i < 64 || i < data[data_total_len - 1] as usize + 32 {
-            let aes: Cbc<Aes128, NoPadding> = Cbc::new(Aes128::new(&key), &iv);
+            let aes = cbc::Encryptor::<Aes128>::new(&key, &iv);
 
             let data_repeat_len = password.len() + block_size + u.len();
             data[..password.len()].copy_from_slice(password);
@@ -497,7 +729,7 @@ impl Decoder {
 
             // The plaintext length will always be a multiple of the block size, unwrap is okay
             let encrypted = aes
-                .encrypt(&mut data[..data_total_len], data_total_len)
+                .encrypt_padded_mut::<NoPadding>(&mut data[..data_total_len], data_total_len)
                 .unwrap();
 
             let sum: usize = encrypted[..16].iter().map(|byte| *byte as usize).sum();
@@ -528,7 +760,19 @@ impl Decoder {
         hash
     }
 
+    /// Decrypt a string of object `id` with generation `gen`.
     pub fn decrypt<'buf>(&self, id: u64, gen: u16, data: &'buf mut [u8]) -> Result<&'buf [u8]> {
+        self.decrypt_with(self.filters.strings, id, gen, data)
+    }
+
+    /// Decrypt the data of a stream with the dictionary `info`, using the crypt filter
+    /// that applies to it.
+    pub fn decrypt_stream<'buf>(&self, id: u64, gen: u16, info: &Dictionary, data: &'buf mut [u8]) -> Result<&'buf [u8]> {
+        let method = t!(self.filters.for_stream(info));
+        self.decrypt_with(method, id, gen, data)
+    }
+
+    fn decrypt_with<'buf>(&self, method: CryptMethod, id: u64, gen: u16, data: &'buf mut [u8]) -> Result<&'buf [u8]> {
         if self.encrypt_indirect_object == Some(PlainRef { id, gen }) {
             // Strings inside the /Encrypt dictionary are not encrypted
             return Ok(data);
@@ -547,38 +791,19 @@ impl Decoder {
         // Algorithm 1
         // a) we have those already
 
-        match self.method {
-            CryptMethod::None => unreachable!(),
+        match method {
+            CryptMethod::None => Ok(data),
             CryptMethod::V2 => {
-                // b)
-                let mut key = [0; 16 + 5];
-                let n = self.key_size;
-                key[..n].copy_from_slice(self.key());
-                key[n..n + 3].copy_from_slice(&id.to_le_bytes()[..3]);
-                key[n + 3..n + 5].copy_from_slice(&gen.to_le_bytes()[..2]);
-
-                // c)
-                let key = *md5::compute(&key[..n + 5]);
-
-                // d)
-                Rc4::encrypt(&key[..(n + 5).min(16)], data);
+                // b) - d)
+                let (key, n) = object_key(self.key(), method, id, gen);
+                Rc4::encrypt(&key[..n], data);
                 Ok(data)
             }
             CryptMethod::AESV2 => {
-                // b)
-                let mut key = [0; 16 + 5 + 4];
-                let n = self.key_size;
-                key[..n].copy_from_slice(self.key());
-                key[n..n + 3].copy_from_slice(&id.to_le_bytes()[..3]);
-                key[n + 3..n + 5].copy_from_slice(&gen.to_le_bytes()[..2]);
-                key[n + 5..n + 9].copy_from_slice(b"sAlT");
-
-                // c)
-                let key = *md5::compute(&key[..n + 9]);
-
-                // d)
-                type Aes128Cbc = Cbc<Aes128, Pkcs7>;
-                let key = &key[..(n + 5).min(16)];
+                // b) - d)
+                type Aes128Cbc = cbc::Decryptor<Aes128>;
+                let (key, n) = object_key(self.key(), method, id, gen);
+                let key = &key[..n];
                 if data.len() < 16 {
                     return Err(PdfError::DecryptionFailure);
                 }
@@ -586,11 +811,11 @@ impl Decoder {
                 let cipher =
                     t!(Aes128Cbc::new_from_slices(key, iv).map_err(|_| PdfError::DecryptionFailure));
                 Ok(t!(cipher
-                    .decrypt(ciphertext)
+                    .decrypt_padded_mut::<Pkcs7>(ciphertext)
                     .map_err(|_| PdfError::DecryptionFailure)))
             }
             CryptMethod::AESV3 => {
-                type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+                type Aes256Cbc = cbc::Decryptor<Aes256>;
                 if data.len() < 16 {
                     return Err(PdfError::DecryptionFailure);
                 }
@@ -598,7 +823,7 @@ impl Decoder {
                 let cipher =
                     t!(Aes256Cbc::new_from_slices(self.key(), iv).map_err(|_| PdfError::DecryptionFailure));
                 Ok(t!(cipher
-                    .decrypt(ciphertext)
+                    .decrypt_padded_mut::<Pkcs7>(ciphertext)
                     .map_err(|_| PdfError::DecryptionFailure)))
             }
         }
@@ -613,11 +838,264 @@ impl fmt::Debug for Decoder {
     }
 }
 
+/// Algorithm used by [`CryptDict::standard`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncryptionMethod {
+    /// RC4 with a 40 bit key (V 1, R 2)
+    Rc4_40,
+    /// RC4 with a 128 bit key (V 2, R 3)
+    Rc4_128,
+    /// AES with a 128 bit key (V 4, R 4)
+    Aes128,
+    /// AES with a 256 bit key (V 5, R 6)
+    Aes256,
+}
+
+/// Parameters for encrypting a file with the standard security handler.
+#[derive(Clone, Debug)]
+pub struct Encryption {
+    pub method: EncryptionMethod,
+    pub user_password: Vec<u8>,
+    /// If empty, the user password is used as owner password as well.
+    pub owner_password: Vec<u8>,
+    /// What a user who only knows the user password is allowed to do.
+    pub permissions: Permissions,
+    pub encrypt_metadata: bool,
+}
+
+impl CryptDict {
+    /// Set up the standard security handler for a file with the first /ID entry `id`.
+    ///
+    /// Returns the dictionary to be stored as /Encrypt and the `Encoder` for the file's objects.
+    pub fn standard(options: &Encryption, id: &[u8]) -> Result<(CryptDict, Encoder)> {
+        let (v, r, bits, method) = match options.method {
+            EncryptionMethod::Rc4_40 => (1, 2, 40, CryptMethod::V2),
+            EncryptionMethod::Rc4_128 => (2, 3, 128, CryptMethod::V2),
+            EncryptionMethod::Aes128 => (4, 4, 128, CryptMethod::AESV2),
+            EncryptionMethod::Aes256 => (5, 6, 256, CryptMethod::AESV3),
+        };
+        let key_size = bits as usize / 8;
+
+        let mut crypt_filters = HashMap::new();
+        let mut filter_name = None;
+        if v >= 4 {
+            crypt_filters.insert("StdCF".into(), CryptFilter {
+                method,
+                auth_event: AuthEvent::DocOpen,
+                length: Some(key_size as u32),
+                recipients: None,
+                encrypt_metadata: None,
+                _other: Dictionary::new(),
+            });
+            filter_name = Some(String::from("StdCF"));
+        }
+        let mut dict = CryptDict {
+            filter: "Standard".into(),
+            sub_filter: None,
+            o: PdfString::new(vec![]),
+            u: PdfString::new(vec![]),
+            r,
+            p: options.permissions.to_p(),
+            v,
+            bits,
+            crypt_filters,
+            default_crypt_filter: filter_name.clone(),
+            string_crypt_filter: filter_name,
+            embedded_file_filter: None,
+            encrypt_metadata: options.encrypt_metadata,
+            oe: None,
+            ue: None,
+            perms: None,
+            recipients: None,
+            _other: Dictionary::new(),
+        };
+
+        let user = &*options.user_password;
+        let owner = match options.owner_password.is_empty() {
+            true => user,
+            false => &*options.owner_password,
+        };
+
+        let key;
+        if r <= 4 {
+            // algorithm 3
+            let password_wrap_key = t!(key_derivation_owner_password_rc4(r, key_size, owner));
+            let mut o = [0u8; 32];
+            let n = user.len().min(32);
+            o[..n].copy_from_slice(&user[..n]);
+            o[n..].copy_from_slice(&PADDING[..32 - n]);
+            let rounds = if r == 2 { 1u8 } else { 20u8 };
+            for round in 0..rounds {
+                let mut round_key = password_wrap_key.clone();
+                for byte in round_key.iter_mut() {
+                    *byte ^= round;
+                }
+                Rc4::encrypt(&round_key, &mut o);
+            }
+            dict.o = PdfString::new(o.to_vec());
+
+            // algorithm 2, then 4 or 5
+            key = key_derivation_user_password_rc4(r, key_size, &dict, id, user);
+            let mut u = match r {
+                2 => compute_u_rev_2(&key[..key_size]),
+                _ => compute_u_rev_3_4(id, &key[..key_size]),
+            };
+            u.resize(32, 0);
+            dict.u = PdfString::new(u);
+        } else {
+            key = t!(random_bytes());
+            let user = t!(prepare_password_r5(user));
+            let owner = t!(prepare_password_r5(owner));
+            let salts: [u8; 32] = t!(random_bytes());
+            let (user_validation_salt, user_key_salt) = (&salts[0..8], &salts[8..16]);
+            let (owner_validation_salt, owner_key_salt) = (&salts[16..24], &salts[24..32]);
+
+            // algorithm 8
+            let mut u = Decoder::revision_6_kdf(&user, user_validation_salt, b"").to_vec();
+            u.extend_from_slice(user_validation_salt);
+            u.extend_from_slice(user_key_salt);
+            let ue = t!(aes256_wrap(&Decoder::revision_6_kdf(&user, user_key_salt, b""), &key));
+
+            // algorithm 9
+            let mut o = Decoder::revision_6_kdf(&owner, owner_validation_salt, &u).to_vec();
+            o.extend_from_slice(owner_validation_salt);
+            o.extend_from_slice(owner_key_salt);
+            let oe = t!(aes256_wrap(&Decoder::revision_6_kdf(&owner, owner_key_salt, &u), &key));
+
+            // algorithm 10
+            let mut perms = [0u8; 16];
+            perms[..4].copy_from_slice(&options.permissions.to_p().to_le_bytes());
+            perms[4..8].copy_from_slice(&[0xff; 4]);
+            perms[8] = if options.encrypt_metadata { b'T' } else { b'F' };
+            perms[9..12].copy_from_slice(b"adb");
+            perms[12..].copy_from_slice(&t!(random_bytes::<4>()));
+
+            dict.u = PdfString::new(u);
+            dict.o = PdfString::new(o);
+            dict.ue = Some(PdfString::new(ue));
+            dict.oe = Some(PdfString::new(oe));
+            dict.perms = Some(PdfString::new(t!(aes256_wrap(&key, &perms))));
+        }
+
+        let encoder = Encoder::new(key, key_size, method, options.encrypt_metadata);
+        Ok((dict, encoder))
+    }
+}
+
+// AES-256 without padding and a zero IV, as used for UE, OE and Perms
+fn aes256_wrap(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher =
+        t!(cbc::Encryptor::<Aes256>::new_from_slices(key, &[0u8; 16]).map_err(|e| other!("{}", e)));
+    Ok(cipher.encrypt_padded_vec_mut::<NoPadding>(data))
+}
+
+/// Encrypts the strings and streams of objects, the counterpart of the `Decoder`.
+pub struct Encoder {
+    key_size: usize,
+    key: [u8; 32], // maximum length
+    method: CryptMethod,
+    encrypt_metadata: bool,
+    filters: CryptFilters,
+}
+impl Encoder {
+    pub fn new(key: [u8; 32], key_size: usize, method: CryptMethod, encrypt_metadata: bool) -> Encoder {
+        Encoder {
+            key_size,
+            key,
+            method,
+            encrypt_metadata,
+            filters: CryptFilters::uniform(method),
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key[.. self.key_size]
+    }
+
+    /// Encrypt a string or stream of object `id` with generation `gen`.
+    pub fn encrypt(&self, id: u64, gen: u16, data: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_with(self.method, id, gen, data)
+    }
+
+    fn encrypt_with(&self, method: CryptMethod, id: u64, gen: u16, data: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            CryptMethod::None => Ok(data.to_vec()),
+            CryptMethod::V2 => {
+                let (key, n) = object_key(self.key(), method, id, gen);
+                let mut data = data.to_vec();
+                Rc4::encrypt(&key[..n], &mut data);
+                Ok(data)
+            }
+            CryptMethod::AESV2 => {
+                type Aes128Cbc = cbc::Encryptor<Aes128>;
+                let (key, n) = object_key(self.key(), method, id, gen);
+                let iv: [u8; 16] = t!(random_bytes());
+                let cipher = t!(Aes128Cbc::new_from_slices(&key[..n], &iv).map_err(|e| other!("{}", e)));
+                let mut out = iv.to_vec();
+                out.extend_from_slice(&cipher.encrypt_padded_vec_mut::<Pkcs7>(data));
+                Ok(out)
+            }
+            CryptMethod::AESV3 => {
+                type Aes256Cbc = cbc::Encryptor<Aes256>;
+                let iv: [u8; 16] = t!(random_bytes());
+                let cipher = t!(Aes256Cbc::new_from_slices(self.key(), &iv).map_err(|e| other!("{}", e)));
+                let mut out = iv.to_vec();
+                out.extend_from_slice(&cipher.encrypt_padded_vec_mut::<Pkcs7>(data));
+                Ok(out)
+            }
+        }
+    }
+
+    /// Encrypt all strings and streams in `p`, which is the object `id` with generation `gen`.
+    ///
+    /// Metadata streams are left as they are unless /EncryptMetadata is set.
+    pub fn encrypt_primitive(&self, id: u64, gen: u16, p: Primitive) -> Result<Primitive> {
+        Ok(match p {
+            Primitive::Stream(PdfStream { info, data }) => {
+                let is_metadata = matches!(info.get("Type"), Some(Primitive::Name(ref ty)) if ty == "Metadata");
+                if is_metadata && !self.encrypt_metadata {
+                    return Ok(Primitive::Stream(PdfStream { info, data }));
+                }
+                let method = t!(self.filters.for_stream(&info));
+                let mut info = t!(self.encrypt_dict(id, gen, info));
+                let data = t!(self.encrypt_with(method, id, gen, &data));
+                info.insert("Length", Primitive::Integer(data.len() as i32));
+                Primitive::Stream(PdfStream { info, data })
+            }
+            Primitive::String(s) => Primitive::String(PdfString::new(t!(self.encrypt_with(self.filters.strings, id, gen, s.as_bytes())))),
+            Primitive::Dictionary(dict) => Primitive::Dictionary(t!(self.encrypt_dict(id, gen, dict))),
+            Primitive::Array(parts) => Primitive::Array(
+                t!(parts.into_iter().map(|p| self.encrypt_primitive(id, gen, p)).collect::<Result<_>>())
+            ),
+            p => p
+        })
+    }
+    fn encrypt_dict(&self, id: u64, gen: u16, dict: Dictionary) -> Result<Dictionary> {
+        let mut out = Dictionary::new();
+        for (k, v) in dict {
+            out.insert(k, t!(self.encrypt_primitive(id, gen, v)));
+        }
+        Ok(out)
+    }
+}
+impl fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encoder")
+            .field("method", &self.method)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    #[test]
-    fn unencrypted_strings() {
-        let data_prefix = b"%PDF-1.5\n\
+    use super::{CryptDict, Decoder};
+    use crate::object::{Object, NoResolve, PlainRef, Resolve};
+    use crate::primitive::Primitive;
+
+    /// A one page file with the content stream `stream` (object 4) and the encryption
+    /// dictionary `encrypt` (object 5).
+    fn encrypted_file(stream: &[u8], encrypt: &[u8]) -> Vec<u8> {
+        let mut data_prefix = b"%PDF-1.5\n\
             1 0 obj\n\
             << /Type /Catalog /Pages 2 0 R >>\n\
             endobj\n\
@@ -627,27 +1105,13 @@ mod tests {
             3 0 obj\n\
             << /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R >>\n\
             endobj\n\
-            4 0 obj\n\
-            << /Length 0 >>\n\
-            stream\n\
-            endstream\n\
-            endobj\n\
-            5 0 obj\n\
-            <<\n\
-                /V 4\n\
-                /CF <<\n\
-                    /StdCF << /Type /CryptFilter /CFM /V2 >>\n\
-                >>\n\
-                /StmF /StdCF\n\
-                /StrF /StdCF\n\
-                /R 4\n\
-                /O (owner pwd hash!!)\n\
-                /U <E721D9D63EC4E7BD4DA6C9F0E30C8290>\n\
-                /P -4\n\
-            >>\n\
-            endobj\n\
-            xref\n\
-            1 5\n";
+            4 0 obj\n".to_vec();
+        data_prefix.extend_from_slice(stream);
+        data_prefix.extend_from_slice(b"\nendobj\n5 0 obj\n");
+        data_prefix.extend_from_slice(encrypt);
+        data_prefix.extend_from_slice(b"\nendobj\nxref\n1 5\n");
+        let data_prefix = &data_prefix[..];
+
         let mut data = data_prefix.to_vec();
         for obj_nr in 1..=5 {
             let needle = format!("\n{} 0 obj\n", obj_nr).into_bytes();
@@ -674,7 +1138,34 @@ mod tests {
             .rposition(|w| w == b"xref")
             .unwrap();
         data.append(&mut format!("{}\n%%EOF", xref_offset).into_bytes());
+        data
+    }
+
+    fn encrypt_dict(stm_f: &str) -> Vec<u8> {
+        format!("<<\n\
+                /V 4\n\
+                /CF <<\n\
+                    /StdCF << /Type /CryptFilter /CFM /V2 >>\n\
+                >>\n\
+                /StmF /{}\n\
+                /StrF /StdCF\n\
+                /R 4\n\
+                /O (owner pwd hash!!)\n\
+                /U <E721D9D63EC4E7BD4DA6C9F0E30C8290>\n\
+                /P -4\n\
+            >>", stm_f).into_bytes()
+    }
 
+    fn stream_data(file: &crate::file::File<Vec<u8>>, id: u64) -> Vec<u8> {
+        match file.resolve(PlainRef { id, gen: 0 }).unwrap() {
+            Primitive::Stream(stream) => stream.data,
+            p => panic!("not a stream: {:?}", p)
+        }
+    }
+
+    #[test]
+    fn unencrypted_strings() {
+        let data = encrypted_file(b"<< /Length 0 >>\nstream\n\nendstream", &encrypt_dict("StdCF"));
         let file = crate::file::File::from_data(data).unwrap();
 
         // PDF reference says strings in the encryption dictionary are "not
@@ -684,4 +1175,37 @@ mod tests {
             b"owner pwd hash!!",
         );
     }
+
+    #[test]
+    fn identity_stream_filter() {
+        let stream = b"<< /Length 5 >>\nstream\nBT ET\nendstream";
+        let file = crate::file::File::from_data(encrypted_file(stream, &encrypt_dict("Identity"))).unwrap();
+        assert_eq!(stream_data(&file, 4), b"BT ET");
+    }
+
+    #[test]
+    fn crypt_filter_identity_name() {
+        // /StmF would encrypt the stream, the /Crypt filter overrides it
+        for stream in [
+            &b"<< /Length 5 /Filter /Crypt >>\nstream\nBT ET\nendstream"[..],
+            &b"<< /Length 5 /Filter [/Crypt] /DecodeParms [<< /Name /Identity >>] >>\nstream\nBT ET\nendstream"[..],
+        ] {
+            let file = crate::file::File::from_data(encrypted_file(stream, &encrypt_dict("StdCF"))).unwrap();
+            assert_eq!(stream_data(&file, 4), b"BT ET");
+        }
+    }
+
+    #[test]
+    fn crypt_filter_by_name() {
+        let dict = crate::parser::parse(&encrypt_dict("Identity"), &NoResolve).unwrap();
+        let dict = CryptDict::from_primitive(dict, &NoResolve).unwrap();
+        let encoder = Decoder::default(&dict, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap().encoder();
+        let encrypted = encoder.encrypt(4, 0, b"BT ET").unwrap();
+
+        let mut stream = format!("<< /Length {} /Filter /Crypt /DecodeParms << /Name /StdCF >> >>\nstream\n", encrypted.len()).into_bytes();
+        stream.extend_from_slice(&encrypted);
+        stream.extend_from_slice(b"\nendstream");
+        let file = crate::file::File::from_data(encrypted_file(&stream, &encrypt_dict("Identity"))).unwrap();
+        assert_eq!(stream_data(&file, 4), b"BT ET");
+    }
 }
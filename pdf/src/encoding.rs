@@ -4,6 +4,9 @@ use crate::object::{Object, Resolve};
 use crate::primitive::Primitive;
 use crate::error::{Result};
 
+mod tables;
+mod glyphlist;
+
 #[derive(Debug, Clone)]
 pub struct Encoding {
     pub base: BaseEncoding,
@@ -17,6 +20,7 @@ pub enum BaseEncoding {
     MacRomanEncoding,
     WinAnsiEncoding,
     MacExpertEncoding,
+    ZapfDingbatsEncoding,
     #[pdf(name="Identity-H")]
     IdentityH,
     None,
@@ -50,14 +54,14 @@ impl Object for Encoding {
                                 differences.insert(gid, name);
                                 gid += 1;
                             },
-                            _ => panic!()
+                            p => bail!("unexpected primitive in /Differences: {:?}", p)
                         }
                     }
                 }
                 Ok(Encoding { base, differences })
             }
             Primitive::Reference(r) => Self::from_primitive(resolve.resolve(r)?, resolve),
-            _ => panic!()
+            p => bail!("invalid /Encoding {:?}", p)
         }
     }
 }
@@ -68,4 +72,176 @@ impl Encoding {
             differences: HashMap::new()
         }
     }
+    /// The glyph name of `code`, from `/Differences` or the base encoding.
+    pub fn glyph_name(&self, code: u8) -> Option<&str> {
+        match self.differences.get(&(code as u32)) {
+            Some(name) => Some(name),
+            None => self.base.glyph_name(code)
+        }
+    }
+    /// The text `code` stands for.
+    pub fn decode(&self, code: u8) -> Option<String> {
+        self.glyph_name(code).and_then(glyph_name_to_unicode)
+    }
+}
+impl BaseEncoding {
+    /// The 256 glyph names of a simple encoding, `None` for CMaps and unknown encodings.
+    pub fn table(&self) -> Option<&'static [&'static str; 256]> {
+        match *self {
+            BaseEncoding::StandardEncoding => Some(&tables::STANDARD),
+            BaseEncoding::SymbolEncoding => Some(&tables::SYMBOL),
+            BaseEncoding::MacRomanEncoding => Some(&tables::MAC_ROMAN),
+            BaseEncoding::WinAnsiEncoding => Some(&tables::WIN_ANSI),
+            BaseEncoding::MacExpertEncoding => Some(&tables::MAC_EXPERT),
+            BaseEncoding::ZapfDingbatsEncoding => Some(&tables::ZAPF_DINGBATS),
+            _ => None
+        }
+    }
+    pub fn glyph_name(&self, code: u8) -> Option<&'static str> {
+        self.table().map(|t| t[code as usize]).filter(|name| !name.is_empty())
+    }
+    /// The code of the glyph `name`, if it is part of this encoding.
+    pub fn code(&self, name: &str) -> Option<u8> {
+        self.table()?.iter().position(|&n| n == name).map(|i| i as u8)
+    }
+}
+
+/// Map a glyph name to the text it represents, following the Adobe Glyph List specification.
+///
+/// Anything after a period is a variant suffix and ignored, underscores separate the components
+/// of ligatures. Each component is looked up in the glyph list or decoded from the `uniXXXX`
+/// and `uXXXX[XX]` forms. Small caps (`Asmall`), old style (`oneoldstyle`) and superior or inferior
+/// forms of expert fonts map to the plain characters.
+pub fn glyph_name_to_unicode(name: &str) -> Option<String> {
+    let name = name.split('.').next().unwrap();
+    let mut out = String::new();
+    for component in name.split('_') {
+        component_to_unicode(component, &mut out)?;
+    }
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+fn component_to_unicode(name: &str, out: &mut String) -> Option<()> {
+    if let Some(text) = glyph_text(name) {
+        out.push_str(text);
+        return Some(());
+    }
+    if let Some(hex) = name.strip_prefix("uni") {
+        if hex.is_empty() || hex.len() % 4 != 0 {
+            return None;
+        }
+        let start = out.len();
+        for i in (0 .. hex.len()).step_by(4) {
+            match parse_hex(&hex[i .. i + 4]) {
+                Some(c) if !(0xD800 ..= 0xDFFF).contains(&c) => out.push(char::from_u32(c)?),
+                _ => {
+                    out.truncate(start);
+                    return None;
+                }
+            }
+        }
+        return Some(());
+    }
+    if let Some(hex) = name.strip_prefix('u') {
+        if (4 ..= 6).contains(&hex.len()) {
+            out.push(parse_hex(hex).and_then(char::from_u32)?);
+            return Some(());
+        }
+    }
+    for &(suffix, digits) in &[("oldstyle", "0123456789"), ("superior", "⁰¹²³⁴⁵⁶⁷⁸⁹"), ("inferior", "₀₁₂₃₄₅₆₇₈₉")] {
+        if let Some(base) = name.strip_suffix(suffix) {
+            let c = glyph_char(base)?;
+            out.push(c.to_digit(10).and_then(|d| digits.chars().nth(d as usize)).unwrap_or(c));
+            return Some(());
+        }
+    }
+    if let Some(base) = name.strip_suffix("small") {
+        // `Acutesmall` is the small form of `acute`
+        let c = glyph_char(base).or_else(|| glyph_char(&base.to_lowercase()))?;
+        out.extend(c.to_lowercase());
+        return Some(());
+    }
+    None
+}
+
+fn glyph_text(name: &str) -> Option<&'static str> {
+    glyphlist::GLYPHS.binary_search_by_key(&name, |&(n, _)| n).ok().map(|i| glyphlist::GLYPHS[i].1)
+}
+
+/// The character of a glyph that stands for a single one.
+fn glyph_char(name: &str) -> Option<char> {
+    let mut chars = glyph_text(name)?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    if s.bytes().all(|b| b.is_ascii_digit() || (b'A' ..= b'F').contains(&b)) {
+        u32::from_str_radix(s, 16).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyph_names() {
+        let decode = |name| glyph_name_to_unicode(name);
+        assert_eq!(decode("A").as_deref(), Some("A"));
+        assert_eq!(decode("eacute").as_deref(), Some("é"));
+        assert_eq!(decode("fi").as_deref(), Some("\u{FB01}"));
+        assert_eq!(decode("f_i").as_deref(), Some("fi"));
+        assert_eq!(decode("a.sc").as_deref(), Some("a"));
+        assert_eq!(decode("uni20AC").as_deref(), Some("€"));
+        assert_eq!(decode("uni00410042").as_deref(), Some("AB"));
+        assert_eq!(decode("u1F600").as_deref(), Some("😀"));
+        assert_eq!(decode("uniD800"), None);
+        assert_eq!(decode("uni20ac"), None);
+        assert_eq!(decode("Asmall").as_deref(), Some("a"));
+        assert_eq!(decode("twooldstyle").as_deref(), Some("2"));
+        assert_eq!(decode("twoinferior").as_deref(), Some("₂"));
+        assert_eq!(decode("a20").as_deref(), Some("✔"));
+        assert_eq!(decode("g123"), None);
+        // names of the full glyph list
+        assert_eq!(decode("afii57636").as_deref(), Some("\u{20AA}"));
+        assert_eq!(decode("alefhebrew").as_deref(), Some("\u{05D0}"));
+        assert_eq!(decode("lamedholamdagesh").as_deref(), Some("\u{05DC}\u{05B9}\u{05BC}"));
+        assert_eq!(decode("zukatakana").as_deref(), Some("\u{30BA}"));
+        // and not the private use values of the glyph list
+        assert_eq!(decode("Aacutesmall").as_deref(), Some("á"));
+        assert_eq!(decode("parenlefttp").as_deref(), Some("\u{239B}"));
+    }
+
+    #[test]
+    fn tables() {
+        for base in &[BaseEncoding::StandardEncoding, BaseEncoding::WinAnsiEncoding, BaseEncoding::MacRomanEncoding,
+                BaseEncoding::SymbolEncoding, BaseEncoding::ZapfDingbatsEncoding] {
+            for code in 0 ..= 255 {
+                if let Some(name) = base.glyph_name(code) {
+                    assert!(glyph_name_to_unicode(name).is_some(), "{:?} {} {}", base, code, name);
+                }
+            }
+        }
+        let win = Encoding { base: BaseEncoding::WinAnsiEncoding, differences: HashMap::new() };
+        assert_eq!(win.decode(0x80).as_deref(), Some("€"));
+        assert_eq!(win.decode(0xE9).as_deref(), Some("é"));
+        assert_eq!(BaseEncoding::StandardEncoding.glyph_name(0x27), Some("quoteright"));
+        assert_eq!(BaseEncoding::MacRomanEncoding.code("eacute"), Some(0x8E));
+        assert_eq!(BaseEncoding::SymbolEncoding.glyph_name(0x61), Some("alpha"));
+
+        let mut differences = HashMap::new();
+        differences.insert(0x41, "Omega".to_string());
+        let enc = Encoding { base: BaseEncoding::StandardEncoding, differences };
+        assert_eq!(enc.decode(0x41).as_deref(), Some("Ω"));
+        assert_eq!(enc.decode(0x42).as_deref(), Some("B"));
+    }
 }
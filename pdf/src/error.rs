@@ -141,6 +141,9 @@ pub enum PdfError {
     #[snafu(display("CID decode error"))]
     CidDecode,
 
+    #[snafu(display("The file of the predefined CMap {} is not available, use CMap::set_resource_dir to set the directory of the CMap files", name))]
+    MissingCMap { name: String },
+
     #[snafu(display("Max nesting depth reached"))]
     MaxDepth,
 
@@ -1,23 +1,24 @@
 //! This is kind of the entry-point of the type-safe PDF functionality.
 use std::fs;
 use std::marker::PhantomData;
-use std::collections::HashMap;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::path::Path;
 use std::io::Write;
 
 use crate as pdf;
 use crate::error::*;
 use crate::object::*;
-use crate::primitive::{Primitive, Dictionary, PdfString};
-use crate::backend::Backend;
+use crate::primitive::{Primitive, Dictionary, PdfString, PdfStream};
+use crate::backend::{Backend, LazyBackend};
 use crate::any::{Any};
 use crate::parser::Lexer;
 use crate::parser::{parse_indirect_object, parse};
-use crate::xref::{XRef, XRefTable, XRefInfo};
-use crate::crypt::Decoder;
-use crate::crypt::CryptDict;
+use crate::xref::{XRef, XRefTable, XRefInfo, XRefKind};
+use crate::crypt::{self, Decoder, CryptDict, Encryption, Permissions, PasswordType};
+#[cfg(feature="pubsec")]
+use crate::crypt::PubSecKey;
+use crate::repair;
 
 #[must_use]
 pub struct PromisedRef<T> {
@@ -33,9 +34,37 @@ impl<T> PromisedRef<T> {
     }
 }
 
+const CACHE_SHARDS: usize = 16;
+
+/// Objects that have been resolved, split into shards so that threads which resolve
+/// different objects rarely wait for each other.
+struct ObjectCache {
+    shards: Vec<Mutex<HashMap<PlainRef, Any>>>,
+}
+impl ObjectCache {
+    fn new() -> ObjectCache {
+        ObjectCache {
+            shards: (0 .. CACHE_SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+    fn shard(&self, key: PlainRef) -> &Mutex<HashMap<PlainRef, Any>> {
+        &self.shards[key.id as usize % CACHE_SHARDS]
+    }
+    fn get(&self, key: PlainRef) -> Option<Any> {
+        self.shard(key).lock().unwrap().get(&key).cloned()
+    }
+    fn insert(&self, key: PlainRef, value: Any) {
+        self.shard(key).lock().unwrap().insert(key, value);
+    }
+    fn remove_object(&mut self, id: ObjNr) {
+        let shard = &mut self.shards[id as usize % CACHE_SHARDS];
+        shard.get_mut().unwrap().retain(|key, _| key.id != id);
+    }
+}
+
 pub struct Storage<B: Backend> {
     // objects identical to those in the backend
-    cache: RefCell<HashMap<PlainRef, Any>>,
+    cache: ObjectCache,
 
     // objects that differ from the backend
     changes:    HashMap<ObjNr, Primitive>,
@@ -48,6 +77,13 @@ pub struct Storage<B: Backend> {
 
     // Position of the PDF header in the file.
     start_offset: usize,
+
+    // startxref of the last revision in the backend, and how its xref section is stored.
+    last_xref: Option<(usize, XRefKind)>,
+
+    // The xref sections of the backend were rebuilt by scanning the file, so the next update
+    // has to list every object.
+    repaired: bool,
 }
 impl<B: Backend> Storage<B> {
     pub fn new(backend: B, refs: XRefTable, start_offset: usize) -> Storage<B> {
@@ -55,9 +91,11 @@ impl<B: Backend> Storage<B> {
             backend,
             refs,
             start_offset,
-            cache: RefCell::new(HashMap::new()),
+            cache: ObjectCache::new(),
             changes: HashMap::new(),
             decoder: None,
+            last_xref: None,
+            repaired: false,
         }
     }
 }
@@ -67,8 +105,9 @@ impl<B: Backend> Resolve for Storage<B> {
             Some(ref p) => Ok((*p).clone()),
             None => match t!(self.refs.get(r.id)) {
                 XRef::Raw {pos, ..} => {
-                    let mut lexer = Lexer::new(t!(self.backend.read(self.start_offset + pos ..)));
-                    let p = t!(parse_indirect_object(&mut lexer, self, self.decoder.as_ref())).1;
+                    let (_, p) = t!(self.backend.with_data_from(self.start_offset + pos, |data| {
+                        parse_indirect_object(&mut Lexer::new(data), self, self.decoder.as_ref())
+                    }));
                     Ok(p)
                 }
                 XRef::Stream {stream_id, index} => {
@@ -86,14 +125,16 @@ impl<B: Backend> Resolve for Storage<B> {
     fn get<T: Object>(&self, r: Ref<T>) -> Result<RcRef<T>> {
         let key = r.get_inner();
         
-        if let Some(any) = self.cache.borrow().get(&key) {
-            return Ok(RcRef::new(key, any.clone().downcast()?));
+        if let Some(any) = self.cache.get(key) {
+            return Ok(RcRef::new(key, any.downcast()?));
         }
 
+        // Another thread may be resolving the same object, in which case both results are equal
+        // and the later one stays in the cache.
         let primitive = t!(self.resolve(key));
         let obj = t!(T::from_primitive(primitive, self));
-        let rc = Rc::new(obj);
-        self.cache.borrow_mut().insert(key, Any::new(rc.clone()));
+        let rc = Arc::new(obj);
+        self.cache.insert(key, Any::new(rc.clone()));
         
         Ok(RcRef::new(key, rc))
     }
@@ -104,7 +145,7 @@ impl<B: Backend> Updater for Storage<B> {
         self.refs.push(XRef::Promised);
         let primitive = obj.to_primitive(self)?;
         self.changes.insert(id, primitive);
-        let rc = Rc::new(obj);
+        let rc = Arc::new(obj);
         let r = PlainRef { id, gen: 0 };
         
         Ok(RcRef::new(r, rc))
@@ -112,14 +153,15 @@ impl<B: Backend> Updater for Storage<B> {
     fn update<T: ObjectWrite>(&mut self, old: PlainRef, obj: T) -> Result<RcRef<T>> {
         let r = match self.refs.get(old.id)? {
             XRef::Free { .. } => panic!(),
-            XRef::Raw { gen_nr, .. } => PlainRef { id: old.id, gen: gen_nr + 1 },
-            XRef::Stream { .. } => return self.create(obj),
+            XRef::Raw { gen_nr, .. } => PlainRef { id: old.id, gen: gen_nr },
+            XRef::Stream { .. } => PlainRef { id: old.id, gen: 0 },
             XRef::Promised => PlainRef { id: old.id, gen: 0 },
             XRef::Invalid => panic!()
         };
         let primitive = obj.to_primitive(self)?;
         self.changes.insert(old.id, primitive);
-        let rc = Rc::new(obj);
+        self.cache.remove_object(old.id);
+        let rc = Arc::new(obj);
         
         Ok(RcRef::new(r, rc))
     }
@@ -143,46 +185,276 @@ impl<B: Backend> Updater for Storage<B> {
     }
 }
 
+/// Options for [`Storage::save_with`].
+#[derive(Clone, Debug, Default)]
+pub struct SaveOptions {
+    /// Pack changed objects that are not streams into compressed object streams.
+    /// Implies a cross-reference stream, even if the previous revision used a table.
+    pub object_streams: bool,
+
+    /// Encrypt the output with the standard security handler.
+    ///
+    /// An incremental update keeps the encryption of the file it is appended to, so this can
+    /// only be used for new files and with [`Storage::save_compacted`].
+    pub encryption: Option<Encryption>,
+}
+
+// maximum number of objects packed into a single object stream
+const OBJECTS_PER_STREAM: usize = 100;
+
 impl Storage<Vec<u8>> {
+    /// Append all changed objects to the backend as an incremental update.
+    ///
+    /// The original bytes are left untouched. The new cross-reference section only lists the
+    /// changed objects, uses the same form (table or stream) as the previous revision and
+    /// points back to it with `/Prev`. If the file had to be repaired when it was loaded, it
+    /// is a cross-reference stream listing all objects instead.
     pub fn save(&mut self, trailer: &mut Trailer) -> Result<&[u8]> {
-        let xref_promise = self.promise::<Stream<XRefInfo>>();
+        self.save_with(trailer, &SaveOptions::default())
+    }
 
-        trailer.highest_id = self.refs.len() as _;
-        let trailer = trailer.to_dict(self)?;
+    /// Like [`save`](Storage::save), with additional options.
+    pub fn save_with(&mut self, trailer: &mut Trailer, options: &SaveOptions) -> Result<&[u8]> {
+        let encoder = match (&options.encryption, &self.decoder) {
+            (Some(encryption), None) if self.last_xref.is_none() && !self.repaired => {
+                let (dict, encoder) = t!(CryptDict::standard(encryption, t!(file_id(trailer))));
+                trailer.encrypt_dict = Some(t!(self.create(dict)));
+                Some(encoder)
+            }
+            (Some(_), _) => bail!("can't change the encryption in an incremental update"),
+            (None, Some(decoder)) => Some(decoder.encoder()),
+            (None, None) => None
+        };
+        // the encryption dictionary itself is not encrypted
+        let encrypt_id = trailer.encrypt_dict.as_ref().map(|r| r.get_ref().get_inner().id);
 
-        let mut changes: Vec<_> = self.changes.iter().collect();
+        let kind = match self.last_xref {
+            _ if options.object_streams => XRefKind::Stream,
+            Some((_, kind)) => kind,
+            None => XRefKind::Stream
+        };
+
+        let mut changes: Vec<_> = self.changes.drain().collect();
         changes.sort_unstable_by_key(|&(id, _)| id);
 
-        for (&id, primitive) in changes.iter() {
-            let pos = self.backend.len();
-            self.refs.set(id, XRef::Raw { pos: pos as _, gen_nr: 0 });
-            write!(&mut self.backend, "{} {} obj\n", id, 0)?;
-            primitive.serialize(&mut self.backend, 0)?;
-            write!(self.backend, "endobj\n")?;
+        let mut packed = vec![];
+        if options.object_streams {
+            // the encryption dictionary and objects with a generation number other than 0
+            // have to stay outside of object streams
+            let (inner, raw) = changes.into_iter().partition(|&(id, ref p)| {
+                !matches!(p, Primitive::Stream(_))
+                && Some(id) != encrypt_id
+                && matches!(self.refs.get(id), Ok(XRef::Promised) | Ok(XRef::Stream { .. }) | Ok(XRef::Raw { gen_nr: 0, .. }))
+            });
+            changes = raw;
+            for chunk in inner.chunks(OBJECTS_PER_STREAM) {
+                let stream_id = self.refs.len() as ObjNr;
+                self.refs.push(XRef::Promised);
+                for (index, &(id, _)) in chunk.iter().enumerate() {
+                    self.refs.set(id, XRef::Stream { stream_id, index });
+                }
+                let stream = t!(ObjectStream::new(chunk));
+                changes.push((stream_id, t!(stream.to_primitive(self))));
+                packed.extend(chunk.iter().map(|&(id, _)| id));
+            }
         }
 
-        let xref_pos = self.backend.len();
+        let xref_promise = match kind {
+            XRefKind::Stream => Some(self.promise::<Stream<XRefInfo>>()),
+            XRefKind::Table => None,
+        };
 
-        // only write up to the xref stream obj id
-        let stream = self.refs.write_stream(xref_promise.get_inner().id as _)?;
+        trailer.highest_id = self.refs.len() as _;
+        trailer.prev_trailer_pos = self.last_xref.map(|(pos, _)| pos as _);
+        let trailer = trailer.to_dict(self)?;
 
-        write!(&mut self.backend, "{} {} obj\n", xref_promise.get_inner().id, 0)?;
-        let mut xref_and_trailer = stream.to_pdf_stream(&mut NoUpdate)?;
-        for (k, v) in trailer.into_iter() {
-            xref_and_trailer.info.insert(k, v);
+        if !self.backend.is_empty() && !self.backend.ends_with(b"\n") {
+            self.backend.push(b'\n');
+        }
+        let mut ids = packed;
+        for (id, primitive) in changes {
+            let gen_nr = match self.refs.get(id)? {
+                XRef::Raw { gen_nr, .. } => gen_nr,
+                _ => 0
+            };
+            let primitive = match encoder {
+                Some(ref encoder) if Some(id) != encrypt_id => t!(encoder.encrypt_primitive(id, gen_nr, primitive)),
+                _ => primitive
+            };
+            let pos = self.backend.len() - self.start_offset;
+            self.refs.set(id, XRef::Raw { pos, gen_nr });
+            writeln!(self.backend, "{} {} obj", id, gen_nr)?;
+            primitive.serialize(&mut self.backend, 0)?;
+            writeln!(self.backend, "\nendobj")?;
+            ids.push(id);
+        }
+        if self.repaired {
+            // the old sections can't be trusted
+            ids = (0 .. self.refs.len() as ObjNr)
+                .filter(|&id| matches!(self.refs.get(id), Ok(XRef::Free { .. }) | Ok(XRef::Raw { .. }) | Ok(XRef::Stream { .. })))
+                .collect();
         }
 
-        xref_and_trailer.serialize(&mut self.backend)?;
-        write!(self.backend, "endobj\n")?;
+        let xref_pos = self.backend.len() - self.start_offset;
+        match xref_promise {
+            Some(xref_promise) => {
+                let xref_id = xref_promise.get_inner().id;
+                self.refs.set(xref_id, XRef::Raw { pos: xref_pos, gen_nr: 0 });
+                ids.push(xref_id);
+                ids.sort_unstable();
+
+                let stream = self.refs.write_stream_sections(&ids, self.refs.len())?;
+                let stream = match options.object_streams {
+                    true => t!(stream.flate_encode()),
+                    false => stream.hexencode(),
+                };
+                let mut xref_and_trailer = stream.to_pdf_stream(&mut NoUpdate)?;
+                for (k, v) in trailer.into_iter() {
+                    xref_and_trailer.info.insert(k, v);
+                }
 
-        let _ = self.fulfill(xref_promise, stream)?;
+                writeln!(self.backend, "{} {} obj", xref_id, 0)?;
+                xref_and_trailer.serialize(&mut self.backend)?;
+                writeln!(self.backend, "endobj")?;
+            }
+            None => {
+                self.refs.write_table(&ids, &mut self.backend)?;
+                writeln!(self.backend, "trailer")?;
+                Primitive::Dictionary(trailer).serialize(&mut self.backend, 0)?;
+            }
+        }
 
-        write!(self.backend, "\nstartxref\n{}\n%%EOF", xref_pos).unwrap();
+        write!(self.backend, "startxref\n{}\n%%EOF\n", xref_pos)?;
+        self.last_xref = Some((xref_pos, kind));
+        self.repaired = false;
 
         Ok(&self.backend)
     }
 }
 
+impl<B: Backend> Storage<B> {
+    /// Write all objects reachable from `trailer` as a new file with a single revision.
+    ///
+    /// Unreachable objects are dropped and the remaining ones are renumbered densely,
+    /// in the order they are found. The output is only encrypted if `options.encryption` is set,
+    /// `options.object_streams` is ignored.
+    pub fn save_compacted(&mut self, trailer: &mut Trailer, options: &SaveOptions) -> Result<Vec<u8>> {
+        let encryption = match options.encryption {
+            Some(ref encryption) => Some(t!(CryptDict::standard(encryption, t!(file_id(trailer))))),
+            None => None
+        };
+        trailer.prev_trailer_pos = None;
+        let mut trailer = t!(trailer.to_dict(self));
+        trailer.remove("Encrypt");
+
+        // old object number -> new object number
+        let mut numbers = HashMap::new();
+        let mut objects = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        for p in trailer.values() {
+            p.trace(&mut |r| queue.push_back(r));
+        }
+        while let Some(r) = queue.pop_front() {
+            if !visited.insert(r.id) {
+                continue;
+            }
+            // references to missing objects are replaced by null below
+            let p = match self.resolve(r) {
+                Ok(Primitive::Stream(mut stream)) => {
+                    // the length may be an indirect object and is wrong after decryption
+                    stream.info.insert("Length", Primitive::Integer(stream.data.len() as i32));
+                    Primitive::Stream(stream)
+                }
+                Ok(p) => p,
+                Err(e) => {
+                    warn!("dropping reference to {:?}: {}", r, e);
+                    continue;
+                }
+            };
+            p.trace(&mut |r| queue.push_back(r));
+            numbers.insert(r.id, objects.len() as ObjNr + 1);
+            objects.push(p);
+        }
+
+        let mut out = Vec::new();
+        let header = t!(self.backend.read(self.start_offset .. std::cmp::min(self.start_offset + 8, self.backend.len())));
+        let version = header.get(.. 8).filter(|v| v.starts_with(b"%PDF-")).unwrap_or(b"%PDF-1.7");
+        out.extend_from_slice(version);
+        out.extend_from_slice(b"\n%\xe2\xe3\xcf\xd3\n");
+
+        let mut refs = XRefTable::new(objects.len() as ObjNr + 1);
+        refs.set(0, XRef::Free { next_obj_nr: 0, gen_nr: 0xffff });
+        for (i, p) in objects.into_iter().enumerate() {
+            let id = i as ObjNr + 1;
+            let p = match encryption {
+                Some((_, ref encoder)) => t!(encoder.encrypt_primitive(id, 0, renumber(p, &numbers))),
+                None => renumber(p, &numbers)
+            };
+            refs.set(id, XRef::Raw { pos: out.len(), gen_nr: 0 });
+            writeln!(out, "{} 0 obj", id)?;
+            p.serialize(&mut out, 0)?;
+            writeln!(out, "\nendobj")?;
+        }
+        let mut encrypt_ref = None;
+        if let Some((dict, _)) = encryption {
+            let id = refs.len() as ObjNr;
+            refs.push(XRef::Raw { pos: out.len(), gen_nr: 0 });
+            writeln!(out, "{} 0 obj", id)?;
+            t!(dict.to_primitive(&mut NoUpdate)).serialize(&mut out, 0)?;
+            writeln!(out, "\nendobj")?;
+            encrypt_ref = Some(PlainRef { id, gen: 0 });
+        }
+
+        let xref_pos = out.len();
+        let ids: Vec<ObjNr> = (0 .. refs.len() as ObjNr).collect();
+        refs.write_table(&ids, &mut out)?;
+
+        let mut trailer = renumber_dict(trailer, &numbers);
+        trailer.insert("Size", Primitive::Integer(refs.len() as i32));
+        if let Some(r) = encrypt_ref {
+            trailer.insert("Encrypt", Primitive::Reference(r));
+        }
+        writeln!(out, "trailer")?;
+        Primitive::Dictionary(trailer).serialize(&mut out, 0)?;
+        write!(out, "startxref\n{}\n%%EOF\n", xref_pos)?;
+
+        Ok(out)
+    }
+}
+
+/// The first part of the file identifier, which is created if the trailer has none.
+fn file_id(trailer: &mut Trailer) -> Result<&[u8]> {
+    if trailer.id.is_empty() {
+        let id = PdfString::new(t!(crypt::random_bytes::<16>()).to_vec());
+        trailer.id = vec![id.clone(), id];
+    }
+    Ok(trailer.id[0].as_bytes())
+}
+
+fn renumber(p: Primitive, numbers: &HashMap<ObjNr, ObjNr>) -> Primitive {
+    match p {
+        Primitive::Reference(r) => match numbers.get(&r.id) {
+            Some(&id) => Primitive::Reference(PlainRef { id, gen: 0 }),
+            None => Primitive::Null
+        },
+        Primitive::Array(parts) => Primitive::Array(parts.into_iter().map(|p| renumber(p, numbers)).collect()),
+        Primitive::Dictionary(dict) => Primitive::Dictionary(renumber_dict(dict, numbers)),
+        Primitive::Stream(PdfStream { info, data }) => Primitive::Stream(PdfStream {
+            info: renumber_dict(info, numbers),
+            data
+        }),
+        p => p
+    }
+}
+fn renumber_dict(dict: Dictionary, numbers: &HashMap<ObjNr, ObjNr>) -> Dictionary {
+    let mut out = Dictionary::new();
+    for (k, v) in dict {
+        out.insert(k, renumber(v, numbers));
+    }
+    out
+}
+
 pub fn load_storage_and_trailer<B: Backend>(backend: B) -> Result<(Storage<B>, Dictionary)> {
     load_storage_and_trailer_password(backend, b"")
 }
@@ -190,10 +462,43 @@ pub fn load_storage_and_trailer<B: Backend>(backend: B) -> Result<(Storage<B>, D
 pub fn load_storage_and_trailer_password<B: Backend>(
     backend: B,
     password: &[u8],
+) -> Result<(Storage<B>, Dictionary)> {
+    load_storage_and_trailer_with(backend, |dict, id| Decoder::from_password(dict, id, password))
+}
+
+/// Like `load_storage_and_trailer_password`, for files encrypted with the public-key security handler.
+#[cfg(feature="pubsec")]
+pub fn load_storage_and_trailer_pubsec<B: Backend>(
+    backend: B,
+    key: &PubSecKey,
+) -> Result<(Storage<B>, Dictionary)> {
+    load_storage_and_trailer_with(backend, |dict, _| Decoder::from_pubsec(dict, key))
+}
+
+fn load_storage_and_trailer_with<B: Backend>(
+    backend: B,
+    decoder: impl FnOnce(&CryptDict, &[u8]) -> Result<Decoder>,
 ) -> Result<(Storage<B>, Dictionary)> {
     let start_offset = t!(backend.locate_start_offset());
-    let (refs, trailer) = t!(backend.read_xref_table_and_trailer(start_offset));
+    let (refs, trailer, last_xref) = match backend.read_xref_table_and_trailer(start_offset) {
+        Ok((refs, trailer)) => {
+            let xref_offset = t!(backend.locate_xref_offset());
+            let pos = start_offset + xref_offset;
+            let kind = match Lexer::new(&t!(backend.read(pos .. std::cmp::min(pos + 16, backend.len())))).next() {
+                Ok(word) if word.equals(b"xref") => XRefKind::Table,
+                _ => XRefKind::Stream
+            };
+            (refs, trailer, Some((xref_offset, kind)))
+        }
+        Err(e) => {
+            warn!("can't read xref table, scanning the file instead: {}", e);
+            let (refs, trailer) = t!(repair::build_xref_table(&backend, start_offset));
+            (refs, trailer, None)
+        }
+    };
     let mut storage = Storage::new(backend, refs, start_offset);
+    storage.repaired = last_xref.is_none();
+    storage.last_xref = last_xref;
 
     if let Some(crypt) = trailer.get("Encrypt") {
         let key = trailer
@@ -206,7 +511,7 @@ pub fn load_storage_and_trailer_password<B: Backend>(
             .as_string()?
             .as_bytes();
         let dict = CryptDict::from_primitive(crypt.clone(), &storage)?;
-        storage.decoder = Some(t!(Decoder::from_password(&dict, key, password)));
+        storage.decoder = Some(t!(decoder(&dict, key)));
         if let Primitive::Reference(reference) = crypt {
             storage.decoder.as_mut().unwrap().encrypt_indirect_object = Some(*reference);
         }
@@ -258,11 +563,29 @@ impl File<Vec<u8>> {
         Self::from_data_password(fs::read(path)?, password)
     }
 
+    /// Opens the file at `path`, encrypted for the recipient `key`, and uses Vec<u8> as backend.
+    #[cfg(feature="pubsec")]
+    pub fn open_pubsec(path: impl AsRef<Path>, key: &PubSecKey) -> Result<Self> {
+        Self::from_data_pubsec(fs::read(path)?, key)
+    }
+
+    /// Writes the original file followed by an incremental update containing all changes.
     pub fn save_to(&mut self, path: impl AsRef<Path>) -> Result<()> {
-        std::fs::write(path, self.storage.save(&mut self.trailer)?)?;
+        self.save_to_with(path, &SaveOptions::default())
+    }
+
+    /// Like [`save_to`](File::save_to), with additional options.
+    pub fn save_to_with(&mut self, path: impl AsRef<Path>, options: &SaveOptions) -> Result<()> {
+        std::fs::write(path, self.storage.save_with(&mut self.trailer, options)?)?;
         Ok(())
     }
 }
+impl File<LazyBackend<fs::File>> {
+    /// Opens the file at `path` and reads it on demand, see [`LazyBackend`].
+    pub fn open_lazy(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_data(LazyBackend::open(path)?)
+    }
+}
 impl<B: Backend> File<B> {
     pub fn from_data_password(backend: B, password: &[u8]) -> Result<Self> {
         Self::load_data(backend, password)
@@ -272,8 +595,18 @@ impl<B: Backend> File<B> {
         Self::from_data_password(backend, b"")
     }
 
+    #[cfg(feature="pubsec")]
+    pub fn from_data_pubsec(backend: B, key: &PubSecKey) -> Result<Self> {
+        let (storage, trailer) = load_storage_and_trailer_pubsec(backend, key)?;
+        Self::from_storage(storage, trailer)
+    }
+
     fn load_data(backend: B, password: &[u8]) -> Result<Self> {
         let (storage, trailer) = load_storage_and_trailer_password(backend, password)?;
+        Self::from_storage(storage, trailer)
+    }
+
+    fn from_storage(storage: Storage<B>, trailer: Dictionary) -> Result<Self> {
         let trailer = t!(Trailer::from_primitive(
             Primitive::Dictionary(trailer),
             &storage,
@@ -296,6 +629,38 @@ impl<B: Backend> File<B> {
         self.trailer.root.pages.page(self, n)
     }
 
+    /// Which password opened the file, or `None` if it is not encrypted.
+    pub fn password_type(&self) -> Option<PasswordType> {
+        self.storage.decoder.as_ref().map(|decoder| decoder.password_type())
+    }
+
+    /// What the reader of this file is allowed to do with it.
+    ///
+    /// Everything is permitted if the file is not encrypted or was opened with the owner password.
+    /// It is up to the application to enforce these.
+    pub fn permissions(&self) -> Permissions {
+        match (self.password_type(), &self.trailer.encrypt_dict) {
+            (Some(PasswordType::User), Some(dict)) => self.storage.decoder.as_ref()
+                .and_then(|decoder| decoder.permissions())
+                .unwrap_or_else(|| dict.permissions()),
+            _ => Permissions::all()
+        }
+    }
+
+    /// Writes only the objects that are still reachable from the trailer, as a single revision.
+    ///
+    /// See [`Storage::save_compacted`].
+    pub fn save_compacted(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.save_compacted_with(path, &SaveOptions::default())
+    }
+
+    /// Like [`save_compacted`](File::save_compacted), with additional options.
+    pub fn save_compacted_with(&mut self, path: impl AsRef<Path>, options: &SaveOptions) -> Result<()> {
+        let data = t!(self.storage.save_compacted(&mut self.trailer, options));
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
     pub fn update_catalog(&mut self, catalog: Catalog) -> Result<()> {
         self.trailer.root = self.create(catalog)?;
         Ok(())
@@ -2,15 +2,24 @@ use crate as pdf;
 use crate::object::*;
 use crate::primitive::*;
 use crate::error::*;
-use crate::encoding::Encoding;
+use crate::encoding::{Encoding, BaseEncoding, glyph_name_to_unicode};
+use crate::cmap::CMap;
+use crate::content::{Content, Matrix, Op};
 use std::collections::HashMap;
-use crate::parser::{Lexer, parse_with_lexer};
-use utf16_ext::Utf16ReadExt;
-use byteorder::BE;
-use std::convert::TryInto;
+
+mod program;
+mod truetype;
+mod cff;
+mod type1;
+mod afm;
+pub use self::program::{FontProgram, Glyph, PathOp};
+pub use self::truetype::TrueTypeFont;
+pub use self::cff::CffFont;
+pub use self::type1::Type1Font;
+pub use self::afm::FontMetrics;
 
 #[allow(non_upper_case_globals, dead_code)] 
-mod flags {
+pub(crate) mod flags {
     pub const FixedPitch: u32    = 1 << 0;
     pub const Serif: u32         = 1 << 1;
     pub const Symbolic: u32      = 1 << 2;
@@ -40,6 +49,9 @@ pub struct Font {
     pub data: Result<FontData>,
     
     encoding: Option<Encoding>,
+
+    /// the `/Encoding` of a composite font
+    cmap: Option<CMap>,
     
     to_unicode: Option<Stream>,
     
@@ -51,6 +63,7 @@ pub enum FontData {
     Type1(TFont),
     Type0(Type0Font),
     TrueType(TFont),
+    Type3(Type3Font),
     CIDFontType0(CIDFont),
     CIDFontType2(CIDFont, Option<Vec<u16>>),
     Other(Dictionary),
@@ -61,10 +74,32 @@ impl Object for Font {
     fn from_primitive(p: Primitive, resolve: &impl Resolve) -> Result<Self> {
         let mut dict = p.into_dictionary(resolve)?;
         dict.expect("Font", "Type", "Font", true)?;
-        let base_font = dict.require("Font", "BaseFont")?.into_name()?;
         let subtype = FontType::from_primitive(dict.require("Font", "Subtype")?, resolve)?;
+        // optional for Type3 fonts, which may have a /Name instead
+        let base_font = match (subtype, dict.remove("BaseFont")) {
+            (_, Some(p)) => p.into_name()?,
+            (FontType::Type3, None) => match dict.get("Name") {
+                Some(p) => p.clone().into_name()?,
+                None => String::new()
+            },
+            (_, None) => return Err(PdfError::MissingEntry { typ: "Font", field: "BaseFont".into() })
+        };
         
-        let encoding = dict.remove("Encoding").map(|p| Object::from_primitive(p, resolve)).transpose()?;
+        let (encoding, cmap) = match (subtype, dict.remove("Encoding")) {
+            (FontType::Type0, Some(p)) => {
+                // predefined CMaps also have a (meaningless) simple encoding, embedded ones don't
+                let encoding = match p {
+                    Primitive::Name(_) => Some(Encoding::from_primitive(p.clone(), resolve)?),
+                    _ => None
+                };
+                let cmap = CMap::from_primitive(p, resolve)
+                    .map_err(|e| warn!("can't read CMap of {}: {}", base_font, e))
+                    .ok();
+                (encoding, cmap)
+            }
+            (_, Some(p)) => (Some(Encoding::from_primitive(p, resolve)?), None),
+            (_, None) => (None, None)
+        };
 
         let to_unicode = match dict.remove("ToUnicode") {
             Some(p) => Some(Stream::from_primitive(p, resolve)?),
@@ -76,6 +111,7 @@ impl Object for Font {
                 FontType::Type0 => FontData::Type0(Type0Font::from_dict(dict, resolve)?),
                 FontType::Type1 => FontData::Type1(TFont::from_dict(dict, resolve)?),
                 FontType::TrueType => FontData::TrueType(TFont::from_dict(dict, resolve)?),
+                FontType::Type3 => FontData::Type3(Type3Font::from_dict(dict, resolve)?),
                 FontType::CIDFontType0 => FontData::CIDFontType0(CIDFont::from_dict(dict, resolve)?),
                 FontType::CIDFontType2 => {
                     let cid_map = match dict.remove("CIDToGIDMap") {
@@ -98,6 +134,7 @@ impl Object for Font {
             name: base_font,
             data,
             encoding,
+            cmap,
             to_unicode,
             _other
         })
@@ -111,6 +148,10 @@ pub struct Widths {
     first_char: usize
 }
 impl Widths {
+    /// Width of glyphs that have none in the array.
+    pub fn default_width(&self) -> f32 {
+        self.default
+    }
     pub fn get(&self, cid: usize) -> f32 {
         if cid < self.first_char {
             self.default
@@ -166,6 +207,43 @@ impl Widths {
         self.values[cid - self.first_char] = width;
     }
 }
+/// The vertical metrics of a CID font, for the vertical writing mode. In 1/1000 em.
+#[derive(Debug, Clone)]
+pub struct VerticalMetrics {
+    /// `(v_y, w1_y)` of CIDs without metrics of their own
+    default: (f32, f32),
+    values: HashMap<usize, VerticalMetric>,
+}
+/// How a glyph is placed in vertical writing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerticalMetric {
+    /// Vertical displacement `w1_y`, negative because vertical text runs downward.
+    pub advance: f32,
+    /// The position vector `(v_x, v_y)` from the horizontal origin of the glyph to its vertical
+    /// origin, which is the point placed at the current text position.
+    pub position: (f32, f32),
+}
+impl Default for VerticalMetrics {
+    fn default() -> Self {
+        VerticalMetrics {
+            default: (880., -1000.),
+            values: HashMap::new(),
+        }
+    }
+}
+impl VerticalMetrics {
+    /// The metrics of `cid`, given its horizontal width `width`, which the position vector
+    /// defaults to half of.
+    pub fn get(&self, cid: usize, width: f32) -> VerticalMetric {
+        match self.values.get(&cid) {
+            Some(&metric) => metric,
+            None => VerticalMetric {
+                advance: self.default.1,
+                position: (width / 2., self.default.0),
+            }
+        }
+    }
+}
 impl Font {
     pub fn embedded_data(&self) -> Option<Result<&[u8]>> {
         match self.data.as_ref().ok()? {
@@ -188,6 +266,112 @@ impl Font {
     pub fn encoding(&self) -> Option<&Encoding> {
         self.encoding.as_ref()
     }
+    /// The CMap of a composite font, which maps its codes to CIDs.
+    pub fn cmap(&self) -> Option<&CMap> {
+        self.cmap.as_ref()
+    }
+    /// The encoding of a simple font, with the built-in encoding of the font in place of a
+    /// missing `/Encoding` or `/BaseEncoding`.
+    ///
+    /// `None` for composite fonts and for symbolic fonts that only have the encoding of their
+    /// font program.
+    pub fn simple_encoding(&self) -> Option<Encoding> {
+        if matches!(self.data, Ok(FontData::Type0(_))) || self.is_cid() {
+            return None;
+        }
+        // without the subset tag
+        let name = self.name.rsplit('+').next().unwrap();
+        let symbolic = self.font_descriptor().is_some_and(|d| d.flags & flags::Symbolic != 0);
+        let builtin = if matches!(self.data, Ok(FontData::Type3(_))) {
+            // glyphs of Type3 fonts only have the names given in /Differences
+            None
+        } else if name.starts_with("Symbol") {
+            Some(BaseEncoding::SymbolEncoding)
+        } else if name.starts_with("ZapfDingbats") || name.starts_with("Dingbats") {
+            Some(BaseEncoding::ZapfDingbatsEncoding)
+        } else if symbolic {
+            None
+        } else {
+            Some(BaseEncoding::StandardEncoding)
+        };
+        match self.encoding {
+            Some(Encoding { base: BaseEncoding::None, ref differences }) => Some(Encoding {
+                base: builtin.unwrap_or(BaseEncoding::None),
+                differences: differences.clone()
+            }),
+            Some(ref encoding) => Some(encoding.clone()),
+            None => builtin.map(|base| Encoding { base, differences: HashMap::new() })
+        }
+    }
+    /// Parse the embedded font program.
+    pub fn program(&self) -> Option<Result<FontProgram>> {
+        self.embedded_data().map(|data| data.and_then(FontProgram::parse))
+    }
+    /// The glyph of `program` (the font's program) that shows the character code `code`,
+    /// which is `len` bytes long (see [`CMap::codes`]). `None` if there is none, or the font's
+    /// predefined CMap isn't available.
+    pub fn gid(&self, program: &FontProgram, code: u32, len: usize) -> Option<u32> {
+        let (cid, descendant) = match self.data.as_ref().ok()? {
+            FontData::Type0(ref t0) => {
+                let cid = match self.cmap {
+                    Some(ref cmap) => cmap.cid(code, len).ok()??,
+                    None => code
+                };
+                (cid, &**t0.descendant_fonts.first()?)
+            }
+            FontData::CIDFontType0(_) | FontData::CIDFontType2(..) => (code, self),
+            _ => return self.simple_gid(program, code as u8)
+        };
+        match descendant.data {
+            Ok(FontData::CIDFontType2(_, Some(ref map))) => map.get(cid as usize).map(|&gid| gid as u32),
+            _ => program.gid_for_cid(cid)
+        }
+    }
+    fn simple_gid(&self, program: &FontProgram, code: u8) -> Option<u32> {
+        let encoding = self.encoding.as_ref();
+        let name = match encoding.and_then(|e| e.differences.get(&(code as u32))) {
+            Some(name) => Some(name.as_str()),
+            None => match encoding {
+                Some(e) if e.base != BaseEncoding::None => e.base.glyph_name(code),
+                _ => program.builtin_glyph_name(code).or_else(|| self.simple_encoding()?.base.glyph_name(code))
+            }
+        };
+        let tt = match program.as_truetype() {
+            Some(tt) => tt,
+            None => return name.and_then(|name| program.gid_for_name(name))
+        };
+
+        // TrueType fonts are looked up by Unicode or Mac Roman code for nonsymbolic fonts,
+        // and by the code itself for symbolic ones
+        let symbolic = self.font_descriptor().is_some_and(|d| d.flags & flags::Symbolic != 0);
+        if let Some(name) = name.filter(|_| !symbolic || encoding.is_some()) {
+            let unicode = glyph_name_to_unicode(name).and_then(|s| s.chars().next());
+            if let Some(gid) = unicode.and_then(|c| tt.cmap_lookup(3, 1, c as u32)) {
+                return Some(gid);
+            }
+            if let Some(gid) = BaseEncoding::MacRomanEncoding.code(name).and_then(|c| tt.cmap_lookup(1, 0, c as u32)) {
+                return Some(gid);
+            }
+        }
+        [0, 0xF000, 0xF100, 0xF200].iter()
+            .find_map(|&prefix| tt.cmap_lookup(3, 0, prefix + code as u32))
+            .or_else(|| tt.cmap_lookup(1, 0, code as u32))
+            .or_else(|| if tt.cmap_subtables().next().is_none() { Some(code as u32) } else { None })
+    }
+    /// The glyph of `program` for the character code, see [`Font::gid`].
+    pub fn glyph(&self, program: &FontProgram, code: u32, len: usize) -> Option<Glyph> {
+        program.glyph(self.gid(program, code, len)?)
+    }
+    /// The font descriptor, for composite fonts the one of the descendant font.
+    pub fn font_descriptor(&self) -> Option<&FontDescriptor> {
+        match self.data.as_ref().ok()? {
+            FontData::Type0(ref t) => t.descendant_fonts.first().and_then(|f| f.font_descriptor()),
+            FontData::CIDFontType0(ref c) | FontData::CIDFontType2(ref c, _) => Some(&c.font_descriptor),
+            FontData::Type1(ref t) | FontData::TrueType(ref t) => t.font_descriptor.as_ref(),
+            FontData::Type3(ref t) => t.font_descriptor.as_ref(),
+            _ => None
+        }
+    }
     pub fn info(&self) -> Option<&TFont> {
         match self.data.as_ref().ok()? {
             FontData::Type1(ref info) => Some(info),
@@ -197,7 +381,7 @@ impl Font {
     }
     pub fn widths(&self, resolve: &impl Resolve) -> Result<Option<Widths>> {
         match self.data {
-            Ok(FontData::Type0(ref t0)) => t0.descendant_fonts[0].widths(resolve),
+            Ok(FontData::Type0(ref t0)) => t!(t0.descendant()).widths(resolve),
             Ok(FontData::Type1(ref info)) | Ok(FontData::TrueType(ref info)) => {
                 match info {
                     &TFont { first_char: Some(first), ref widths, .. } => Ok(Some(Widths {
@@ -205,9 +389,21 @@ impl Font {
                         first_char: first as usize,
                         values: widths.clone()
                     })),
-                    _ => Ok(None)
+                    _ => Ok(self.standard_metrics().map(|m| self.standard_widths(m)))
                 }
             },
+            // in glyph space, scaled to the usual 1/1000 em
+            Ok(FontData::Type3(ref t3)) => match t3.first_char {
+                Some(first) => {
+                    let scale = t3.font_matrix().a * 1000.;
+                    Ok(Some(Widths {
+                        default: 0.0,
+                        first_char: first as usize,
+                        values: t3.widths.iter().map(|w| w * scale).collect()
+                    }))
+                }
+                None => Ok(None)
+            },
             Ok(FontData::CIDFontType0(ref cid)) | Ok(FontData::CIDFontType2(ref cid, _)) => {
                 let mut widths = Widths::new(cid.default_width);
                 let mut iter = cid.widths.iter();
@@ -245,8 +441,115 @@ impl Font {
             _ => Ok(None)
         }
     }
+    /// The glyph procedure of a Type3 font for `code`, and its operations.
+    pub fn type3_glyph(&self, code: u8, resolve: &impl Resolve) -> Option<Result<&[Op]>> {
+        let t3 = match self.data {
+            Ok(FontData::Type3(ref t3)) => t3,
+            _ => return None
+        };
+        let name = self.encoding.as_ref()?.glyph_name(code)?;
+        t3.glyph(name, resolve)
+    }
+    /// Whether the CMap of this font selects the vertical writing mode.
+    pub fn is_vertical(&self) -> bool {
+        self.cmap.as_ref().is_some_and(|cmap| cmap.is_vertical())
+    }
+    /// The vertical metrics (`/W2` and `/DW2`) of a CID font, or of the descendant of a Type0 font.
+    pub fn vertical_metrics(&self, resolve: &impl Resolve) -> Result<Option<VerticalMetrics>> {
+        let cid = match self.data {
+            Ok(FontData::Type0(ref t0)) => return t!(t0.descendant()).vertical_metrics(resolve),
+            Ok(FontData::CIDFontType0(ref cid)) | Ok(FontData::CIDFontType2(ref cid, _)) => cid,
+            _ => return Ok(None)
+        };
+        let mut metrics = VerticalMetrics::default();
+        if let Some(ref dw2) = cid.default_vertical {
+            match dw2[..] {
+                [v_y, w1_y] => metrics.default = (v_y, w1_y),
+                _ => bail!("invalid DW2 array: {:?}", dw2)
+            }
+        }
+        let numbers = |p: &Primitive| -> Result<Vec<f32>> {
+            match *p {
+                Primitive::Array(ref array) => array.iter().map(|n| n.as_number()).collect(),
+                Primitive::Reference(r) => match resolve.resolve(r)? {
+                    Primitive::Array(array) => array.iter().map(|n| n.as_number()).collect(),
+                    p => bail!("unexpected primitive in W2 array: {:?}", p)
+                },
+                ref p => bail!("unexpected primitive in W2 array: {:?}", p)
+            }
+        };
+        let mut iter = cid.vertical_widths.iter();
+        while let Some(p) = iter.next() {
+            let c1 = p.as_integer()?;
+            if c1 < 0 {
+                bail!("invalid CID {} in W2 array", c1);
+            }
+            match iter.next() {
+                // c [w1_y v_x v_y ...]
+                Some(p @ &Primitive::Array(_)) | Some(p @ &Primitive::Reference(_)) => {
+                    for (i, m) in numbers(p)?.chunks_exact(3).enumerate() {
+                        metrics.values.insert(c1 as usize + i, VerticalMetric { advance: m[0], position: (m[1], m[2]) });
+                    }
+                }
+                // c_first c_last w1_y v_x v_y
+                Some(&Primitive::Integer(c2)) => {
+                    // CIDs are 16 bit
+                    if c2 < c1 || c2 - c1 > 0xFFFF {
+                        bail!("invalid CID range {} {} in W2 array", c1, c2);
+                    }
+                    let mut next = || try_opt!(iter.next()).as_number();
+                    let metric = VerticalMetric { advance: next()?, position: (next()?, next()?) };
+                    for c in c1 as usize ..= c2 as usize {
+                        metrics.values.insert(c, metric);
+                    }
+                }
+                p => bail!("unexpected primitive in W2 array: {:?}", p)
+            }
+        }
+        Ok(Some(metrics))
+    }
+    /// The bundled metrics of the standard font this simple font refers to, see
+    /// [`FontMetrics::standard`].
+    pub fn standard_metrics(&self) -> Option<&'static FontMetrics> {
+        match self.data {
+            Ok(FontData::Type1(_)) | Ok(FontData::TrueType(_)) => FontMetrics::standard(&self.name),
+            _ => None
+        }
+    }
+    fn standard_widths(&self, metrics: &FontMetrics) -> Widths {
+        let encoding = self.simple_encoding();
+        let mut widths = Widths::new(0.0);
+        for code in 0 ..= 255 {
+            let name = encoding.as_ref().and_then(|e| e.glyph_name(code)).or_else(|| metrics.glyph_name(code));
+            if let Some(width) = name.and_then(|name| metrics.width(name)) {
+                widths.set(code as usize, width);
+            }
+        }
+        widths
+    }
+    /// The text of the codes of this font, from its `/ToUnicode` CMap.
+    ///
+    /// Simple fonts fall back to the glyph names of their [encoding](Font::simple_encoding) for
+    /// codes the CMap leaves out, or for all codes if there is no CMap or it can't be read.
+    /// `None` if neither gives any text.
     pub fn to_unicode(&self) -> Option<Result<ToUnicodeMap>> {
-        self.to_unicode.as_ref().map(|s| s.data().and_then(parse_cmap))
+        let cmap = self.to_unicode.as_ref().map(|s| s.data().and_then(CMap::parse).map(CMap::into_unicode_map));
+        let encoding = match self.simple_encoding() {
+            Some(encoding) => encoding,
+            None => return cmap.map(|r| r.map(|inner| ToUnicodeMap { inner }))
+        };
+        let mut inner: HashMap<u32, String> = (0 ..= 255)
+            .filter_map(|code| Some((code as u32, encoding.decode(code)?)))
+            .collect();
+        match cmap {
+            Some(Ok(map)) => inner.extend(map),
+            Some(Err(e)) => warn!("can't read ToUnicode of {}: {}", self.name, e),
+            None => {}
+        }
+        if inner.is_empty() {
+            return None;
+        }
+        Some(Ok(ToUnicodeMap { inner }))
     }
 }
 #[derive(Object, Debug)]
@@ -269,6 +572,54 @@ pub struct TFont {
     pub font_descriptor: Option<FontDescriptor>
 }
 
+/// A font whose glyphs are content streams.
+#[derive(Object, Debug)]
+pub struct Type3Font {
+    #[pdf(key="FontBBox")]
+    pub font_bbox: Rect,
+
+    /// see [`Type3Font::font_matrix`]
+    #[pdf(key="FontMatrix")]
+    pub font_matrix: Vec<f32>,
+
+    /// The glyph procedures by glyph name.
+    #[pdf(key="CharProcs")]
+    pub char_procs: HashMap<String, Content>,
+
+    /// Resources of the glyph procedures. Without them, the resources of the page are used.
+    #[pdf(key="Resources")]
+    pub resources: Option<MaybeRef<Resources>>,
+
+    #[pdf(key="FirstChar")]
+    pub first_char: Option<i32>,
+
+    #[pdf(key="LastChar")]
+    pub last_char: Option<i32>,
+
+    /// In glyph space.
+    #[pdf(key="Widths")]
+    pub widths: Vec<f32>,
+
+    #[pdf(key="FontDescriptor")]
+    pub font_descriptor: Option<FontDescriptor>
+}
+impl Type3Font {
+    /// Maps glyph space to text space.
+    pub fn font_matrix(&self) -> Matrix {
+        match self.font_matrix[..] {
+            [a, b, c, d, e, f] => Matrix { a, b, c, d, e, f },
+            _ => {
+                warn!("invalid FontMatrix {:?}", self.font_matrix);
+                Matrix { a: 0.001, b: 0., c: 0., d: 0.001, e: 0., f: 0. }
+            }
+        }
+    }
+    /// The operations of the glyph `name`.
+    pub fn glyph(&self, name: &str, resolve: &impl Resolve) -> Option<Result<&[Op]>> {
+        self.char_procs.get(name).map(|proc| proc.operations(resolve))
+    }
+}
+
 #[derive(Object, Debug)]
 pub struct Type0Font {
     #[pdf(key="DescendantFonts")]
@@ -277,6 +628,14 @@ pub struct Type0Font {
     #[pdf(key="ToUnicode")]
     to_unicode: Option<Stream>,
 }
+impl Type0Font {
+    fn descendant(&self) -> Result<&Font> {
+        match self.descendant_fonts.first() {
+            Some(font) => Ok(font),
+            None => bail!("Type0 font without descendant font")
+        }
+    }
+}
 
 #[derive(Object, Debug)]
 pub struct CIDFont {
@@ -292,6 +651,13 @@ pub struct CIDFont {
     #[pdf(key="W")]
     pub widths: Vec<Primitive>,
 
+    /// `[v_y w1_y]` for CIDs that are not in `/W2`
+    #[pdf(key="DW2")]
+    default_vertical: Option<Vec<f32>>,
+
+    #[pdf(key="W2")]
+    pub vertical_widths: Vec<Primitive>,
+
     #[pdf(other)]
     _other: Dictionary
 }
@@ -406,92 +772,24 @@ pub enum FontStretch {
 #[derive(Clone, Debug)]
 pub struct ToUnicodeMap {
     // todo: reduce allocations
-    inner: HashMap<u16, String>
+    inner: HashMap<u32, String>
 }
 impl ToUnicodeMap {
     /// Create a new ToUnicodeMap from key/value pairs.
     /// 
     /// subject to change
     pub fn create(iter: impl Iterator<Item=(u16, String)>) -> Self {
-        ToUnicodeMap { inner: iter.collect() }
+        ToUnicodeMap { inner: iter.map(|(code, s)| (code as u32, s)).collect() }
     }
     pub fn get(&self, gid: u16) -> Option<&str> {
-        self.inner.get(&gid).map(|s| s.as_str())
+        self.get_code(gid as u32)
     }
-}
-
-fn utf16be_to_string(mut data: &[u8]) -> Result<String> {
-    (&mut data)
-        .utf16_chars::<BE>()
-        .map(|r| r.map_err(|_| PdfError::Utf16Decode))
-        .collect()
-}
-fn parse_cid(s: &PdfString) -> Result<u16> {
-    let b = s.as_bytes();
-    match b.len() {
-        2 => Ok(u16::from_be_bytes(b.try_into().unwrap())),
-        1 => Ok(b[0] as u16),
-        _ => Err(PdfError::CidDecode),
+    /// The text for a code of any length.
+    pub fn get_code(&self, code: u32) -> Option<&str> {
+        self.inner.get(&code).map(|s| s.as_str())
     }
-}
-fn parse_cmap(data: &[u8]) -> Result<ToUnicodeMap> {
-    let mut lexer = Lexer::new(data);
-    let mut map = HashMap::new();
-    while let Ok(substr) = lexer.next() {
-        match substr.as_slice() {
-            b"beginbfchar" => loop {
-                let a = parse_with_lexer(&mut lexer, &NoResolve);
-                let b = parse_with_lexer(&mut lexer, &NoResolve);
-                match (a, b) {
-                    (Ok(Primitive::String(cid_data)), Ok(Primitive::String(unicode_data))) => {
-                        let cid = parse_cid(&cid_data)?;
-                        let unicode = utf16be_to_string(unicode_data.as_bytes())?;
-                        map.insert(cid, unicode);
-                    }
-                    _ => break,
-                }
-            },
-            b"beginbfrange" => loop {
-                let a = parse_with_lexer(&mut lexer, &NoResolve);
-                let b = parse_with_lexer(&mut lexer, &NoResolve);
-                let c = parse_with_lexer(&mut lexer, &NoResolve);
-                match (a, b, c) {
-                    (
-                        Ok(Primitive::String(cid_start_data)),
-                        Ok(Primitive::String(cid_end_data)),
-                        Ok(Primitive::String(unicode_data)),
-                    ) if unicode_data.data.len() > 0 => {
-                        let cid_start = parse_cid(&cid_start_data)?;
-                        let cid_end = parse_cid(&cid_end_data)?;
-                        let mut unicode_data = unicode_data.into_bytes();
-
-                        for cid in cid_start..=cid_end {
-                            let unicode = utf16be_to_string(&unicode_data)?;
-                            map.insert(cid, unicode);
-                            *unicode_data.last_mut().unwrap() += 1;
-                        }
-                    }
-                    (
-                        Ok(Primitive::String(cid_start_data)),
-                        Ok(Primitive::String(cid_end_data)),
-                        Ok(Primitive::Array(unicode_data_arr)),
-                    ) => {
-                        let cid_start = parse_cid(&cid_start_data)?;
-                        let cid_end = parse_cid(&cid_end_data)?;
-
-                        for (cid, unicode_data) in (cid_start..=cid_end).zip(unicode_data_arr) {
-                            let unicode =
-                                utf16be_to_string(&unicode_data.as_string()?.as_bytes())?;
-                            map.insert(cid, unicode);
-                        }
-                    }
-                    _ => break,
-                }
-            },
-            b"endcmap" => break,
-            _ => {}
-        }
+    /// The codes and their text, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item=(u32, &str)> {
+        self.inner.iter().map(|(&code, s)| (code, s.as_str()))
     }
-
-    Ok(ToUnicodeMap { inner: map })
-}
\ No newline at end of file
+}
@@ -0,0 +1,273 @@
+//! Adobe Font Metrics
+//!
+//! With the `standard-fonts` feature, the metrics of the 14 standard fonts are bundled and used
+//! for fonts that come without `/Widths`, see [`FontMetrics::standard`].
+
+use std::collections::HashMap;
+
+use crate::error::Result;
+use crate::object::Rect;
+
+/// The metrics of a font, as read from an AFM file. All values are in units of 1/1000 em.
+#[derive(Debug, Clone)]
+pub struct FontMetrics {
+    pub name: String,
+    pub bbox: Rect,
+    pub ascent: f32,
+    pub descent: f32,
+    pub cap_height: Option<f32>,
+    pub x_height: Option<f32>,
+    pub italic_angle: f32,
+    pub fixed_pitch: bool,
+    widths: HashMap<String, f32>,
+    /// glyph names of the built-in encoding
+    codes: HashMap<u8, String>,
+    /// left glyph → right glyph → adjustment
+    kerning: HashMap<String, HashMap<String, f32>>,
+}
+
+impl FontMetrics {
+    pub fn parse(text: &str) -> Result<FontMetrics> {
+        let mut name = None;
+        let mut bbox = None;
+        let (mut ascent, mut descent) = (None, None);
+        let (mut cap_height, mut x_height) = (None, None);
+        let mut italic_angle = 0.;
+        let mut fixed_pitch = false;
+        let mut widths = HashMap::new();
+        let mut codes = HashMap::new();
+        let mut kerning: HashMap<String, HashMap<String, f32>> = HashMap::new();
+
+        let number = |s: Option<&str>| -> Result<f32> {
+            match s.map(str::parse) {
+                Some(Ok(n)) => Ok(n),
+                _ => bail!("expected a number in AFM file, found {:?}", s)
+            }
+        };
+
+        let mut in_chars = false;
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let key = match words.next() {
+                Some(key) => key,
+                None => continue
+            };
+            if in_chars {
+                if key == "EndCharMetrics" {
+                    in_chars = false;
+                    continue;
+                }
+                // C 65 ; WX 667 ; N A ; B ...
+                let (mut code, mut width, mut glyph) = (None, None, None);
+                for item in line.split(';') {
+                    let mut words = item.split_whitespace();
+                    match words.next() {
+                        Some("C") => code = Some(number(words.next())?),
+                        Some("WX") => width = Some(number(words.next())?),
+                        Some("N") => glyph = words.next(),
+                        _ => {}
+                    }
+                }
+                let glyph = match glyph {
+                    Some(glyph) => glyph.to_owned(),
+                    None => bail!("glyph without name in AFM file: {}", line)
+                };
+                if let Some(code) = code.filter(|&c| (0. ..= 255.).contains(&c)) {
+                    codes.insert(code as u8, glyph.clone());
+                }
+                widths.insert(glyph, width.unwrap_or(0.));
+                continue;
+            }
+            match key {
+                "FontName" => name = words.next().map(String::from),
+                "FontBBox" => bbox = Some(Rect {
+                    left: number(words.next())?,
+                    bottom: number(words.next())?,
+                    right: number(words.next())?,
+                    top: number(words.next())?,
+                }),
+                "Ascender" => ascent = Some(number(words.next())?),
+                "Descender" => descent = Some(number(words.next())?),
+                "CapHeight" => cap_height = Some(number(words.next())?),
+                "XHeight" => x_height = Some(number(words.next())?),
+                "ItalicAngle" => italic_angle = number(words.next())?,
+                "IsFixedPitch" => fixed_pitch = words.next() == Some("true"),
+                "StartCharMetrics" => in_chars = true,
+                "KPX" => {
+                    let (left, right) = match (words.next(), words.next()) {
+                        (Some(l), Some(r)) => (l, r),
+                        _ => bail!("incomplete kerning pair in AFM file: {}", line)
+                    };
+                    let value = number(words.next())?;
+                    kerning.entry(left.to_owned()).or_default().insert(right.to_owned(), value);
+                }
+                _ => {}
+            }
+        }
+
+        let name = try_opt!(name);
+        let bbox = try_opt!(bbox);
+        Ok(FontMetrics {
+            name,
+            ascent: ascent.unwrap_or(bbox.top),
+            descent: descent.unwrap_or(bbox.bottom),
+            bbox,
+            cap_height,
+            x_height,
+            italic_angle,
+            fixed_pitch,
+            widths,
+            codes,
+            kerning,
+        })
+    }
+
+    /// The advance of the glyph called `name`.
+    pub fn width(&self, name: &str) -> Option<f32> {
+        self.widths.get(name).cloned()
+    }
+
+    /// The glyph name for `code` in the built-in encoding of the font.
+    pub fn glyph_name(&self, code: u8) -> Option<&str> {
+        self.codes.get(&code).map(|s| s.as_str())
+    }
+
+    /// The adjustment of the advance of `left` when followed by `right`, usually negative.
+    pub fn kerning(&self, left: &str, right: &str) -> f32 {
+        self.kerning.get(left).and_then(|pairs| pairs.get(right)).cloned().unwrap_or(0.)
+    }
+}
+
+#[cfg(feature = "standard-fonts")]
+mod standard {
+    use super::FontMetrics;
+    use once_cell::sync::Lazy;
+
+    static FONTS: Lazy<Vec<FontMetrics>> = Lazy::new(|| {
+        [
+            include_str!("afm/Courier.afm"),
+            include_str!("afm/Courier-Bold.afm"),
+            include_str!("afm/Courier-Oblique.afm"),
+            include_str!("afm/Courier-BoldOblique.afm"),
+            include_str!("afm/Helvetica.afm"),
+            include_str!("afm/Helvetica-Bold.afm"),
+            include_str!("afm/Helvetica-Oblique.afm"),
+            include_str!("afm/Helvetica-BoldOblique.afm"),
+            include_str!("afm/Times-Roman.afm"),
+            include_str!("afm/Times-Bold.afm"),
+            include_str!("afm/Times-Italic.afm"),
+            include_str!("afm/Times-BoldItalic.afm"),
+            include_str!("afm/Symbol.afm"),
+            include_str!("afm/ZapfDingbats.afm"),
+        ].iter().map(|s| FontMetrics::parse(s).expect("invalid bundled AFM file")).collect()
+    });
+
+    /// Maps a base font name to one of the standard 14, also accepting the usual alternative
+    /// names like `Arial,Bold` or `TimesNewRomanPS-ItalicMT`.
+    fn standard_name(name: &str) -> Option<&'static str> {
+        // without the subset tag
+        let name = name.rsplit('+').next().unwrap();
+        let name: String = name.chars().filter(|&c| c != ' ').collect();
+        let (family, style) = match name.find([',', '-']) {
+            Some(i) => (&name[.. i], &name[i + 1 ..]),
+            None => (&name[..], "")
+        };
+        let family = family.trim_end_matches("MT").trim_end_matches("PS");
+        let style = style.trim_end_matches("MT");
+        let variant = match style {
+            "" | "Roman" | "Regular" => 0,
+            "Bold" => 1,
+            "Italic" | "Oblique" => 2,
+            "BoldItalic" | "BoldOblique" => 3,
+            _ => return None
+        };
+        let names = match family {
+            "Courier" | "CourierNew" => ["Courier", "Courier-Bold", "Courier-Oblique", "Courier-BoldOblique"],
+            "Helvetica" | "Arial" => ["Helvetica", "Helvetica-Bold", "Helvetica-Oblique", "Helvetica-BoldOblique"],
+            "Times" | "TimesNewRoman" => ["Times-Roman", "Times-Bold", "Times-Italic", "Times-BoldItalic"],
+            "Symbol" if variant == 0 => return Some("Symbol"),
+            "ZapfDingbats" | "Dingbats" if variant == 0 => return Some("ZapfDingbats"),
+            _ => return None
+        };
+        Some(names[variant])
+    }
+
+    impl FontMetrics {
+        /// The bundled metrics of the standard font `name`.
+        ///
+        /// Always `None` without the `standard-fonts` feature.
+        pub fn standard(name: &str) -> Option<&'static FontMetrics> {
+            let name = standard_name(name)?;
+            FONTS.iter().find(|m| m.name == name)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn names() {
+            assert_eq!(standard_name("Helvetica"), Some("Helvetica"));
+            assert_eq!(standard_name("ABCDEF+Arial,BoldItalic"), Some("Helvetica-BoldOblique"));
+            assert_eq!(standard_name("TimesNewRomanPS-ItalicMT"), Some("Times-Italic"));
+            assert_eq!(standard_name("Times-Roman"), Some("Times-Roman"));
+            assert_eq!(standard_name("Helvetica-Narrow"), None);
+            assert_eq!(standard_name("Symbol,Bold"), None);
+        }
+
+        #[test]
+        fn bundled() {
+            assert_eq!(FONTS.len(), 14);
+            let helvetica = FontMetrics::standard("Helvetica").unwrap();
+            assert_eq!(helvetica.width("A"), Some(667.));
+            assert_eq!(helvetica.width("Aacute"), Some(667.));
+            assert_eq!(helvetica.kerning("A", "V"), -70.);
+            assert_eq!(FontMetrics::standard("Courier-Bold").unwrap().width("W"), Some(600.));
+            let symbol = FontMetrics::standard("Symbol").unwrap();
+            assert_eq!(symbol.glyph_name(0x61), Some("alpha"));
+            assert_eq!(symbol.ascent, symbol.bbox.top);
+        }
+
+        // TODO: the bundled files only have the WinAnsi glyphs and a part of the kerning pairs,
+        // they have to be replaced by Adobe's unmodified Core14 AFM files.
+        #[test]
+        #[ignore = "the bundled AFM files are not Adobe's complete Core14 metrics yet"]
+        fn core14() {
+            let helvetica = FontMetrics::standard("Helvetica").unwrap();
+            assert!(helvetica.width("Lcaron").is_some());
+            assert!(helvetica.kerning("Aogonek", "V") < 0.);
+            assert!(FontMetrics::standard("Times-Roman").unwrap().width("scommaaccent").is_some());
+        }
+    }
+}
+
+#[cfg(not(feature = "standard-fonts"))]
+impl FontMetrics {
+    /// The bundled metrics of the standard font `name`.
+    ///
+    /// Always `None` without the `standard-fonts` feature.
+    pub fn standard(_name: &str) -> Option<&'static FontMetrics> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let afm = "StartFontMetrics 4.1\nComment Test metrics\nFontName Test\nNotice No notice.\nFontBBox -10 -200 1000 900\nAscender 700\n\
+            StartCharMetrics 2\nC 32 ; WX 250 ; N space ;\nC -1 ; WX 500 ; N a ; B 0 0 400 450 ;\n\
+            EndCharMetrics\nStartKernData\nStartKernPairs 1\nKPX a space -20\nEndKernPairs\n\
+            EndKernData\nEndFontMetrics\n";
+        let metrics = FontMetrics::parse(afm).unwrap();
+        assert_eq!(metrics.name, "Test");
+        assert_eq!((metrics.ascent, metrics.descent), (700., -200.));
+        assert_eq!(metrics.glyph_name(32), Some("space"));
+        assert_eq!(metrics.width("a"), Some(500.));
+        assert_eq!(metrics.kerning("a", "space"), -20.);
+        assert_eq!(metrics.kerning("space", "a"), 0.);
+    }
+}
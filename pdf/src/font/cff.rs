@@ -0,0 +1,893 @@
+//! Compact Font Format with Type 2 charstrings
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::content::{Matrix, Point};
+use crate::encoding::{BaseEncoding, Encoding};
+use crate::error::{PdfError, Result};
+
+use super::program::{Glyph, Outline, read_u8, read_u16, read_u32};
+
+mod strings;
+use self::strings::STANDARD_STRINGS;
+
+/// Nesting limit for subroutine calls, from the Type 2 charstring spec.
+const MAX_SUBR_DEPTH: usize = 10;
+
+/// Top DICT and Private DICT operators
+mod op {
+    pub const CHARSET: u16 = 15;
+    pub const ENCODING: u16 = 16;
+    pub const CHAR_STRINGS: u16 = 17;
+    pub const PRIVATE: u16 = 18;
+    pub const SUBRS: u16 = 19;
+    pub const DEFAULT_WIDTH_X: u16 = 20;
+    pub const NOMINAL_WIDTH_X: u16 = 21;
+    pub const FONT_MATRIX: u16 = 1207;
+    pub const ROS: u16 = 1230;
+    pub const FD_ARRAY: u16 = 1236;
+    pub const FD_SELECT: u16 = 1237;
+}
+
+#[derive(Debug, Default)]
+struct Private {
+    subrs: Vec<Range<usize>>,
+    default_width: f32,
+    nominal_width: f32,
+}
+
+#[derive(Debug)]
+enum CffEncoding {
+    Standard,
+    Expert,
+    /// code and glyph
+    Custom(Vec<(u8, u32)>),
+}
+
+#[derive(Debug)]
+pub struct CffFont {
+    data: Vec<u8>,
+    strings: Vec<Range<usize>>,
+    global_subrs: Vec<Range<usize>>,
+    char_strings: Vec<Range<usize>>,
+    /// the Private DICT, or one per Font DICT of a CID-keyed font
+    privates: Vec<Private>,
+    /// the Font DICT of each glyph of a CID-keyed font
+    fd_select: Vec<u8>,
+    /// SID, or CID for CID-keyed fonts, of each glyph
+    charset: Vec<u16>,
+    cid_keyed: bool,
+    /// glyph by name or by CID
+    names: HashMap<String, u32>,
+    cids: HashMap<u16, u32>,
+    encoding: CffEncoding,
+    font_matrix: Matrix,
+}
+
+type Dict = Vec<(u16, Vec<f64>)>;
+
+fn get(dict: &Dict, key: u16) -> Option<&[f64]> {
+    dict.iter().find(|(op, _)| *op == key).map(|(_, operands)| operands.as_slice())
+}
+fn get_usize(dict: &Dict, key: u16) -> Option<usize> {
+    get(dict, key).and_then(|v| v.first()).map(|&v| v as usize)
+}
+
+/// The ranges of the objects in the INDEX at `pos`, and the end of the INDEX.
+fn index(data: &[u8], pos: usize) -> Result<(Vec<Range<usize>>, usize)> {
+    let count = t!(read_u16(data, pos)) as usize;
+    if count == 0 {
+        return Ok((vec![], pos + 2));
+    }
+    let off_size = t!(read_u8(data, pos + 2)) as usize;
+    if !(1 ..= 4).contains(&off_size) {
+        bail!("invalid offset size {}", off_size);
+    }
+    let offset = |i: usize| -> Result<usize> {
+        let start = pos + 3 + i * off_size;
+        match data.get(start .. start + off_size) {
+            Some(b) => Ok(b.iter().fold(0, |v, &b| v << 8 | b as usize)),
+            None => Err(PdfError::EOF)
+        }
+    };
+    // offsets are relative to the byte before the object data
+    let base = pos + 3 + (count + 1) * off_size - 1;
+    let mut ranges = Vec::with_capacity(count);
+    let mut start = t!(offset(0));
+    for i in 1 ..= count {
+        let end = t!(offset(i));
+        if end < start || base + end > data.len() {
+            bail!("invalid INDEX offset {}", end);
+        }
+        ranges.push(base + start .. base + end);
+        start = end;
+    }
+    Ok((ranges, base + start))
+}
+
+fn dict(data: &[u8]) -> Result<Dict> {
+    let mut entries = vec![];
+    let mut operands = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let b0 = data[pos];
+        pos += 1;
+        match b0 {
+            0 ..= 21 => {
+                let op = if b0 == 12 {
+                    pos += 1;
+                    1200 + t!(read_u8(data, pos - 1)) as u16
+                } else {
+                    b0 as u16
+                };
+                entries.push((op, std::mem::take(&mut operands)));
+            }
+            28 => {
+                operands.push(t!(read_u16(data, pos)) as i16 as f64);
+                pos += 2;
+            }
+            29 => {
+                operands.push(t!(read_u32(data, pos)) as i32 as f64);
+                pos += 4;
+            }
+            30 => {
+                let mut s = String::new();
+                'real: loop {
+                    let b = t!(read_u8(data, pos));
+                    pos += 1;
+                    for nibble in [b >> 4, b & 0xf] {
+                        match nibble {
+                            0 ..= 9 => s.push((b'0' + nibble) as char),
+                            0xa => s.push('.'),
+                            0xb => s.push('E'),
+                            0xc => s.push_str("E-"),
+                            0xe => s.push('-'),
+                            0xf => break 'real,
+                            _ => {}
+                        }
+                    }
+                }
+                operands.push(s.parse().unwrap_or(0.));
+            }
+            32 ..= 246 => operands.push(b0 as f64 - 139.),
+            247 ..= 250 => {
+                operands.push((b0 as f64 - 247.) * 256. + t!(read_u8(data, pos)) as f64 + 108.);
+                pos += 1;
+            }
+            251 ..= 254 => {
+                operands.push(-(b0 as f64 - 251.) * 256. - t!(read_u8(data, pos)) as f64 - 108.);
+                pos += 1;
+            }
+            _ => bail!("invalid DICT byte {}", b0)
+        }
+    }
+    Ok(entries)
+}
+
+impl CffFont {
+    pub fn parse(data: &[u8]) -> Result<CffFont> {
+        let header_size = t!(read_u8(data, 2)) as usize;
+        let (_names, pos) = t!(index(data, header_size));
+        let (top_dicts, pos) = t!(index(data, pos));
+        let (strings, pos) = t!(index(data, pos));
+        let (global_subrs, _) = t!(index(data, pos));
+        let top = t!(dict(&data[try_opt!(top_dicts.first()).clone()]));
+
+        let char_strings = t!(index(data, try_opt!(get_usize(&top, op::CHAR_STRINGS)))).0;
+        let num_glyphs = char_strings.len();
+        let cid_keyed = get(&top, op::ROS).is_some();
+
+        let (privates, fd_select) = if cid_keyed {
+            let fd_array = t!(index(data, try_opt!(get_usize(&top, op::FD_ARRAY)))).0;
+            let mut privates = Vec::with_capacity(fd_array.len());
+            for range in fd_array {
+                let fd = t!(dict(&data[range]));
+                privates.push(t!(private(data, &fd)));
+            }
+            let fd_select = t!(fd_select(data, try_opt!(get_usize(&top, op::FD_SELECT)), num_glyphs));
+            (privates, fd_select)
+        } else {
+            (vec![t!(private(data, &top))], vec![])
+        };
+
+        let charset = t!(charset(data, get_usize(&top, op::CHARSET).unwrap_or(0), num_glyphs));
+        let font_matrix = match get(&top, op::FONT_MATRIX) {
+            Some(&[a, b, c, d, e, f]) => Matrix { a: a as f32, b: b as f32, c: c as f32, d: d as f32, e: e as f32, f: f as f32 },
+            _ => Matrix { a: 0.001, d: 0.001, .. Matrix::default() }
+        };
+
+        let mut font = CffFont {
+            data: data.into(),
+            strings,
+            global_subrs,
+            char_strings,
+            privates,
+            fd_select,
+            charset,
+            cid_keyed,
+            names: HashMap::new(),
+            cids: HashMap::new(),
+            encoding: CffEncoding::Standard,
+            font_matrix,
+        };
+        if cid_keyed {
+            font.cids = font.charset.iter().enumerate().map(|(gid, &cid)| (cid, gid as u32)).collect();
+        } else {
+            font.names = font.charset.iter().enumerate()
+                .filter_map(|(gid, &sid)| font.string(sid).map(|name| (name.to_owned(), gid as u32)))
+                .collect();
+            font.encoding = match get_usize(&top, op::ENCODING).unwrap_or(0) {
+                0 => CffEncoding::Standard,
+                1 => CffEncoding::Expert,
+                offset => CffEncoding::Custom(t!(font.custom_encoding(offset)))
+            };
+        }
+        Ok(font)
+    }
+
+    fn custom_encoding(&self, offset: usize) -> Result<Vec<(u8, u32)>> {
+        let data = &self.data;
+        let format = t!(read_u8(data, offset));
+        let mut codes = vec![];
+        let mut pos = offset + 1;
+        match format & 0x7f {
+            0 => {
+                let n = t!(read_u8(data, pos)) as usize;
+                for i in 0 .. n {
+                    codes.push((t!(read_u8(data, pos + 1 + i)), i as u32 + 1));
+                }
+                pos += 1 + n;
+            }
+            1 => {
+                let ranges = t!(read_u8(data, pos)) as usize;
+                let mut gid = 1;
+                for i in 0 .. ranges {
+                    let first = t!(read_u8(data, pos + 1 + 2 * i));
+                    let left = t!(read_u8(data, pos + 2 + 2 * i));
+                    for code in first ..= first.saturating_add(left) {
+                        codes.push((code, gid));
+                        gid += 1;
+                    }
+                }
+                pos += 1 + 2 * ranges;
+            }
+            f => bail!("invalid CFF encoding format {}", f)
+        }
+        if format & 0x80 != 0 {
+            // supplements: more codes for glyphs that already have one
+            let n = t!(read_u8(data, pos)) as usize;
+            for i in 0 .. n {
+                let code = t!(read_u8(data, pos + 1 + 3 * i));
+                let sid = t!(read_u16(data, pos + 2 + 3 * i));
+                if let Some(gid) = self.charset.iter().position(|&s| s == sid) {
+                    codes.push((code, gid as u32));
+                }
+            }
+        }
+        Ok(codes)
+    }
+
+    fn string(&self, sid: u16) -> Option<&str> {
+        match STANDARD_STRINGS.get(sid as usize) {
+            Some(s) => Some(s),
+            None => {
+                let range = self.strings.get(sid as usize - STANDARD_STRINGS.len())?.clone();
+                std::str::from_utf8(&self.data[range]).ok()
+            }
+        }
+    }
+
+    pub fn is_cid_keyed(&self) -> bool {
+        self.cid_keyed
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.char_strings.len()
+    }
+
+    pub fn font_matrix(&self) -> Matrix {
+        self.font_matrix
+    }
+
+    /// The name of glyph `gid`, unless this is a CID-keyed font.
+    pub fn glyph_name(&self, gid: u32) -> Option<&str> {
+        if self.cid_keyed {
+            return None;
+        }
+        self.charset.get(gid as usize).and_then(|&sid| self.string(sid))
+    }
+
+    pub fn gid_for_name(&self, name: &str) -> Option<u32> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn gid_for_cid(&self, cid: u32) -> Option<u32> {
+        if self.cid_keyed {
+            self.cids.get(&(cid as u16)).cloned()
+        } else {
+            Some(cid)
+        }
+    }
+
+    /// The built-in encoding. The Expert encoding isn't supported.
+    pub fn encoding(&self) -> Option<Encoding> {
+        if self.cid_keyed {
+            return None;
+        }
+        match self.encoding {
+            CffEncoding::Standard => Some(Encoding::standard()),
+            CffEncoding::Expert => None,
+            CffEncoding::Custom(ref codes) => Some(Encoding {
+                base: BaseEncoding::None,
+                differences: codes.iter()
+                    .filter_map(|&(code, gid)| Some((code as u32, self.glyph_name(gid)?.to_owned())))
+                    .collect()
+            })
+        }
+    }
+
+    /// The glyph name for `code` in the built-in encoding.
+    pub fn glyph_name_for_code(&self, code: u8) -> Option<&str> {
+        if self.cid_keyed {
+            return None;
+        }
+        match self.encoding {
+            CffEncoding::Standard => BaseEncoding::StandardEncoding.glyph_name(code),
+            CffEncoding::Expert => None,
+            CffEncoding::Custom(ref codes) => codes.iter()
+                .find(|&&(c, _)| c == code)
+                .and_then(|&(_, gid)| self.glyph_name(gid))
+        }
+    }
+
+    pub fn glyph(&self, gid: u32) -> Result<Option<Glyph>> {
+        self.glyph_with_accents(gid, true)
+    }
+
+    fn glyph_with_accents(&self, gid: u32, accents: bool) -> Result<Option<Glyph>> {
+        let code = match self.char_strings.get(gid as usize) {
+            Some(range) => &self.data[range.clone()],
+            None => return Ok(None)
+        };
+        let private = match self.fd_select.get(gid as usize) {
+            Some(&fd) => try_opt!(self.privates.get(fd as usize)),
+            None => try_opt!(self.privates.first())
+        };
+        let mut interpreter = Interpreter {
+            font: self,
+            private,
+            stack: Vec::with_capacity(48),
+            outline: Outline::default(),
+            width: None,
+            stems: 0,
+            transient: [0.; 32],
+            seac: None,
+        };
+        t!(interpreter.run(code, 0));
+        let Interpreter { outline, width, seac, .. } = interpreter;
+        let mut glyph = Glyph {
+            path: outline.finish(),
+            advance: width.unwrap_or(private.default_width),
+        };
+
+        // endchar with the arguments of seac: an accented character from two standard glyphs
+        if let Some((dx, dy, base, accent)) = seac {
+            if !accents {
+                bail!("nested seac");
+            }
+            let standard = |code: u8| BaseEncoding::StandardEncoding.glyph_name(code).and_then(|name| self.gid_for_name(name));
+            if let Some(base) = standard(base).map(|gid| self.glyph_with_accents(gid, false)).transpose()?.flatten() {
+                glyph.path.extend(base.path);
+            }
+            if let Some(mut accent) = standard(accent).map(|gid| self.glyph_with_accents(gid, false)).transpose()?.flatten() {
+                accent.transform(&Matrix { e: dx, f: dy, .. Matrix::default() });
+                glyph.path.extend(accent.path);
+            }
+        }
+        Ok(Some(glyph))
+    }
+}
+
+fn private(data: &[u8], dict: &Dict) -> Result<Private> {
+    let (size, offset) = match get(dict, op::PRIVATE) {
+        Some(&[size, offset]) => (size as usize, offset as usize),
+        _ => return Ok(Private::default())
+    };
+    let end = match offset.checked_add(size) {
+        Some(end) => end,
+        None => bail!("invalid Private DICT at {} with size {}", offset, size)
+    };
+    let private = t!(self::dict(try_opt!(data.get(offset .. end))));
+    let subrs = match get_usize(&private, op::SUBRS) {
+        Some(subrs) => match offset.checked_add(subrs) {
+            Some(pos) => t!(index(data, pos)).0,
+            None => bail!("invalid Subrs offset {}", subrs)
+        },
+        None => vec![]
+    };
+    Ok(Private {
+        subrs,
+        default_width: get(&private, op::DEFAULT_WIDTH_X).and_then(|v| v.first()).cloned().unwrap_or(0.) as f32,
+        nominal_width: get(&private, op::NOMINAL_WIDTH_X).and_then(|v| v.first()).cloned().unwrap_or(0.) as f32,
+    })
+}
+
+fn charset(data: &[u8], offset: usize, num_glyphs: usize) -> Result<Vec<u16>> {
+    let mut charset = Vec::with_capacity(num_glyphs);
+    match offset {
+        // ISOAdobe, where the glyph is the SID
+        0 => charset.extend((0 .. num_glyphs).map(|gid| gid as u16)),
+        1 | 2 => {
+            debug!("Expert charsets are not supported");
+            charset.extend((0 .. num_glyphs).map(|gid| gid as u16));
+        }
+        _ => {
+            charset.push(0);
+            let format = t!(read_u8(data, offset));
+            let mut pos = offset + 1;
+            while charset.len() < num_glyphs {
+                match format {
+                    0 => {
+                        charset.push(t!(read_u16(data, pos)));
+                        pos += 2;
+                    }
+                    1 | 2 => {
+                        let first = t!(read_u16(data, pos));
+                        let left = if format == 1 {
+                            pos += 3;
+                            t!(read_u8(data, pos - 1)) as u16
+                        } else {
+                            pos += 4;
+                            t!(read_u16(data, pos - 2))
+                        };
+                        charset.extend((0 ..= left).map(|i| first.wrapping_add(i)));
+                    }
+                    f => bail!("invalid charset format {}", f)
+                }
+            }
+            charset.truncate(num_glyphs);
+        }
+    }
+    Ok(charset)
+}
+
+fn fd_select(data: &[u8], offset: usize, num_glyphs: usize) -> Result<Vec<u8>> {
+    match t!(read_u8(data, offset)) {
+        0 => Ok(try_opt!(data.get(offset + 1 .. offset + 1 + num_glyphs)).to_vec()),
+        3 => {
+            let ranges = t!(read_u16(data, offset + 1)) as usize;
+            let mut fds = vec![0; num_glyphs];
+            for i in 0 .. ranges {
+                let range = offset + 3 + 3 * i;
+                let first = t!(read_u16(data, range)) as usize;
+                let fd = t!(read_u8(data, range + 2));
+                let end = t!(read_u16(data, range + 3)) as usize;
+                for slot in fds.iter_mut().take(end).skip(first) {
+                    *slot = fd;
+                }
+            }
+            Ok(fds)
+        }
+        f => bail!("invalid FDSelect format {}", f)
+    }
+}
+
+fn bias(subrs: usize) -> usize {
+    if subrs < 1240 {
+        107
+    } else if subrs < 33900 {
+        1131
+    } else {
+        32768
+    }
+}
+
+struct Interpreter<'a> {
+    font: &'a CffFont,
+    private: &'a Private,
+    stack: Vec<f32>,
+    outline: Outline,
+    width: Option<f32>,
+    stems: usize,
+    transient: [f32; 32],
+    seac: Option<(f32, f32, u8, u8)>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// The first stack clearing operator may have the width before its arguments.
+    fn width(&mut self, extra: bool) {
+        if self.width.is_none() {
+            self.width = Some(if extra && !self.stack.is_empty() {
+                self.private.nominal_width + self.stack.remove(0)
+            } else {
+                self.private.default_width
+            });
+        }
+    }
+
+    fn stems(&mut self) {
+        self.width(self.stack.len() % 2 == 1);
+        self.stems += self.stack.len() / 2;
+        self.stack.clear();
+    }
+
+    fn rel(&self, dx: f32, dy: f32) -> Point {
+        let p = self.outline.pos();
+        Point { x: p.x + dx, y: p.y + dy }
+    }
+
+    fn curve(&mut self, d: &[f32]) {
+        let c1 = self.rel(d[0], d[1]);
+        let c2 = Point { x: c1.x + d[2], y: c1.y + d[3] };
+        let p = Point { x: c2.x + d[4], y: c2.y + d[5] };
+        self.outline.curve_to(c1, c2, p);
+    }
+
+    /// hvcurveto and vhcurveto
+    fn alternating_curves(&mut self, mut horizontal: bool) {
+        let args = std::mem::take(&mut self.stack);
+        let mut i = 0;
+        while i + 4 <= args.len() {
+            let last = if args.len() - i == 5 { args[i + 4] } else { 0. };
+            let d = &args[i ..];
+            if horizontal {
+                self.curve(&[d[0], 0., d[1], d[2], last, d[3]]);
+            } else {
+                self.curve(&[0., d[0], d[1], d[2], d[3], last]);
+            }
+            i += 4;
+            horizontal = !horizontal;
+        }
+    }
+
+    fn pop(&mut self) -> Result<f32> {
+        self.stack.pop().ok_or_else(|| other!("charstring stack underflow"))
+    }
+
+    /// Returns whether the charstring ended.
+    fn run(&mut self, code: &[u8], depth: usize) -> Result<bool> {
+        if depth > MAX_SUBR_DEPTH {
+            bail!("charstring subroutines nested too deep");
+        }
+        let mut pos = 0;
+        while pos < code.len() {
+            let b0 = code[pos];
+            pos += 1;
+            match b0 {
+                // hstem, vstem, hstemhm, vstemhm
+                1 | 3 | 18 | 23 => self.stems(),
+                // hintmask, cntrmask: the stack has the arguments of an implicit vstem
+                19 | 20 => {
+                    self.stems();
+                    pos += self.stems.div_ceil(8);
+                }
+                // rmoveto
+                21 => {
+                    self.width(self.stack.len() > 2);
+                    let p = self.rel(self.stack.first().cloned().unwrap_or(0.), self.stack.get(1).cloned().unwrap_or(0.));
+                    self.outline.move_to(p);
+                    self.stack.clear();
+                }
+                // hmoveto, vmoveto
+                22 | 4 => {
+                    self.width(self.stack.len() > 1);
+                    let d = self.stack.first().cloned().unwrap_or(0.);
+                    let p = if b0 == 22 { self.rel(d, 0.) } else { self.rel(0., d) };
+                    self.outline.move_to(p);
+                    self.stack.clear();
+                }
+                // rlineto
+                5 => {
+                    for d in std::mem::take(&mut self.stack).chunks_exact(2) {
+                        let p = self.rel(d[0], d[1]);
+                        self.outline.line_to(p);
+                    }
+                }
+                // hlineto, vlineto
+                6 | 7 => {
+                    for (i, &d) in std::mem::take(&mut self.stack).iter().enumerate() {
+                        let p = if (i % 2 == 0) == (b0 == 6) { self.rel(d, 0.) } else { self.rel(0., d) };
+                        self.outline.line_to(p);
+                    }
+                }
+                // rrcurveto
+                8 => {
+                    for d in std::mem::take(&mut self.stack).chunks_exact(6) {
+                        self.curve(d);
+                    }
+                }
+                // rcurveline
+                24 => {
+                    let args = std::mem::take(&mut self.stack);
+                    if args.len() >= 2 {
+                        let (curves, line) = args.split_at(args.len() - 2);
+                        for d in curves.chunks_exact(6) {
+                            self.curve(d);
+                        }
+                        let p = self.rel(line[0], line[1]);
+                        self.outline.line_to(p);
+                    }
+                }
+                // rlinecurve
+                25 => {
+                    let args = std::mem::take(&mut self.stack);
+                    if args.len() >= 6 {
+                        let (lines, curve) = args.split_at(args.len() - 6);
+                        for d in lines.chunks_exact(2) {
+                            let p = self.rel(d[0], d[1]);
+                            self.outline.line_to(p);
+                        }
+                        self.curve(curve);
+                    }
+                }
+                // vvcurveto, hhcurveto
+                26 | 27 => {
+                    let mut args = std::mem::take(&mut self.stack);
+                    let first = if args.len() % 4 == 1 { args.remove(0) } else { 0. };
+                    for (i, d) in args.chunks_exact(4).enumerate() {
+                        let first = if i == 0 { first } else { 0. };
+                        if b0 == 26 {
+                            self.curve(&[first, d[0], d[1], d[2], 0., d[3]]);
+                        } else {
+                            self.curve(&[d[0], first, d[1], d[2], d[3], 0.]);
+                        }
+                    }
+                }
+                // vhcurveto, hvcurveto
+                30 => self.alternating_curves(false),
+                31 => self.alternating_curves(true),
+                // callsubr, callgsubr
+                10 | 29 => {
+                    let (font, private) = (self.font, self.private);
+                    let subrs = if b0 == 10 { &private.subrs } else { &font.global_subrs };
+                    let index = self.pop()? as i32 + bias(subrs.len()) as i32;
+                    let range = match subrs.get(index as usize) {
+                        Some(range) if index >= 0 => range.clone(),
+                        _ => bail!("invalid subroutine {}", index)
+                    };
+                    if t!(self.run(&font.data[range], depth + 1)) {
+                        return Ok(true);
+                    }
+                }
+                // return
+                11 => return Ok(false),
+                // endchar
+                14 => {
+                    self.width(self.stack.len() == 1 || self.stack.len() == 5);
+                    if let [dx, dy, base, accent] = self.stack[..] {
+                        self.seac = Some((dx, dy, base as u8, accent as u8));
+                    }
+                    self.outline.close();
+                    return Ok(true);
+                }
+                12 => {
+                    let b1 = t!(read_u8(code, pos));
+                    pos += 1;
+                    t!(self.escape(b1));
+                }
+                28 => {
+                    self.stack.push(t!(read_u16(code, pos)) as i16 as f32);
+                    pos += 2;
+                }
+                32 ..= 246 => self.stack.push(b0 as f32 - 139.),
+                247 ..= 250 => {
+                    self.stack.push((b0 as f32 - 247.) * 256. + t!(read_u8(code, pos)) as f32 + 108.);
+                    pos += 1;
+                }
+                251 ..= 254 => {
+                    self.stack.push(-(b0 as f32 - 251.) * 256. - t!(read_u8(code, pos)) as f32 - 108.);
+                    pos += 1;
+                }
+                255 => {
+                    self.stack.push(t!(read_u32(code, pos)) as i32 as f32 / 65536.);
+                    pos += 4;
+                }
+                _ => {
+                    debug!("reserved charstring operator {}", b0);
+                    self.stack.clear();
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn escape(&mut self, op: u8) -> Result<()> {
+        match op {
+            // hflex, flex, hflex1, flex1
+            34 ..= 37 => {
+                let a = std::mem::take(&mut self.stack);
+                let d = match (op, a.len()) {
+                    (35, 13) => [a[0], a[1], a[2], a[3], a[4], a[5], a[6], a[7], a[8], a[9], a[10], a[11]],
+                    (34, 7) => [a[0], 0., a[1], a[2], a[3], 0., a[4], 0., a[5], -a[2], a[6], 0.],
+                    (36, 9) => [a[0], a[1], a[2], a[3], a[4], 0., a[5], 0., a[6], a[7], a[8], -(a[1] + a[3] + a[7])],
+                    (37, 11) => {
+                        let dx: f32 = a[.. 10].iter().step_by(2).sum();
+                        let dy: f32 = a[1 .. 10].iter().step_by(2).sum();
+                        let (dx6, dy6) = if dx.abs() > dy.abs() { (a[10], -dy) } else { (-dx, a[10]) };
+                        [a[0], a[1], a[2], a[3], a[4], a[5], a[6], a[7], a[8], a[9], dx6, dy6]
+                    }
+                    _ => bail!("wrong number of flex arguments")
+                };
+                self.curve(&d[.. 6]);
+                self.curve(&d[6 ..]);
+            }
+            // and, or, not
+            3 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push((a != 0. && b != 0.) as u8 as f32); }
+            4 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push((a != 0. || b != 0.) as u8 as f32); }
+            5 => { let a = self.pop()?; self.stack.push((a == 0.) as u8 as f32); }
+            // abs, add, sub, div, neg, eq
+            9 => { let a = self.pop()?; self.stack.push(a.abs()); }
+            10 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push(a + b); }
+            11 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push(a - b); }
+            12 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push(if b != 0. { a / b } else { 0. }); }
+            14 => { let a = self.pop()?; self.stack.push(-a); }
+            15 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push((a == b) as u8 as f32); }
+            // drop
+            18 => { self.pop()?; }
+            // put, get
+            20 => {
+                let (i, v) = (self.pop()? as usize, self.pop()?);
+                if let Some(slot) = self.transient.get_mut(i) {
+                    *slot = v;
+                }
+            }
+            21 => {
+                let i = self.pop()? as usize;
+                self.stack.push(self.transient.get(i).cloned().unwrap_or(0.));
+            }
+            // ifelse
+            22 => {
+                let (v2, v1, s2, s1) = (self.pop()?, self.pop()?, self.pop()?, self.pop()?);
+                self.stack.push(if v1 <= v2 { s1 } else { s2 });
+            }
+            // random: anything in (0, 1]
+            23 => self.stack.push(0.5),
+            // mul, sqrt, dup, exch
+            24 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push(a * b); }
+            26 => { let a = self.pop()?; self.stack.push(a.abs().sqrt()); }
+            27 => { let a = self.pop()?; self.stack.push(a); self.stack.push(a); }
+            28 => { let (b, a) = (self.pop()?, self.pop()?); self.stack.push(b); self.stack.push(a); }
+            // index
+            29 => {
+                let i = self.pop()?.max(0.) as usize;
+                let v = *self.stack.iter().rev().nth(i).ok_or_else(|| other!("charstring stack underflow"))?;
+                self.stack.push(v);
+            }
+            // roll
+            30 => {
+                let (j, n) = (self.pop()? as i32, self.pop()? as usize);
+                if n > 0 && n <= self.stack.len() {
+                    let start = self.stack.len() - n;
+                    let shift = j.rem_euclid(n as i32) as usize;
+                    self.stack[start ..].rotate_right(shift);
+                }
+            }
+            // dotsection and reserved operators
+            _ => self.stack.clear()
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::program::PathOp;
+
+    /// An INDEX with one byte offsets.
+    fn index_data(objects: &[&[u8]]) -> Vec<u8> {
+        let mut out = (objects.len() as u16).to_be_bytes().to_vec();
+        if objects.is_empty() {
+            return out;
+        }
+        out.push(1);
+        let mut offset = 1;
+        out.push(offset);
+        for o in objects {
+            offset += o.len() as u8;
+            out.push(offset);
+        }
+        out.extend(objects.concat());
+        out
+    }
+
+    /// A number in a DICT or charstring.
+    fn num(v: i16) -> Vec<u8> {
+        let [a, b] = v.to_be_bytes();
+        vec![28, a, b]
+    }
+
+    fn font() -> Vec<u8> {
+        // 10 is the width, 20 30 hstem, 40 50 hintmask, 100 100 rmoveto, local and global subr
+        let square = [num(10), num(20), num(30), vec![1], num(40), num(50), vec![19, 0xc0], num(100), num(100), vec![21],
+            num(-107), vec![10], num(-107), vec![29], vec![14]].concat();
+        // 0 0 rmoveto, flex and hflex
+        let flex = [num(0), num(0), vec![21],
+            [100, 0, 100, 50, 100, 0, 100, -50, 100, 0, 100, 0, 50].iter().map(|&v| num(v)).collect::<Vec<_>>().concat(), vec![12, 35],
+            [50, 50, 20, 50, 50, 50, 50].iter().map(|&v| num(v)).collect::<Vec<_>>().concat(), vec![12, 34],
+            vec![14]].concat();
+        let local_subr = [num(300), vec![6, 11]].concat();
+        let global_subr = [num(0), num(300), vec![5, 11]].concat();
+
+        let header = [1, 0, 4, 1];
+        let names = index_data(&[b"Test"]);
+        // CharStrings and Private with fixed size operands, filled in below
+        let top_len = 3 + 1 + 3 + 3 + 1;
+        let top_index_len = 2 + 1 + 2 + top_len;
+        let strings = index_data(&[]);
+        let global_subrs = index_data(&[&global_subr]);
+        let char_strings_pos = header.len() + names.len() + top_index_len + strings.len() + global_subrs.len();
+        let char_strings = index_data(&[&[14], &square, &flex]);
+        let private_pos = char_strings_pos + char_strings.len();
+        // defaultWidthX 500, nominalWidthX 100, Subrs right after the Private DICT
+        let private_len = 3 + 1 + 3 + 1 + 3 + 1;
+        let private = [num(500), vec![20], num(100), vec![21], num(private_len as i16), vec![19]].concat();
+        assert_eq!(private.len(), private_len);
+        let top = [num(char_strings_pos as i16), vec![17], num(private_len as i16), num(private_pos as i16), vec![18]].concat();
+        assert_eq!(top.len(), top_len);
+
+        [&header[..], &names, &index_data(&[&top]), &strings, &global_subrs, &char_strings, &private, &index_data(&[&local_subr])].concat()
+    }
+
+    #[test]
+    fn index_and_dict() {
+        let data = [&[0xff, 0xff][..], &index_data(&[b"ab", b"", b"cde"]), &[0xaa]].concat();
+        let (ranges, end) = index(&data, 2).unwrap();
+        assert_eq!(ranges.iter().map(|r| &data[r.clone()]).collect::<Vec<_>>(), [&b"ab"[..], b"", b"cde"]);
+        assert_eq!(data[end], 0xaa);
+        assert_eq!(index(&[0, 0], 0).unwrap(), (vec![], 2));
+        // offsets past the end, and an invalid offset size
+        assert!(index(&[0, 1, 1, 1, 9, b'a'], 0).is_err());
+        assert!(index(&[0, 1, 5, 1, 2, b'a'], 0).is_err());
+
+        let dict = dict(&[0x8b, 0xf7, 0x00, 0xfb, 0x00, 1, 28, 0x27, 0x10, 30, 0x1a, 0x5f, 12, 7]).unwrap();
+        assert_eq!(dict, [(1, vec![0., 108., -108.]), (1207, vec![10000., 1.5])]);
+    }
+
+    #[test]
+    fn private_dict() {
+        let data = font();
+        let font = CffFont::parse(&data).unwrap();
+        let dict = &font.privates[0];
+        assert_eq!((dict.default_width, dict.nominal_width), (500., 100.));
+        assert_eq!(dict.subrs.len(), 1);
+        assert_eq!(font.global_subrs.len(), 1);
+
+        // Private DICT and Subrs offsets that overflow
+        assert!(private(&data, &vec![(op::PRIVATE, vec![1., 1e30])]).is_err());
+        // 1E30 Subrs
+        let data = [0, 30, 0x1b, 0x30, 0xff, 19];
+        assert!(private(&data, &vec![(op::PRIVATE, vec![5., 1.])]).is_err());
+    }
+
+    #[test]
+    fn charstrings() {
+        let font = CffFont::parse(&font()).unwrap();
+        let p = |x, y| Point { x, y };
+
+        assert_eq!(font.glyph(0).unwrap().unwrap().advance, 500.);
+
+        // width, hints, hintmask and subroutines
+        let square = font.glyph(1).unwrap().unwrap();
+        assert_eq!(square.advance, 110.);
+        assert_eq!(square.path, [
+            PathOp::MoveTo(p(100., 100.)),
+            PathOp::LineTo(p(400., 100.)),
+            PathOp::LineTo(p(400., 400.)),
+            PathOp::Close,
+        ]);
+
+        let flex = font.glyph(2).unwrap().unwrap();
+        assert_eq!(flex.advance, 500.);
+        assert_eq!(flex.path, [
+            PathOp::MoveTo(p(0., 0.)),
+            PathOp::CurveTo(p(100., 0.), p(200., 50.), p(300., 50.)),
+            PathOp::CurveTo(p(400., 0.), p(500., 0.), p(600., 0.)),
+            PathOp::CurveTo(p(650., 0.), p(700., 20.), p(750., 20.)),
+            PathOp::CurveTo(p(800., 20.), p(850., 0.), p(900., 0.)),
+            PathOp::Close,
+        ]);
+        assert!(font.glyph(3).unwrap().is_none());
+    }
+}
@@ -0,0 +1,222 @@
+//! Embedded font programs
+//!
+//! The font files of `/FontFile`, `/FontFile2` and `/FontFile3` are parsed far enough to get at
+//! the glyph outlines and at the tables needed to find them. Hints are ignored.
+
+use crate::content::{Matrix, Point};
+use crate::encoding::Encoding;
+use crate::error::{PdfError, Result};
+
+use super::cff::CffFont;
+use super::truetype::TrueTypeFont;
+use super::type1::Type1Font;
+
+/// One segment of a glyph outline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathOp {
+    MoveTo(Point),
+    LineTo(Point),
+    /// control point, end point
+    QuadTo(Point, Point),
+    /// two control points, end point
+    CurveTo(Point, Point, Point),
+    Close,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Glyph {
+    /// The outline in glyph space, see [`FontProgram::font_matrix`].
+    pub path: Vec<PathOp>,
+    /// Horizontal advance in glyph space.
+    pub advance: f32,
+}
+impl Glyph {
+    pub(super) fn transform(&mut self, m: &Matrix) {
+        let apply = |p: &mut Point| *p = Point {
+            x: m.a * p.x + m.c * p.y + m.e,
+            y: m.b * p.x + m.d * p.y + m.f,
+        };
+        for op in self.path.iter_mut() {
+            match op {
+                PathOp::MoveTo(p) | PathOp::LineTo(p) => apply(p),
+                PathOp::QuadTo(c, p) => {
+                    apply(c);
+                    apply(p);
+                }
+                PathOp::CurveTo(c1, c2, p) => {
+                    apply(c1);
+                    apply(c2);
+                    apply(p);
+                }
+                PathOp::Close => {}
+            }
+        }
+    }
+}
+
+/// A parsed font program.
+#[derive(Debug)]
+pub enum FontProgram {
+    /// `/FontFile2`, or OpenType with `glyf` outlines
+    TrueType(TrueTypeFont),
+    /// `/FontFile3` (`Type1C`, `CIDFontType0C`), or OpenType with a `CFF ` table
+    Cff(CffFont),
+    /// `/FontFile`
+    Type1(Type1Font),
+}
+
+impl FontProgram {
+    /// Parse a font file, guessing the format from its first bytes.
+    pub fn parse(data: &[u8]) -> Result<FontProgram> {
+        match data.get(.. 4) {
+            Some(b"OTTO") | Some(b"true") | Some(b"ttcf") | Some([0, 1, 0, 0]) => {
+                let font = t!(TrueTypeFont::parse(data));
+                match font.table(b"CFF ") {
+                    Some(cff) => Ok(FontProgram::Cff(t!(CffFont::parse(cff)))),
+                    None => Ok(FontProgram::TrueType(font))
+                }
+            }
+            Some([1, _, _, _]) => Ok(FontProgram::Cff(t!(CffFont::parse(data)))),
+            Some([0x80, 1, _, _]) | Some([b'%', b'!', _, _]) => Ok(FontProgram::Type1(t!(Type1Font::parse(data)))),
+            _ => bail!("unknown font format")
+        }
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        match *self {
+            FontProgram::TrueType(ref f) => f.glyph_count(),
+            FontProgram::Cff(ref f) => f.glyph_count(),
+            FontProgram::Type1(ref f) => f.glyph_count(),
+        }
+    }
+
+    /// Maps glyph space to text space (where the font size is 1).
+    pub fn font_matrix(&self) -> Matrix {
+        match *self {
+            FontProgram::TrueType(ref f) => f.font_matrix(),
+            FontProgram::Cff(ref f) => f.font_matrix(),
+            FontProgram::Type1(ref f) => f.font_matrix(),
+        }
+    }
+
+    /// The outline and advance of glyph `gid`.
+    pub fn glyph(&self, gid: u32) -> Option<Glyph> {
+        let glyph = match *self {
+            FontProgram::TrueType(ref f) => f.glyph(gid),
+            FontProgram::Cff(ref f) => f.glyph(gid),
+            FontProgram::Type1(ref f) => f.glyph(gid),
+        };
+        glyph.map_err(|e| warn!("can't read glyph {}: {}", gid, e)).ok().flatten()
+    }
+
+    /// The glyph with the PostScript name `name`. TrueType fonts only know `.notdef`.
+    pub fn gid_for_name(&self, name: &str) -> Option<u32> {
+        match *self {
+            FontProgram::TrueType(_) => if name == ".notdef" { Some(0) } else { None },
+            FontProgram::Cff(ref f) => f.gid_for_name(name),
+            FontProgram::Type1(ref f) => f.gid_for_name(name),
+        }
+    }
+
+    /// The glyph of CID `cid`. The CID is the glyph index unless it is a CID-keyed CFF font.
+    pub fn gid_for_cid(&self, cid: u32) -> Option<u32> {
+        match *self {
+            FontProgram::Cff(ref f) => f.gid_for_cid(cid),
+            _ => Some(cid)
+        }
+    }
+
+    /// The encoding of the font program itself: the `/Encoding` of Type1 fonts and the
+    /// encoding of name-keyed CFF fonts.
+    pub fn builtin_encoding(&self) -> Option<Encoding> {
+        match *self {
+            FontProgram::TrueType(_) => None,
+            FontProgram::Cff(ref f) => f.encoding(),
+            FontProgram::Type1(ref f) => f.encoding().cloned(),
+        }
+    }
+
+    /// The glyph name for `code` in the built-in encoding.
+    pub fn builtin_glyph_name(&self, code: u8) -> Option<&str> {
+        match *self {
+            FontProgram::TrueType(_) => None,
+            FontProgram::Cff(ref f) => f.glyph_name_for_code(code),
+            FontProgram::Type1(ref f) => f.encoding().and_then(|e| e.glyph_name(code)),
+        }
+    }
+
+    pub fn as_truetype(&self) -> Option<&TrueTypeFont> {
+        match *self {
+            FontProgram::TrueType(ref f) => Some(f),
+            _ => None
+        }
+    }
+}
+
+/// Builds an outline from the drawing operations of a charstring, where moving starts a new,
+/// implicitly closed path.
+#[derive(Default)]
+pub(super) struct Outline {
+    path: Vec<PathOp>,
+    pos: Point,
+    open: bool,
+}
+impl Outline {
+    pub fn pos(&self) -> Point {
+        self.pos
+    }
+    pub fn move_to(&mut self, p: Point) {
+        self.close();
+        self.pos = p;
+    }
+    fn start(&mut self) {
+        if !self.open {
+            self.path.push(PathOp::MoveTo(self.pos));
+            self.open = true;
+        }
+    }
+    pub fn line_to(&mut self, p: Point) {
+        self.start();
+        self.path.push(PathOp::LineTo(p));
+        self.pos = p;
+    }
+    pub fn quad_to(&mut self, c: Point, p: Point) {
+        self.start();
+        self.path.push(PathOp::QuadTo(c, p));
+        self.pos = p;
+    }
+    pub fn curve_to(&mut self, c1: Point, c2: Point, p: Point) {
+        self.start();
+        self.path.push(PathOp::CurveTo(c1, c2, p));
+        self.pos = p;
+    }
+    pub fn close(&mut self) {
+        if self.open {
+            self.path.push(PathOp::Close);
+            self.open = false;
+        }
+    }
+    pub fn finish(mut self) -> Vec<PathOp> {
+        self.close();
+        self.path
+    }
+}
+
+pub(super) fn read_u8(data: &[u8], pos: usize) -> Result<u8> {
+    data.get(pos).cloned().ok_or(PdfError::EOF)
+}
+pub(super) fn read_u16(data: &[u8], pos: usize) -> Result<u16> {
+    match data.get(pos .. pos + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err(PdfError::EOF)
+    }
+}
+pub(super) fn read_i16(data: &[u8], pos: usize) -> Result<i16> {
+    read_u16(data, pos).map(|v| v as i16)
+}
+pub(super) fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    match data.get(pos .. pos + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(PdfError::EOF)
+    }
+}
@@ -0,0 +1,550 @@
+//! TrueType and OpenType fonts
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::content::{Matrix, Point};
+use crate::error::{PdfError, Result};
+use crate::object::Rect;
+
+use super::program::{Glyph, Outline, read_u8, read_u16, read_i16, read_u32};
+
+mod subset;
+
+/// Nesting limit for composite glyphs.
+const MAX_DEPTH: usize = 8;
+
+// flags of composite glyph components
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x1;
+const ARGS_ARE_XY_VALUES: u16 = 0x2;
+const WE_HAVE_A_SCALE: u16 = 0x8;
+const MORE_COMPONENTS: u16 = 0x20;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x40;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x80;
+
+#[derive(Debug, Clone, Copy)]
+struct CmapSubtable {
+    platform: u16,
+    encoding: u16,
+    format: u16,
+    /// start of the subtable in the font
+    offset: usize,
+}
+
+#[derive(Debug)]
+pub struct TrueTypeFont {
+    data: Vec<u8>,
+    tables: Vec<([u8; 4], Range<usize>)>,
+    units_per_em: u16,
+    num_glyphs: usize,
+    long_loca: bool,
+    num_h_metrics: usize,
+    cmaps: Vec<CmapSubtable>,
+}
+
+impl TrueTypeFont {
+    /// Parse an sfnt file. Of a collection, the first font is used.
+    pub fn parse(data: &[u8]) -> Result<TrueTypeFont> {
+        let start = if data.starts_with(b"ttcf") { t!(read_u32(data, 12)) as usize } else { 0 };
+        let num_tables = t!(read_u16(data, start + 4)) as usize;
+        let mut tables = Vec::with_capacity(num_tables);
+        for i in 0 .. num_tables {
+            let record = start + 12 + 16 * i;
+            let tag = match data.get(record .. record + 4) {
+                Some(tag) => [tag[0], tag[1], tag[2], tag[3]],
+                None => return Err(PdfError::EOF)
+            };
+            let offset = t!(read_u32(data, record + 8)) as usize;
+            let len = t!(read_u32(data, record + 12)) as usize;
+            if offset > data.len() {
+                warn!("table {} outside of the font", String::from_utf8_lossy(&tag));
+                continue;
+            }
+            // subsetters sometimes get the length wrong
+            tables.push((tag, offset .. (offset + len).min(data.len())));
+        }
+
+        let mut font = TrueTypeFont {
+            data: data.into(),
+            tables,
+            units_per_em: 1000,
+            num_glyphs: 0,
+            long_loca: false,
+            num_h_metrics: 0,
+            cmaps: vec![],
+        };
+        if let Some(head) = font.table(b"head") {
+            let (units_per_em, long_loca) = (t!(read_u16(head, 18)), t!(read_i16(head, 50)) != 0);
+            font.units_per_em = units_per_em;
+            font.long_loca = long_loca;
+        }
+        if font.units_per_em == 0 {
+            font.units_per_em = 1000;
+        }
+        font.num_glyphs = match font.table(b"maxp") {
+            Some(maxp) => t!(read_u16(maxp, 4)) as usize,
+            None => font.table(b"loca").map(|loca| (loca.len() / if font.long_loca { 4 } else { 2 }).saturating_sub(1)).unwrap_or(0)
+        };
+        if let Some(hhea) = font.table(b"hhea") {
+            font.num_h_metrics = t!(read_u16(hhea, 34)) as usize;
+        }
+        if let Some(range) = font.table_range(b"cmap") {
+            let cmap = &font.data[range.clone()];
+            let count = t!(read_u16(cmap, 2)) as usize;
+            for i in 0 .. count {
+                let record = 4 + 8 * i;
+                let offset = t!(read_u32(cmap, record + 4)) as usize;
+                match read_u16(cmap, offset) {
+                    Ok(format) => font.cmaps.push(CmapSubtable {
+                        platform: t!(read_u16(cmap, record)),
+                        encoding: t!(read_u16(cmap, record + 2)),
+                        format,
+                        offset: range.start + offset,
+                    }),
+                    Err(_) => warn!("cmap subtable {} outside of the table", i)
+                }
+            }
+        }
+        Ok(font)
+    }
+
+    fn table_range(&self, tag: &[u8; 4]) -> Option<Range<usize>> {
+        self.tables.iter().find(|(t, _)| t == tag).map(|(_, r)| r.clone())
+    }
+
+    /// The raw data of table `tag`.
+    pub fn table(&self, tag: &[u8; 4]) -> Option<&[u8]> {
+        self.table_range(tag).map(|r| &self.data[r])
+    }
+
+    pub fn units_per_em(&self) -> u16 {
+        self.units_per_em
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.num_glyphs
+    }
+
+    pub fn font_matrix(&self) -> Matrix {
+        let scale = 1.0 / self.units_per_em as f32;
+        Matrix { a: scale, d: scale, .. Matrix::default() }
+    }
+
+    /// The `(platform, encoding)` ids of the `cmap` subtables.
+    pub fn cmap_subtables(&self) -> impl Iterator<Item=(u16, u16)> + '_ {
+        self.cmaps.iter().map(|s| (s.platform, s.encoding))
+    }
+
+    /// Look up `code` in the `cmap` subtable for `platform` and `encoding`.
+    pub fn cmap_lookup(&self, platform: u16, encoding: u16, code: u32) -> Option<u32> {
+        let subtable = self.cmaps.iter().find(|s| s.platform == platform && s.encoding == encoding)?;
+        match self.lookup(subtable, code) {
+            Ok(Some(0)) => None,
+            Ok(gid) => gid,
+            Err(e) => {
+                warn!("invalid cmap subtable: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The glyph of a Unicode character, from a Windows or Unicode `cmap` subtable.
+    pub fn gid_for_unicode(&self, c: char) -> Option<u32> {
+        [(3, 10), (0, 4), (3, 1), (0, 3), (0, 0), (0, 1), (0, 2)].iter()
+            .find_map(|&(platform, encoding)| self.cmap_lookup(platform, encoding, c as u32))
+    }
+
+    /// The PostScript name from the `name` table.
+    pub fn postscript_name(&self) -> Option<String> {
+        let name = self.table(b"name")?;
+        let count = read_u16(name, 2).ok()? as usize;
+        let strings = read_u16(name, 4).ok()? as usize;
+        for i in 0 .. count {
+            let record = 6 + 12 * i;
+            let (platform, name_id) = (read_u16(name, record).ok()?, read_u16(name, record + 6).ok()?);
+            if name_id != 6 {
+                continue;
+            }
+            let len = read_u16(name, record + 8).ok()? as usize;
+            let offset = strings + read_u16(name, record + 10).ok()? as usize;
+            let data = name.get(offset .. offset + len)?;
+            let s: String = match platform {
+                // UTF-16BE
+                0 | 3 => std::char::decode_utf16(data.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])))
+                    .filter_map(|r| r.ok())
+                    .collect(),
+                _ => data.iter().map(|&b| b as char).collect()
+            };
+            if !s.is_empty() {
+                return Some(s);
+            }
+        }
+        None
+    }
+
+    /// The bounding box of all glyphs, in font units.
+    pub fn bbox(&self) -> Option<Rect> {
+        let head = self.table(b"head")?;
+        Some(Rect {
+            left: read_i16(head, 36).ok()? as f32,
+            bottom: read_i16(head, 38).ok()? as f32,
+            right: read_i16(head, 40).ok()? as f32,
+            top: read_i16(head, 42).ok()? as f32,
+        })
+    }
+
+    /// Ascender and descender from the `hhea` table, in font units.
+    pub fn ascent_descent(&self) -> Option<(f32, f32)> {
+        let hhea = self.table(b"hhea")?;
+        Some((read_i16(hhea, 4).ok()? as f32, read_i16(hhea, 6).ok()? as f32))
+    }
+
+    /// The height of capital letters from the `OS/2` table, in font units.
+    pub fn cap_height(&self) -> Option<f32> {
+        let os2 = self.table(b"OS/2")?;
+        match read_u16(os2, 0).ok()? {
+            0 | 1 => None,
+            _ => Some(read_i16(os2, 88).ok()? as f32)
+        }
+    }
+
+    /// Italic angle in degrees, from the `post` table.
+    pub fn italic_angle(&self) -> f32 {
+        match self.table(b"post").map(|post| read_u32(post, 4)) {
+            Some(Ok(fixed)) => fixed as i32 as f32 / 65536.,
+            _ => 0.
+        }
+    }
+
+    pub fn is_fixed_pitch(&self) -> bool {
+        matches!(self.table(b"post").map(|post| read_u32(post, 12)), Some(Ok(n)) if n != 0)
+    }
+
+    fn lookup(&self, subtable: &CmapSubtable, code: u32) -> Result<Option<u32>> {
+        let data = &self.data[subtable.offset ..];
+        Ok(match subtable.format {
+            0 => match code {
+                0 ..= 255 => Some(t!(read_u8(data, 6 + code as usize)) as u32),
+                _ => None
+            },
+            4 => {
+                if code > 0xFFFF {
+                    return Ok(None);
+                }
+                let seg_count_x2 = t!(read_u16(data, 6)) as usize;
+                let ends = 14;
+                let starts = ends + seg_count_x2 + 2;
+                let deltas = starts + seg_count_x2;
+                let range_offsets = deltas + seg_count_x2;
+                // binary search for the first segment that ends at or after the code
+                let (mut lo, mut hi) = (0, seg_count_x2 / 2);
+                while lo < hi {
+                    let mid = (lo + hi) / 2;
+                    if (t!(read_u16(data, ends + 2 * mid)) as u32) < code {
+                        lo = mid + 1;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                if lo == seg_count_x2 / 2 {
+                    return Ok(None);
+                }
+                let start = t!(read_u16(data, starts + 2 * lo)) as u32;
+                if code < start {
+                    return Ok(None);
+                }
+                let delta = t!(read_u16(data, deltas + 2 * lo));
+                let range_offset = t!(read_u16(data, range_offsets + 2 * lo)) as usize;
+                if range_offset == 0 {
+                    Some((code as u16).wrapping_add(delta) as u32)
+                } else {
+                    let pos = range_offsets + 2 * lo + range_offset + 2 * (code - start) as usize;
+                    match t!(read_u16(data, pos)) {
+                        0 => None,
+                        gid => Some(gid.wrapping_add(delta) as u32)
+                    }
+                }
+            }
+            6 => {
+                let first = t!(read_u16(data, 6)) as u32;
+                let count = t!(read_u16(data, 8)) as u32;
+                if code >= first && code - first < count {
+                    Some(t!(read_u16(data, 10 + 2 * (code - first) as usize)) as u32)
+                } else {
+                    None
+                }
+            }
+            12 => {
+                let groups = t!(read_u32(data, 12)) as usize;
+                let (mut lo, mut hi) = (0, groups);
+                while lo < hi {
+                    let mid = (lo + hi) / 2;
+                    let group = 16 + 12 * mid;
+                    let (start, end) = (t!(read_u32(data, group)), t!(read_u32(data, group + 4)));
+                    if code < start {
+                        hi = mid;
+                    } else if code > end {
+                        lo = mid + 1;
+                    } else {
+                        return Ok(Some(t!(read_u32(data, group + 8)) + code - start));
+                    }
+                }
+                None
+            }
+            format => {
+                debug!("cmap subtable format {} not supported", format);
+                None
+            }
+        })
+    }
+
+    /// Advance width of glyph `gid` in font units.
+    pub fn advance(&self, gid: u32) -> Option<u16> {
+        let hmtx = self.table(b"hmtx")?;
+        let gid = gid as usize;
+        let index = if gid < self.num_h_metrics { gid } else { self.num_h_metrics.checked_sub(1)? };
+        read_u16(hmtx, 4 * index).ok()
+    }
+
+    /// The horizontal kerning pairs of the `kern` table by glyph ids, in font units.
+    ///
+    /// Only format 0 subtables are read, in the Windows and the Apple layout. Kerning in the
+    /// `GPOS` table of OpenType fonts isn't supported.
+    pub fn kerning(&self) -> Result<HashMap<(u16, u16), i16>> {
+        let mut pairs = HashMap::new();
+        let kern = match self.table(b"kern") {
+            Some(kern) => kern,
+            None => return Ok(pairs)
+        };
+        let apple = t!(read_u16(kern, 0)) == 1;
+        let (count, mut pos) = match apple {
+            true => (t!(read_u32(kern, 4)) as usize, 8),
+            false => (t!(read_u16(kern, 2)) as usize, 4),
+        };
+        for _ in 0 .. count {
+            let (len, format, horizontal, header) = match apple {
+                true => {
+                    let coverage = t!(read_u16(kern, pos + 4));
+                    // not vertical, cross-stream or variation kerning
+                    (t!(read_u32(kern, pos)) as usize, coverage & 0xFF, coverage & 0xE000 == 0, 8)
+                }
+                false => {
+                    let coverage = t!(read_u16(kern, pos + 4));
+                    // horizontal, and neither minimum values nor cross-stream
+                    (t!(read_u16(kern, pos + 2)) as usize, coverage >> 8, coverage & 0x7 == 1, 6)
+                }
+            };
+            if format != 0 {
+                pos += len.max(header);
+                continue;
+            }
+            let n = t!(read_u16(kern, pos + header)) as usize;
+            let start = pos + header + 8;
+            if horizontal {
+                for i in 0 .. n {
+                    let p = start + 6 * i;
+                    pairs.insert((t!(read_u16(kern, p)), t!(read_u16(kern, p + 2))), t!(read_i16(kern, p + 4)));
+                }
+            }
+            // the 16 bit length overflows in large tables
+            pos = start + 6 * n;
+        }
+        Ok(pairs)
+    }
+
+    fn glyph_data(&self, gid: u32) -> Result<&[u8]> {
+        let loca = try_opt!(self.table(b"loca"));
+        let glyf = try_opt!(self.table(b"glyf"));
+        let gid = gid as usize;
+        let (start, end) = if self.long_loca {
+            (t!(read_u32(loca, 4 * gid)) as usize, t!(read_u32(loca, 4 * gid + 4)) as usize)
+        } else {
+            (2 * t!(read_u16(loca, 2 * gid)) as usize, 2 * t!(read_u16(loca, 2 * gid + 2)) as usize)
+        };
+        if start > end || end > glyf.len() {
+            bail!("glyph {} outside of the glyf table", gid);
+        }
+        Ok(&glyf[start .. end])
+    }
+
+    pub fn glyph(&self, gid: u32) -> Result<Option<Glyph>> {
+        if gid as usize >= self.num_glyphs {
+            return Ok(None);
+        }
+        let mut outline = Outline::default();
+        t!(self.outline(gid, &mut outline, &Matrix::default(), 0));
+        Ok(Some(Glyph {
+            path: outline.finish(),
+            advance: self.advance(gid).unwrap_or(0) as f32,
+        }))
+    }
+
+    fn outline(&self, gid: u32, outline: &mut Outline, m: &Matrix, depth: usize) -> Result<()> {
+        let data = t!(self.glyph_data(gid));
+        if data.is_empty() {
+            return Ok(());
+        }
+        let contours = t!(read_i16(data, 0));
+        if contours >= 0 {
+            simple_glyph(data, contours as usize, outline, m)
+        } else if depth < MAX_DEPTH {
+            self.composite_glyph(data, outline, m, depth)
+        } else {
+            bail!("composite glyph {} nested too deep", gid)
+        }
+    }
+
+    fn composite_glyph(&self, data: &[u8], outline: &mut Outline, m: &Matrix, depth: usize) -> Result<()> {
+        let f2dot14 = |pos| read_i16(data, pos).map(|v| v as f32 / 16384.);
+
+        let mut pos = 10;
+        loop {
+            let flags = t!(read_u16(data, pos));
+            let gid = t!(read_u16(data, pos + 2)) as u32;
+            pos += 4;
+            let (dx, dy) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                pos += 4;
+                (t!(read_i16(data, pos - 4)) as f32, t!(read_i16(data, pos - 2)) as f32)
+            } else {
+                pos += 2;
+                (t!(read_u8(data, pos - 2)) as i8 as f32, t!(read_u8(data, pos - 1)) as i8 as f32)
+            };
+            // matching points instead of an offset is rare, place the component at the origin then
+            let (e, f) = if flags & ARGS_ARE_XY_VALUES != 0 { (dx, dy) } else { (0., 0.) };
+            let mut component = Matrix { e, f, .. Matrix::default() };
+            if flags & WE_HAVE_A_SCALE != 0 {
+                component.a = t!(f2dot14(pos));
+                component.d = component.a;
+                pos += 2;
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                component.a = t!(f2dot14(pos));
+                component.d = t!(f2dot14(pos + 2));
+                pos += 4;
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                component.a = t!(f2dot14(pos));
+                component.b = t!(f2dot14(pos + 2));
+                component.c = t!(f2dot14(pos + 4));
+                component.d = t!(f2dot14(pos + 6));
+                pos += 8;
+            }
+            t!(self.outline(gid, outline, &concat(&component, m), depth + 1));
+            if flags & MORE_COMPONENTS == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// `a` then `b`
+fn concat(a: &Matrix, b: &Matrix) -> Matrix {
+    Matrix {
+        a: a.a * b.a + a.b * b.c,
+        b: a.a * b.b + a.b * b.d,
+        c: a.c * b.a + a.d * b.c,
+        d: a.c * b.b + a.d * b.d,
+        e: a.e * b.a + a.f * b.c + b.e,
+        f: a.e * b.b + a.f * b.d + b.f,
+    }
+}
+
+fn simple_glyph(data: &[u8], contours: usize, outline: &mut Outline, m: &Matrix) -> Result<()> {
+    const ON_CURVE: u8 = 0x1;
+    const X_SHORT: u8 = 0x2;
+    const Y_SHORT: u8 = 0x4;
+    const REPEAT: u8 = 0x8;
+    const X_SAME_OR_POSITIVE: u8 = 0x10;
+    const Y_SAME_OR_POSITIVE: u8 = 0x20;
+
+    if contours == 0 {
+        return Ok(());
+    }
+    let mut ends = Vec::with_capacity(contours);
+    for i in 0 .. contours {
+        ends.push(t!(read_u16(data, 10 + 2 * i)) as usize);
+    }
+    let num_points = ends[contours - 1] + 1;
+    let instructions = t!(read_u16(data, 10 + 2 * contours)) as usize;
+    let mut pos = 12 + 2 * contours + instructions;
+
+    let mut flags = Vec::with_capacity(num_points);
+    while flags.len() < num_points {
+        let flag = t!(read_u8(data, pos));
+        pos += 1;
+        flags.push(flag);
+        if flag & REPEAT != 0 {
+            let n = t!(read_u8(data, pos));
+            pos += 1;
+            flags.extend(std::iter::repeat_n(flag, n as usize));
+        }
+    }
+    flags.truncate(num_points);
+
+    let mut coords = |short: u8, same_or_positive: u8| -> Result<Vec<f32>> {
+        let mut v = 0i32;
+        let mut values = Vec::with_capacity(num_points);
+        for &flag in &flags {
+            if flag & short != 0 {
+                let d = t!(read_u8(data, pos)) as i32;
+                pos += 1;
+                v += if flag & same_or_positive != 0 { d } else { -d };
+            } else if flag & same_or_positive == 0 {
+                v += t!(read_i16(data, pos)) as i32;
+                pos += 2;
+            }
+            values.push(v as f32);
+        }
+        Ok(values)
+    };
+    let xs = t!(coords(X_SHORT, X_SAME_OR_POSITIVE));
+    let ys = t!(coords(Y_SHORT, Y_SAME_OR_POSITIVE));
+    let points: Vec<(Point, bool)> = xs.iter().zip(&ys).zip(&flags)
+        .map(|((&x, &y), &flag)| (Point {
+            x: m.a * x + m.c * y + m.e,
+            y: m.b * x + m.d * y + m.f,
+        }, flag & ON_CURVE != 0))
+        .collect();
+
+    let mut start = 0;
+    for &end in &ends {
+        if end < start || end >= points.len() {
+            bail!("invalid contour end {}", end);
+        }
+        contour(&points[start ..= end], outline);
+        start = end + 1;
+    }
+    Ok(())
+}
+
+fn midpoint(a: Point, b: Point) -> Point {
+    Point { x: (a.x + b.x) / 2., y: (a.y + b.y) / 2. }
+}
+
+/// Quadratic contour, where two off-curve points imply an on-curve point between them.
+fn contour(points: &[(Point, bool)], outline: &mut Outline) {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    // start on a point on the curve
+    let (start, rest) = if first.1 {
+        (first.0, &points[1 ..])
+    } else if last.1 {
+        (last.0, &points[.. points.len() - 1])
+    } else {
+        (midpoint(first.0, last.0), points)
+    };
+    outline.move_to(start);
+    let mut control: Option<Point> = None;
+    for &(p, on_curve) in rest.iter().chain(std::iter::once(&(start, true))) {
+        match (on_curve, control) {
+            (true, Some(c)) => {
+                outline.quad_to(c, p);
+                control = None;
+            }
+            (true, None) => outline.line_to(p),
+            (false, Some(c)) => {
+                outline.quad_to(c, midpoint(c, p));
+                control = Some(p);
+            }
+            (false, None) => control = Some(p),
+        }
+    }
+    outline.close();
+}
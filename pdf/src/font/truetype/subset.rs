@@ -0,0 +1,202 @@
+//! Subsetting of TrueType fonts for embedding
+
+use std::collections::BTreeSet;
+
+use crate::error::Result;
+
+use super::*;
+
+/// The tables a font embedded in a PDF file needs, sorted by tag. The others, like `cmap`, are
+/// only read by the font as a simple font.
+const TABLES: [&[u8; 4]; 9] = [b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep"];
+
+impl TrueTypeFont {
+    /// A font file with only `glyphs`, the glyphs they are composed of and `.notdef`.
+    ///
+    /// Glyph ids stay the same, the outlines of all other glyphs are removed.
+    pub fn subset(&self, glyphs: &BTreeSet<u16>) -> Result<Vec<u8>> {
+        let mut keep = BTreeSet::new();
+        let mut todo: Vec<u16> = glyphs.iter().cloned().chain(Some(0)).collect();
+        while let Some(gid) = todo.pop() {
+            if gid as usize >= self.num_glyphs || !keep.insert(gid) {
+                continue;
+            }
+            todo.extend(t!(components(t!(self.glyph_data(gid as u32)))));
+        }
+
+        // always with long offsets
+        let mut glyf = vec![];
+        let mut loca = Vec::with_capacity(4 * (self.num_glyphs + 1));
+        for gid in 0 .. self.num_glyphs {
+            loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+            if keep.contains(&(gid as u16)) {
+                glyf.extend_from_slice(t!(self.glyph_data(gid as u32)));
+                glyf.resize(glyf.len().div_ceil(4) * 4, 0);
+            }
+        }
+        loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+
+        let mut head = try_opt!(self.table(b"head")).to_vec();
+        if head.len() < 54 {
+            bail!("head table too short");
+        }
+        // checkSumAdjustment is set at the end
+        head[8 .. 12].copy_from_slice(&[0; 4]);
+        // indexToLocFormat
+        head[50 .. 52].copy_from_slice(&1u16.to_be_bytes());
+
+        let mut tables = Vec::with_capacity(TABLES.len());
+        for &tag in TABLES.iter() {
+            let data = match tag {
+                b"glyf" => std::mem::take(&mut glyf),
+                b"loca" => std::mem::take(&mut loca),
+                b"head" => std::mem::take(&mut head),
+                _ => match self.table(tag) {
+                    Some(data) => data.to_vec(),
+                    None => continue
+                }
+            };
+            tables.push((tag, data));
+        }
+        Ok(write_sfnt(&tables))
+    }
+}
+
+/// The glyphs a composite glyph refers to.
+fn components(data: &[u8]) -> Result<Vec<u16>> {
+    if data.is_empty() || t!(read_i16(data, 0)) >= 0 {
+        return Ok(vec![]);
+    }
+    let mut gids = vec![];
+    let mut pos = 10;
+    loop {
+        let flags = t!(read_u16(data, pos));
+        gids.push(t!(read_u16(data, pos + 2)));
+        pos += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 8 } else { 6 };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            pos += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            pos += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            pos += 8;
+        }
+        if flags & MORE_COMPONENTS == 0 {
+            return Ok(gids);
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4)
+        .map(|c| {
+            let mut word = [0; 4];
+            word[.. c.len()].copy_from_slice(c);
+            u32::from_be_bytes(word)
+        })
+        .fold(0, u32::wrapping_add)
+}
+
+fn write_sfnt(tables: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let num_tables = tables.len();
+    let mut entry_selector = 0;
+    while 2 << entry_selector <= num_tables {
+        entry_selector += 1;
+    }
+    let search_range = 16 << entry_selector;
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&0x00010000u32.to_be_bytes());
+    for n in [num_tables, search_range, entry_selector, 16 * num_tables - search_range] {
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    }
+    let mut offset = 12 + 16 * num_tables;
+    let mut head = None;
+    for &(tag, ref data) in tables {
+        if tag == b"head" {
+            head = Some(offset);
+        }
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&checksum(data).to_be_bytes());
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        offset += data.len().div_ceil(4) * 4;
+    }
+    for (_, data) in tables {
+        out.extend_from_slice(data);
+        out.resize(out.len().div_ceil(4) * 4, 0);
+    }
+    if let Some(head) = head {
+        let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&out));
+        out[head + 8 .. head + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `.notdef`, a triangle, the triangle moved as a composite glyph and another triangle.
+    fn font() -> Vec<u8> {
+        let triangle = [0, 1, 0, 0, 0, 0, 0, 100, 0, 100, 0, 2, 0, 0, 0x31, 0x33, 0x27, 100, 100, 100];
+        let composite = [0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 10, 0];
+        let glyf = [&triangle[..], &composite, &triangle].concat();
+        // short offsets, halved
+        let loca = [0u8, 0, 0, 0, 0, 10, 0, 18, 0, 28];
+        let mut head = vec![0; 54];
+        head[18 .. 20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        hhea[34 .. 36].copy_from_slice(&4u16.to_be_bytes());
+        let hmtx = [0, 0, 0, 0, 0, 100, 0, 0, 0, 110, 0, 0, 0, 100, 0, 0];
+        let maxp = [0, 0, 0x50, 0, 0, 4];
+        write_sfnt(&[(b"glyf", glyf), (b"head", head), (b"hhea", hhea), (b"hmtx", hmtx.to_vec()),
+            (b"loca", loca.to_vec()), (b"maxp", maxp.to_vec())])
+    }
+
+    #[test]
+    fn subset() {
+        let font = TrueTypeFont::parse(&font()).unwrap();
+        assert_eq!(font.glyph(3).unwrap().unwrap().path.len(), 5);
+
+        let subset = TrueTypeFont::parse(&font.subset(&[2].iter().cloned().collect()).unwrap()).unwrap();
+        assert_eq!(subset.glyph_count(), 4);
+        assert!(subset.long_loca);
+        // the component of the composite glyph is kept
+        assert_eq!(subset.glyph(1).unwrap().unwrap().path, font.glyph(1).unwrap().unwrap().path);
+        assert_eq!(subset.glyph(2).unwrap().unwrap().path, font.glyph(2).unwrap().unwrap().path);
+        assert!(subset.glyph(3).unwrap().unwrap().path.is_empty());
+        assert_eq!(subset.advance(3), Some(100));
+    }
+
+    #[test]
+    fn kerning() {
+        // a horizontal and a cross-stream subtable
+        let mut kern = vec![0, 0, 0, 2];
+        kern.extend_from_slice(&[0, 0, 0, 26, 0, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0xFF, 0x9C, 0, 2, 0, 3, 0, 50]);
+        kern.extend_from_slice(&[0, 0, 0, 20, 0, 5, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 3, 0, 10]);
+        let font = TrueTypeFont::parse(&write_sfnt(&[(b"head", vec![0; 54]), (b"kern", kern)])).unwrap();
+        let pairs = font.kerning().unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[&(1, 2)], -100);
+        assert_eq!(pairs[&(2, 3)], 50);
+
+        // the Apple layout
+        let kern = [0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 4, 0, 5, 0, 30].to_vec();
+        let font = TrueTypeFont::parse(&write_sfnt(&[(b"head", vec![0; 54]), (b"kern", kern)])).unwrap();
+        assert_eq!(font.kerning().unwrap()[&(4, 5)], 30);
+
+        let truncated = TrueTypeFont::parse(&write_sfnt(&[(b"kern", vec![0, 0, 0, 1, 0, 0])])).unwrap();
+        assert!(truncated.kerning().is_err());
+    }
+
+    #[test]
+    fn sfnt() {
+        let font = write_sfnt(&[(b"abcd", vec![1, 2, 3, 4, 5]), (b"head", vec![0; 54])]);
+        // header, two records, two padded tables
+        assert_eq!(font.len(), 12 + 32 + 8 + 56);
+        assert_eq!(&font[4 .. 12], &[0, 2, 0, 32, 0, 1, 0, 0]);
+        let parsed = TrueTypeFont::parse(&font).unwrap();
+        assert_eq!(parsed.table(b"abcd"), Some(&[1, 2, 3, 4, 5][..]));
+        assert_eq!(checksum(&font), 0xB1B0AFBA);
+    }
+}
@@ -0,0 +1,573 @@
+//! Type 1 fonts with eexec encrypted charstrings
+
+use std::collections::HashMap;
+
+use crate::content::{Matrix, Point};
+use crate::encoding::{BaseEncoding, Encoding};
+use crate::error::Result;
+
+use super::program::{Glyph, Outline, read_u32};
+
+/// Nesting limit for subroutine calls.
+const MAX_SUBR_DEPTH: usize = 10;
+
+const EEXEC_KEY: u16 = 55665;
+const CHARSTRING_KEY: u16 = 4330;
+
+fn decrypt(data: &[u8], mut r: u16) -> Vec<u8> {
+    data.iter().map(|&c| {
+        let p = c ^ (r >> 8) as u8;
+        r = (c as u16).wrapping_add(r).wrapping_mul(52845).wrapping_add(22719);
+        p
+    }).collect()
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(b, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+/// Splits PostScript into tokens, with binary data after `RD`.
+struct Scanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Scanner<'a> {
+    fn new(data: &'a [u8]) -> Scanner<'a> {
+        Scanner { data, pos: 0 }
+    }
+
+    fn token(&mut self) -> Option<&'a [u8]> {
+        let data = self.data;
+        loop {
+            while self.pos < data.len() && data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            if data.get(self.pos) == Some(&b'%') {
+                while self.pos < data.len() && data[self.pos] != b'\n' && data[self.pos] != b'\r' {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = self.pos;
+        match *data.get(start)? {
+            b'(' => {
+                let mut depth = 0;
+                while self.pos < data.len() {
+                    match data[self.pos] {
+                        b'\\' => self.pos += 1,
+                        b'(' => depth += 1,
+                        b')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                self.pos += 1;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    self.pos += 1;
+                }
+            }
+            b'[' | b']' | b'{' | b'}' | b'<' | b'>' | b')' => self.pos += 1,
+            _ => {
+                self.pos += 1;
+                while self.pos < data.len() && !data[self.pos].is_ascii_whitespace() && !is_delimiter(data[self.pos]) {
+                    self.pos += 1;
+                }
+            }
+        }
+        data.get(start .. self.pos.min(data.len()))
+    }
+
+    fn number(&mut self) -> Option<f32> {
+        std::str::from_utf8(self.token()?).ok()?.parse().ok()
+    }
+
+    /// The number of bytes after the current position.
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    /// Skip to after the token `token`.
+    fn find(&mut self, token: &[u8]) -> bool {
+        while let Some(t) = self.token() {
+            if t == token {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// `n` bytes of binary data, which follow a single space.
+    fn binary(&mut self, n: usize) -> Option<&'a [u8]> {
+        let start = self.pos + 1;
+        let end = start.checked_add(n)?;
+        let data = self.data.get(start .. end)?;
+        self.pos = end;
+        Some(data)
+    }
+}
+
+fn name(token: &[u8]) -> Option<String> {
+    match token.split_first() {
+        Some((b'/', name)) => Some(String::from_utf8_lossy(name).into_owned()),
+        _ => None
+    }
+}
+
+/// The clear text and the binary eexec part of a PFB or PFA file.
+fn split(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    if data.starts_with(&[0x80, 1]) {
+        // PFB: segments with a header of 0x80, the type and the little endian length
+        let (mut clear, mut binary) = (vec![], vec![]);
+        let mut pos = 0;
+        while let Some(&[0x80, kind, a, b, c, d]) = data.get(pos .. pos + 6) {
+            let len = u32::from_le_bytes([a, b, c, d]) as usize;
+            let segment = try_opt!(data.get(pos + 6 .. pos + 6 + len));
+            match kind {
+                1 if binary.is_empty() => clear.extend_from_slice(segment),
+                2 => binary.extend_from_slice(segment),
+                _ => break
+            }
+            pos += 6 + len;
+        }
+        return Ok((clear, binary));
+    }
+
+    let eexec = match data.windows(5).position(|w| w == b"eexec") {
+        Some(pos) => pos + 5,
+        None => bail!("no eexec section")
+    };
+    let clear = data[.. eexec].to_vec();
+    let mut start = eexec;
+    while data.get(start).is_some_and(|b| b.is_ascii_whitespace()) {
+        start += 1;
+    }
+    let rest = &data[start ..];
+    if rest.len() >= 4 && rest[.. 4].iter().all(|b| b.is_ascii_hexdigit()) {
+        let digits: Vec<u8> = rest.iter().cloned()
+            .take_while(|b| b.is_ascii_hexdigit() || b.is_ascii_whitespace())
+            .filter(|b| b.is_ascii_hexdigit())
+            .collect();
+        let binary = digits.chunks_exact(2)
+            .map(|c| u8::from_str_radix(std::str::from_utf8(c).unwrap(), 16).unwrap())
+            .collect();
+        return Ok((clear, binary));
+    }
+    // the encrypted part may start with a whitespace byte, so see which start gives PostScript
+    let plausible = |start: usize| {
+        let sample = &data[start .. data.len().min(start + 68)];
+        decrypt(sample, EEXEC_KEY).iter().skip(4).all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+    };
+    let start = (eexec ..= start).rev().find(|&s| plausible(s)).unwrap_or(start);
+    Ok((clear, data[start ..].to_vec()))
+}
+
+#[derive(Debug)]
+pub struct Type1Font {
+    font_matrix: Matrix,
+    encoding: Option<Encoding>,
+    subrs: Vec<Vec<u8>>,
+    /// decrypted charstrings, `.notdef` first
+    glyphs: Vec<(String, Vec<u8>)>,
+    names: HashMap<String, u32>,
+}
+
+impl Type1Font {
+    pub fn parse(data: &[u8]) -> Result<Type1Font> {
+        let (clear, binary) = t!(split(data));
+
+        let mut font_matrix = Matrix { a: 0.001, d: 0.001, .. Matrix::default() };
+        let mut scanner = Scanner::new(&clear);
+        if scanner.find(b"/FontMatrix") && matches!(scanner.token(), Some(b"[") | Some(b"{")) {
+            let mut m = [0.; 6];
+            for v in m.iter_mut() {
+                *v = try_opt!(scanner.number());
+            }
+            font_matrix = Matrix { a: m[0], b: m[1], c: m[2], d: m[3], e: m[4], f: m[5] };
+        }
+
+        let mut encoding = None;
+        let mut scanner = Scanner::new(&clear);
+        if scanner.find(b"/Encoding") {
+            let mut differences = HashMap::new();
+            while let Some(token) = scanner.token() {
+                match token {
+                    b"StandardEncoding" => {
+                        encoding = Some(Encoding::standard());
+                        break;
+                    }
+                    b"dup" => {
+                        let code = scanner.number();
+                        let glyph = scanner.token().and_then(name);
+                        if let (Some(code), Some(glyph), Some(b"put")) = (code, glyph, scanner.token()) {
+                            differences.insert(code as u32, glyph);
+                        }
+                    }
+                    b"def" | b"readonly" => {
+                        encoding = Some(Encoding { base: BaseEncoding::None, differences });
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let private = decrypt(&binary, EEXEC_KEY);
+        let mut scanner = Scanner::new(private.get(4 ..).unwrap_or_default());
+        let mut len_iv = 4;
+        let mut subrs = vec![];
+        let mut glyphs = vec![];
+        let charstring = |data: &[u8], len_iv: i32| match len_iv {
+            n if n < 0 => data.to_vec(),
+            n => decrypt(data, CHARSTRING_KEY).get(n as usize ..).unwrap_or_default().to_vec()
+        };
+        while let Some(token) = scanner.token() {
+            match token {
+                b"/lenIV" => len_iv = try_opt!(scanner.number()) as i32,
+                b"/Subrs" => {
+                    // each one takes more than a byte
+                    let count = std::cmp::min(try_opt!(scanner.number()) as usize, scanner.remaining());
+                    subrs = vec![vec![]; count];
+                    // dup <index> <length> RD <data> NP
+                    for _ in 0 .. count {
+                        if !scanner.find(b"dup") {
+                            break;
+                        }
+                        let (index, len) = (try_opt!(scanner.number()) as usize, try_opt!(scanner.number()) as usize);
+                        scanner.token();
+                        let data = try_opt!(scanner.binary(len));
+                        if let Some(subr) = subrs.get_mut(index) {
+                            *subr = charstring(data, len_iv);
+                        }
+                    }
+                }
+                b"/CharStrings" => {
+                    if !scanner.find(b"begin") {
+                        break;
+                    }
+                    // /<name> <length> RD <data> ND
+                    while let Some(token) = scanner.token() {
+                        if token == b"end" {
+                            break;
+                        }
+                        if let Some(glyph) = name(token) {
+                            let len = try_opt!(scanner.number()) as usize;
+                            scanner.token();
+                            let data = try_opt!(scanner.binary(len));
+                            glyphs.push((glyph, charstring(data, len_iv)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(i) = glyphs.iter().position(|(name, _)| name == ".notdef") {
+            let notdef = glyphs.remove(i);
+            glyphs.insert(0, notdef);
+        }
+        let names = glyphs.iter().enumerate().map(|(gid, (name, _))| (name.clone(), gid as u32)).collect();
+
+        Ok(Type1Font { font_matrix, encoding, subrs, glyphs, names })
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn font_matrix(&self) -> Matrix {
+        self.font_matrix
+    }
+
+    pub fn encoding(&self) -> Option<&Encoding> {
+        self.encoding.as_ref()
+    }
+
+    pub fn glyph_name(&self, gid: u32) -> Option<&str> {
+        self.glyphs.get(gid as usize).map(|(name, _)| name.as_str())
+    }
+
+    pub fn gid_for_name(&self, name: &str) -> Option<u32> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn glyph(&self, gid: u32) -> Result<Option<Glyph>> {
+        self.glyph_with_accents(gid, true)
+    }
+
+    fn glyph_with_accents(&self, gid: u32, accents: bool) -> Result<Option<Glyph>> {
+        let code = match self.glyphs.get(gid as usize) {
+            Some((_, code)) => code,
+            None => return Ok(None)
+        };
+        let mut interpreter = Interpreter {
+            font: self,
+            stack: Vec::with_capacity(24),
+            ps_stack: vec![],
+            outline: Outline::default(),
+            pos: Point::default(),
+            width: 0.,
+            flex: None,
+            seac: None,
+        };
+        t!(interpreter.run(code, 0));
+        let Interpreter { outline, width, seac, .. } = interpreter;
+        let mut glyph = Glyph { path: outline.finish(), advance: width };
+
+        if let Some((asb, dx, dy, base, accent)) = seac {
+            if !accents {
+                bail!("nested seac");
+            }
+            let standard = |code: u8| BaseEncoding::StandardEncoding.glyph_name(code).and_then(|name| self.gid_for_name(name));
+            if let Some(base) = standard(base).map(|gid| self.glyph_with_accents(gid, false)).transpose()?.flatten() {
+                glyph.path.extend(base.path);
+            }
+            if let Some(mut accent) = standard(accent).map(|gid| self.glyph_with_accents(gid, false)).transpose()?.flatten() {
+                accent.transform(&Matrix { e: dx - asb, f: dy, .. Matrix::default() });
+                glyph.path.extend(accent.path);
+            }
+        }
+        Ok(Some(glyph))
+    }
+}
+
+struct Interpreter<'a> {
+    font: &'a Type1Font,
+    stack: Vec<f32>,
+    /// results of `callothersubr` for `pop`
+    ps_stack: Vec<f32>,
+    outline: Outline,
+    /// current point, which differs from the outline's in flex sequences
+    pos: Point,
+    width: f32,
+    /// the points of a flex sequence, while in one
+    flex: Option<Vec<Point>>,
+    seac: Option<(f32, f32, f32, u8, u8)>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn pop(&mut self) -> Result<f32> {
+        self.stack.pop().ok_or_else(|| other!("charstring stack underflow"))
+    }
+
+    fn arg(&self, i: usize) -> f32 {
+        self.stack.get(i).cloned().unwrap_or(0.)
+    }
+
+    fn move_by(&mut self, dx: f32, dy: f32) {
+        self.pos = Point { x: self.pos.x + dx, y: self.pos.y + dy };
+        match self.flex {
+            Some(ref mut points) => points.push(self.pos),
+            None => self.outline.move_to(self.pos),
+        }
+    }
+
+    fn line_by(&mut self, dx: f32, dy: f32) {
+        self.pos = Point { x: self.pos.x + dx, y: self.pos.y + dy };
+        self.outline.line_to(self.pos);
+    }
+
+    fn curve_by(&mut self, d: [f32; 6]) {
+        let c1 = Point { x: self.pos.x + d[0], y: self.pos.y + d[1] };
+        let c2 = Point { x: c1.x + d[2], y: c1.y + d[3] };
+        self.pos = Point { x: c2.x + d[4], y: c2.y + d[5] };
+        self.outline.curve_to(c1, c2, self.pos);
+    }
+
+    /// Returns whether the charstring ended.
+    fn run(&mut self, code: &[u8], depth: usize) -> Result<bool> {
+        if depth > MAX_SUBR_DEPTH {
+            bail!("charstring subroutines nested too deep");
+        }
+        let mut pos = 0;
+        while pos < code.len() {
+            let b0 = code[pos];
+            pos += 1;
+            match b0 {
+                32 ..= 246 => {
+                    self.stack.push(b0 as f32 - 139.);
+                    continue;
+                }
+                247 ..= 250 => {
+                    self.stack.push((b0 as f32 - 247.) * 256. + *try_opt!(code.get(pos)) as f32 + 108.);
+                    pos += 1;
+                    continue;
+                }
+                251 ..= 254 => {
+                    self.stack.push(-(b0 as f32 - 251.) * 256. - *try_opt!(code.get(pos)) as f32 - 108.);
+                    pos += 1;
+                    continue;
+                }
+                255 => {
+                    self.stack.push(t!(read_u32(code, pos)) as i32 as f32);
+                    pos += 4;
+                    continue;
+                }
+                // hsbw
+                13 => {
+                    self.pos = Point { x: self.arg(0), y: 0. };
+                    self.width = self.arg(1);
+                }
+                // closepath
+                9 => self.outline.close(),
+                // rmoveto, hmoveto, vmoveto
+                21 => self.move_by(self.arg(0), self.arg(1)),
+                22 => self.move_by(self.arg(0), 0.),
+                4 => self.move_by(0., self.arg(0)),
+                // rlineto, hlineto, vlineto
+                5 => self.line_by(self.arg(0), self.arg(1)),
+                6 => self.line_by(self.arg(0), 0.),
+                7 => self.line_by(0., self.arg(0)),
+                // rrcurveto, vhcurveto, hvcurveto
+                8 => self.curve_by([self.arg(0), self.arg(1), self.arg(2), self.arg(3), self.arg(4), self.arg(5)]),
+                30 => self.curve_by([0., self.arg(0), self.arg(1), self.arg(2), self.arg(3), 0.]),
+                31 => self.curve_by([self.arg(0), 0., self.arg(1), self.arg(2), 0., self.arg(3)]),
+                // callsubr
+                10 => {
+                    let index = self.pop()? as usize;
+                    let font = self.font;
+                    let subr = match font.subrs.get(index) {
+                        Some(subr) => subr,
+                        None => bail!("invalid subroutine {}", index)
+                    };
+                    if t!(self.run(subr, depth + 1)) {
+                        return Ok(true);
+                    }
+                    // the subroutine may leave arguments
+                    continue;
+                }
+                // return
+                11 => return Ok(false),
+                // endchar
+                14 => {
+                    self.outline.close();
+                    return Ok(true);
+                }
+                12 => {
+                    let b1 = *try_opt!(code.get(pos));
+                    pos += 1;
+                    match b1 {
+                        // sbw
+                        7 => {
+                            self.pos = Point { x: self.arg(0), y: self.arg(1) };
+                            self.width = self.arg(2);
+                        }
+                        // seac
+                        6 => {
+                            self.seac = Some((self.arg(0), self.arg(1), self.arg(2), self.arg(3) as u8, self.arg(4) as u8));
+                            self.outline.close();
+                            return Ok(true);
+                        }
+                        // div
+                        12 => {
+                            let (b, a) = (self.pop()?, self.pop()?);
+                            self.stack.push(if b != 0. { a / b } else { 0. });
+                            continue;
+                        }
+                        // callothersubr
+                        16 => {
+                            let other = self.pop()? as i32;
+                            let n = self.pop()? as usize;
+                            if n > self.stack.len() {
+                                bail!("charstring stack underflow");
+                            }
+                            let args = self.stack.split_off(self.stack.len() - n);
+                            self.other_subr(other, args);
+                            continue;
+                        }
+                        // pop
+                        17 => {
+                            let v = self.ps_stack.pop().unwrap_or(0.);
+                            self.stack.push(v);
+                            continue;
+                        }
+                        // setcurrentpoint
+                        33 => self.pos = Point { x: self.arg(0), y: self.arg(1) },
+                        // dotsection, vstem3, hstem3
+                        _ => {}
+                    }
+                }
+                // hstem, vstem and reserved operators
+                _ => {}
+            }
+            self.stack.clear();
+        }
+        Ok(false)
+    }
+
+    fn other_subr(&mut self, other: i32, args: Vec<f32>) {
+        match other {
+            // end of flex: two curves through the collected points
+            0 => {
+                if let Some(points) = self.flex.take() {
+                    if points.len() >= 7 {
+                        self.outline.curve_to(points[1], points[2], points[3]);
+                        self.outline.curve_to(points[4], points[5], points[6]);
+                    }
+                }
+                // end point for setcurrentpoint
+                if args.len() >= 3 {
+                    self.ps_stack.push(args[2]);
+                    self.ps_stack.push(args[1]);
+                }
+            }
+            1 => self.flex = Some(vec![]),
+            2 => {}
+            // hint replacement and unknown othersubrs return their arguments
+            _ => self.ps_stack.extend(args.into_iter().rev()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::program::PathOp;
+
+    fn encrypt(data: &[u8], mut r: u16) -> Vec<u8> {
+        data.iter().map(|&p| {
+            let c = p ^ (r >> 8) as u8;
+            r = (c as u16).wrapping_add(r).wrapping_mul(52845).wrapping_add(22719);
+            c
+        }).collect()
+    }
+
+    #[test]
+    fn decrypt_roundtrip() {
+        let data = b"dup /Private 8 dict dup begin";
+        assert_eq!(decrypt(&encrypt(data, EEXEC_KEY), EEXEC_KEY), data);
+    }
+
+    #[test]
+    fn parse_glyph() {
+        // 0 500 hsbw 100 100 rmoveto 300 hlineto 300 vlineto -300 hlineto closepath endchar
+        let square = [139, 248, 136, 13, 239, 239, 21, 247, 192, 6, 247, 192, 7, 251, 192, 6, 9, 14];
+        let mut charstring = vec![0; 4];
+        charstring.extend_from_slice(&square);
+        let charstring = encrypt(&charstring, CHARSTRING_KEY);
+
+        let mut private = b"....dup /Private 2 dict dup begin /Subrs 0 array\n2 index /CharStrings 2 dict dup begin\n".to_vec();
+        private.extend_from_slice(format!("/square {} RD ", charstring.len()).as_bytes());
+        private.extend_from_slice(&charstring);
+        private.extend_from_slice(b" ND\nend end\nmark currentfile closefile\n");
+
+        let mut font = b"%!FontType1-1.0: Test\n/FontMatrix [0.001 0 0 0.001 0 0] readonly def\n/Encoding 256 array\n0 1 255 {1 index exch /.notdef put} for\ndup 65 /square put\nreadonly def\ncurrentfile eexec\n".to_vec();
+        font.extend_from_slice(&encrypt(&private, EEXEC_KEY));
+
+        let font = Type1Font::parse(&font).unwrap();
+        assert_eq!(font.encoding().unwrap().glyph_name(65), Some("square"));
+        let gid = font.gid_for_name("square").unwrap();
+        let glyph = font.glyph(gid).unwrap().unwrap();
+        assert_eq!(glyph.advance, 500.);
+        let p = |x, y| Point { x, y };
+        assert_eq!(glyph.path, [
+            PathOp::MoveTo(p(100., 100.)),
+            PathOp::LineTo(p(400., 100.)),
+            PathOp::LineTo(p(400., 400.)),
+            PathOp::LineTo(p(100., 400.)),
+            PathOp::Close,
+        ]);
+    }
+}
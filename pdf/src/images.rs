@@ -0,0 +1,333 @@
+//! Image extraction
+//!
+//! Collects the images of pages, from the XObjects in their resources (including those of form
+//! XObjects) and from inline images in the content streams. Each one is encoded as a file:
+//! JPEG and JPEG 2000 data is passed through as is, CCITT group 4 data is wrapped in a TIFF file
+//! and everything else is converted to sRGB and written as PNG. CCITT group 3 images are not
+//! supported and skipped.
+
+use std::collections::HashSet;
+
+use crate::backend::Backend;
+use crate::content::Op;
+use crate::error::Result;
+use crate::file::File;
+use crate::object::{ColorSpace, ImageXObject, Page, PlainRef, Resolve, Resources, XObject};
+use crate::primitive::Primitive;
+
+/// Nesting limit for form XObjects, so recursive forms do not loop forever.
+const MAX_FORM_DEPTH: usize = 16;
+
+/// The file format of an [`ExtractedImage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    /// JPEG 2000 in the JP2 container
+    Jp2,
+    /// a bare JPEG 2000 codestream
+    J2k,
+    Tiff,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Jp2 => "jp2",
+            ImageFormat::J2k => "j2k",
+            ImageFormat::Tiff => "tif",
+        }
+    }
+}
+
+/// An image, encoded as a file.
+#[derive(Debug, Clone)]
+pub struct ExtractedImage {
+    /// The index of the page the image was found on.
+    pub page: u32,
+
+    /// The image XObject, `None` for inline images.
+    pub id: Option<PlainRef>,
+
+    pub width: u32,
+    pub height: u32,
+
+    pub format: ImageFormat,
+
+    /// The contents of the file.
+    pub data: Vec<u8>,
+}
+
+/// Extract the images of all pages. Images used on several pages are only reported for the
+/// first one.
+///
+/// Images that can't be decoded are skipped with a warning.
+pub fn document_images<B: Backend>(file: &File<B>) -> Result<Vec<ExtractedImage>> {
+    let mut extractor = Extractor { resolve: file, page: 0, seen: HashSet::new(), images: vec![] };
+    for (nr, page) in file.pages().enumerate() {
+        extractor.page = nr as u32;
+        let result = page.and_then(|page| extractor.page(&page));
+        if let Err(e) = result {
+            warn!("can't extract the images of page {}: {}", nr, e);
+        }
+    }
+    Ok(extractor.images)
+}
+
+/// Extract the images of `page`, which has the index `page_nr`.
+///
+/// Images that can't be decoded are skipped with a warning.
+pub fn page_images(page: &Page, page_nr: u32, resolve: &impl Resolve) -> Result<Vec<ExtractedImage>> {
+    let mut extractor = Extractor { resolve, page: page_nr, seen: HashSet::new(), images: vec![] };
+    if let Err(e) = extractor.page(page) {
+        warn!("can't extract the images of page {}: {}", page_nr, e);
+    }
+    Ok(extractor.images)
+}
+
+struct Extractor<'a, R> {
+    resolve: &'a R,
+    page: u32,
+    /// XObjects that were visited already
+    seen: HashSet<PlainRef>,
+    images: Vec<ExtractedImage>,
+}
+
+impl<'a, R: Resolve> Extractor<'a, R> {
+    fn page(&mut self, page: &Page) -> Result<()> {
+        let resources = t!(page.resources());
+        // the XObjects in the resources can be found without the content stream
+        let ops = match page.contents {
+            Some(ref contents) => contents.operations(self.resolve).unwrap_or_else(|e| {
+                warn!("can't read the contents of page {}: {}", self.page, e);
+                &[]
+            }),
+            None => &[]
+        };
+        self.walk(ops, resources, 0);
+        Ok(())
+    }
+
+    fn walk(&mut self, ops: &[Op], resources: &Resources, depth: usize) {
+        for op in ops {
+            if let Op::InlineImage { ref image } = *op {
+                self.add(image, None, resources);
+            }
+        }
+
+        let mut xobjects: Vec<_> = resources.xobjects.iter().collect();
+        xobjects.sort_by_key(|&(name, _)| name);
+        for (name, &r) in xobjects {
+            if !self.seen.insert(r.get_inner()) {
+                continue;
+            }
+            let xobject = match self.resolve.get(r) {
+                Ok(xobject) => xobject,
+                Err(e) => {
+                    warn!("can't read XObject {}: {}", name, e);
+                    continue;
+                }
+            };
+            match *xobject {
+                XObject::Image(ref image) => self.add(image, Some(r.get_inner()), resources),
+                XObject::Form(ref form) if depth < MAX_FORM_DEPTH => {
+                    let resources = form.dict().resources.as_deref().unwrap_or(resources);
+                    self.walk(&form.operations, resources, depth + 1);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn add(&mut self, image: &ImageXObject, id: Option<PlainRef>, resources: &Resources) {
+        match encode(image, resources, self.resolve) {
+            Ok((format, data)) => self.images.push(ExtractedImage {
+                page: self.page,
+                id,
+                width: image.width as u32,
+                height: image.height as u32,
+                format,
+                data,
+            }),
+            Err(e) => warn!("can't extract image {:?} on page {}: {}", id, self.page, e)
+        }
+    }
+}
+
+fn encode(image: &ImageXObject, resources: &Resources, resolve: &impl Resolve) -> Result<(ImageFormat, Vec<u8>)> {
+    if let Some(data) = image.as_jpeg() {
+        return Ok((ImageFormat::Jpeg, data.to_vec()));
+    }
+    if let Some(data) = image.as_jpx() {
+        let format = match data.get(4 .. 8) {
+            Some(b"jP  ") => ImageFormat::Jp2,
+            _ => ImageFormat::J2k,
+        };
+        return Ok((format, data.to_vec()));
+    }
+    match image.as_ccitt() {
+        Some((data, params)) if params.k < 0 && !params.encoded_byte_align => {
+            // black runs are painted black, unless the samples are inverted
+            let inverted = params.black_is_1 != matches!(image.decode.as_deref(), Some([a, b, ..]) if a > b);
+            return Ok((ImageFormat::Tiff, tiff_g4(image.width as u32, image.height as u32, inverted, data)));
+        }
+        Some((_, params)) if params.k >= 0 => bail!("CCITT group 3 images (K = {}) are not supported", params.k),
+        _ => {}
+    }
+
+    // inline images can name a colour space of the resources
+    let named = match image.color_space {
+        Some(ColorSpace::Other(ref p)) => match p.as_slice() {
+            [Primitive::Name(name)] => resources.color_spaces.get(name),
+            _ => None
+        },
+        _ => None
+    };
+    let rgba = match named {
+        Some(cs) => {
+            let mut image = image.clone();
+            image.info.info.color_space = Some(cs.clone());
+            t!(image.to_rgba8(resolve))
+        }
+        None => t!(image.to_rgba8(resolve))
+    };
+    Ok((ImageFormat::Png, png(image.width as u32, image.height as u32, &rgba)))
+}
+
+/// A PNG file of 8 bit RGBA pixels, without the alpha channel if the image is opaque and as
+/// grayscale if it is gray.
+fn png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let opaque = rgba.chunks_exact(4).all(|p| p[3] == 255);
+    let gray = opaque && rgba.chunks_exact(4).all(|p| p[0] == p[1] && p[1] == p[2]);
+    let (color_type, channels) = match (opaque, gray) {
+        (true, true) => (0, 1),
+        (true, false) => (2, 3),
+        _ => (6, 4),
+    };
+
+    let mut raw = Vec::with_capacity((width as usize * channels + 1) * height as usize);
+    for row in rgba.chunks_exact(4 * width as usize) {
+        // no filter
+        raw.push(0);
+        for pixel in row.chunks_exact(4) {
+            raw.extend_from_slice(&pixel[.. channels]);
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [(b"IHDR", ihdr), (b"IDAT", deflate::deflate_bytes_zlib(&raw)), (b"IEND", vec![])] {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&data);
+        let crc = crc32(&out[start ..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0 .. 8 {
+            crc = if crc & 1 != 0 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// A little endian TIFF file with a single strip of group 4 fax data.
+fn tiff_g4(width: u32, height: u32, inverted: bool, data: &[u8]) -> Vec<u8> {
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    let photometric = if inverted { 1 } else { 0 };
+    let entries: [(u16, u16, u32); 11] = [
+        (256, LONG, width),
+        (257, LONG, height),
+        // BitsPerSample
+        (258, SHORT, 1),
+        // Compression
+        (259, SHORT, 4),
+        // PhotometricInterpretation, 0 is white
+        (262, SHORT, photometric),
+        // StripOffsets, after the directory
+        (273, LONG, 8 + 2 + 11 * 12 + 4),
+        // SamplesPerPixel
+        (277, SHORT, 1),
+        (278, LONG, height),
+        // StripByteCounts
+        (279, LONG, data.len() as u32),
+        // ResolutionUnit none
+        (296, SHORT, 1),
+        // T6Options
+        (293, LONG, 0),
+    ];
+    let mut entries = entries.to_vec();
+    entries.sort_by_key(|e| e.0);
+
+    let mut out = b"II\x2a\x00".to_vec();
+    out.extend_from_slice(&8u32.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, typ, value) in entries {
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&typ.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        match typ {
+            SHORT => {
+                out.extend_from_slice(&(value as u16).to_le_bytes());
+                out.extend_from_slice(&[0, 0]);
+            }
+            _ => out.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    // no further directory
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn png_chunks() {
+        let file = png(2, 1, &[10, 10, 10, 255, 200, 200, 200, 255]);
+        assert_eq!(&file[.. 8], b"\x89PNG\r\n\x1a\n");
+        // gray, 8 bits
+        assert_eq!(&file[8 .. 33], b"\0\0\0\x0dIHDR\0\0\0\x02\0\0\0\x01\x08\0\0\0\0\xd1\x49\x20\x56");
+        let len = u32::from_be_bytes([file[33], file[34], file[35], file[36]]) as usize;
+        assert_eq!(&file[37 .. 41], b"IDAT");
+        assert_eq!(inflate::inflate_bytes_zlib(&file[41 .. 41 + len]).unwrap(), [0, 10, 200]);
+        assert!(file.ends_with(b"\0\0\0\0IEND\xae\x42\x60\x82"));
+
+        // translucent
+        let file = png(1, 1, &[1, 2, 3, 4]);
+        assert_eq!(file[25], 6);
+    }
+
+    #[test]
+    fn tiff() {
+        let file = tiff_g4(16, 2, false, &[1, 2, 3]);
+        assert_eq!(&file[.. 8], b"II\x2a\x00\x08\0\0\0");
+        assert_eq!(file.len(), 8 + 2 + 11 * 12 + 4 + 3);
+        assert!(file.ends_with(&[1, 2, 3]));
+        // the tags are sorted
+        let tags: Vec<u16> = (0 .. 11).map(|i| u16::from_le_bytes([file[10 + 12 * i], file[11 + 12 * i]])).collect();
+        assert!(tags.windows(2).all(|w| w[0] < w[1]), "{:?}", tags);
+    }
+}
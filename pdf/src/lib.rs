@@ -4,6 +4,7 @@
 #[macro_use] extern crate pdf_derive;
 #[macro_use] extern crate snafu;
 #[macro_use] extern crate log;
+#[macro_use] extern crate bitflags;
 
 #[macro_use]
 pub mod error;
@@ -18,9 +19,13 @@ pub mod font;
 pub mod any;
 pub mod encoding;
 pub mod build;
+pub mod text;
+pub mod cmap;
+pub mod images;
 
 // mod content;
 mod enc;
+mod repair;
 pub mod crypt;
 
 // pub use content::*;
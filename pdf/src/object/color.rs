@@ -2,6 +2,11 @@ use crate as pdf;
 use crate::object::*;
 use crate::error::*;
 
+use std::collections::HashMap;
+
+mod icc;
+pub use self::icc::IccProfile;
+
 #[derive(Object, Debug)]
 pub struct IccInfo {
     #[pdf(key="N")]
@@ -17,15 +22,62 @@ pub struct IccInfo {
     pub metadata: Option<Stream<()>>,
 }
 
+#[derive(Object, ObjectWrite, Debug, Clone)]
+pub struct CalGrayInfo {
+    #[pdf(key="WhitePoint")]
+    pub white_point: Vec<f32>,
+
+    #[pdf(key="BlackPoint")]
+    pub black_point: Option<Vec<f32>>,
+
+    #[pdf(key="Gamma", default="1.")]
+    pub gamma: f32,
+}
+
+#[derive(Object, ObjectWrite, Debug, Clone)]
+pub struct CalRGBInfo {
+    #[pdf(key="WhitePoint")]
+    pub white_point: Vec<f32>,
+
+    #[pdf(key="BlackPoint")]
+    pub black_point: Option<Vec<f32>>,
+
+    /// one per component
+    #[pdf(key="Gamma")]
+    pub gamma: Option<Vec<f32>>,
+
+    /// `XA YA ZA XB YB ZB XC YC ZC`
+    #[pdf(key="Matrix")]
+    pub matrix: Option<Vec<f32>>,
+}
+
+#[derive(Object, ObjectWrite, Debug, Clone)]
+pub struct LabInfo {
+    #[pdf(key="WhitePoint")]
+    pub white_point: Vec<f32>,
+
+    #[pdf(key="BlackPoint")]
+    pub black_point: Option<Vec<f32>>,
+
+    /// `amin amax bmin bmax`
+    #[pdf(key="Range")]
+    pub range: Option<Vec<f32>>,
+}
+
 #[derive(Debug, Clone)]
 pub enum ColorSpace {
     DeviceGray,
     DeviceRGB,
     DeviceCMYK,
+    CalGray(CalGrayInfo),
+    CalRGB(CalRGBInfo),
+    Lab(LabInfo),
     DeviceN { names: Vec<String>, alt: Box<ColorSpace>, tint: Function, attr: Option<Dictionary> },
     Indexed(Box<ColorSpace>, Vec<u8>),
     Separation(String, Box<ColorSpace>, Function),
     Icc(RcRef<Stream<IccInfo>>),
+    /// With the colour space of uncolored patterns.
+    Pattern(Option<Box<ColorSpace>>),
     Other(Vec<Primitive>)
 }
 
@@ -41,7 +93,9 @@ impl Object for ColorSpace {
                 "DeviceGray" => ColorSpace::DeviceGray,
                 "DeviceRGB" => ColorSpace::DeviceRGB,
                 "DeviceCMYK" => ColorSpace::DeviceCMYK,
-                _ => unimplemented!()
+                "Pattern" => ColorSpace::Pattern(None),
+                // e.g. the name of a colour space in the resources, used by inline images
+                _ => ColorSpace::Other(vec![p])
             };
             return Ok(cs);
         }
@@ -49,6 +103,16 @@ impl Object for ColorSpace {
         let typ = t!(t!(get_index(&arr, 0)).as_name());
         
         match typ {
+            "CalGray" => Ok(ColorSpace::CalGray(t!(CalGrayInfo::from_primitive(t!(get_index(&arr, 1)).clone(), resolve)))),
+            "CalRGB" => Ok(ColorSpace::CalRGB(t!(CalRGBInfo::from_primitive(t!(get_index(&arr, 1)).clone(), resolve)))),
+            "Lab" => Ok(ColorSpace::Lab(t!(LabInfo::from_primitive(t!(get_index(&arr, 1)).clone(), resolve)))),
+            "Pattern" => {
+                let base = match arr.get(1) {
+                    Some(p) => Some(t!(Object::from_primitive(p.clone(), resolve))),
+                    None => None
+                };
+                Ok(ColorSpace::Pattern(base))
+            }
             "Indexed" => {
                 let base = t!(Object::from_primitive(t!(get_index(&arr, 1)).clone(), resolve));
                 let lookup = match t!(get_index(&arr, 3)) {
@@ -91,11 +155,469 @@ impl Object for ColorSpace {
     }
 }
 impl ObjectWrite for ColorSpace {
-    fn to_primitive(&self, _update: &mut impl Updater) -> Result<Primitive> {
+    fn to_primitive(&self, update: &mut impl Updater) -> Result<Primitive> {
+        let arr = match *self {
+            ColorSpace::DeviceCMYK => return Ok(Primitive::name("DeviceCMYK")),
+            ColorSpace::DeviceRGB => return Ok(Primitive::name("DeviceRGB")),
+            ColorSpace::DeviceGray => return Ok(Primitive::name("DeviceGray")),
+            ColorSpace::Pattern(None) => return Ok(Primitive::name("Pattern")),
+            ColorSpace::CalGray(ref info) => vec![Primitive::name("CalGray"), t!(info.to_primitive(update))],
+            ColorSpace::CalRGB(ref info) => vec![Primitive::name("CalRGB"), t!(info.to_primitive(update))],
+            ColorSpace::Lab(ref info) => vec![Primitive::name("Lab"), t!(info.to_primitive(update))],
+            ColorSpace::Pattern(Some(ref base)) => vec![Primitive::name("Pattern"), t!(base.to_primitive(update))],
+            ColorSpace::Indexed(ref base, ref lookup) => {
+                let n = base.components().max(1);
+                if lookup.is_empty() || lookup.len() % n != 0 {
+                    bail!("lookup table of {} bytes for {} components", lookup.len(), n);
+                }
+                vec![
+                    Primitive::name("Indexed"),
+                    t!(base.to_primitive(update)),
+                    Primitive::Integer((lookup.len() / n - 1) as i32),
+                    Primitive::String(PdfString::new(lookup.clone())),
+                ]
+            }
+            ColorSpace::Icc(ref icc) => vec![Primitive::name("ICCBased"), t!(icc.to_primitive(update))],
+            ColorSpace::Other(ref arr) => match arr.as_slice() {
+                [name @ Primitive::Name(_)] => return Ok(name.clone()),
+                _ => arr.clone()
+            },
+            // functions can't be written yet
+            ColorSpace::Separation(..) | ColorSpace::DeviceN { .. } => bail!("writing Separation/DeviceN colour spaces is not supported")
+        };
+        Ok(Primitive::Array(arr))
+    }
+}
+
+impl ColorSpace {
+    /// The number of components of a colour, 0 for colored patterns and unknown colour spaces.
+    pub fn components(&self) -> usize {
+        match *self {
+            ColorSpace::DeviceGray | ColorSpace::CalGray(_) => 1,
+            ColorSpace::DeviceRGB | ColorSpace::CalRGB(_) | ColorSpace::Lab(_) => 3,
+            ColorSpace::DeviceCMYK => 4,
+            ColorSpace::DeviceN { ref names, .. } => names.len(),
+            ColorSpace::Indexed(..) | ColorSpace::Separation(..) => 1,
+            ColorSpace::Icc(ref icc) => icc.info.info.components as usize,
+            ColorSpace::Pattern(Some(ref base)) => base.components(),
+            ColorSpace::Pattern(None) | ColorSpace::Other(_) => 0,
+        }
+    }
+
+    /// The range of component `i`, which the bytes of an `Indexed` lookup table are scaled to.
+    pub(crate) fn component_range(&self, i: usize) -> (f32, f32) {
         match *self {
-            ColorSpace::DeviceCMYK => Ok(Primitive::name("DeviceCMYK")),
-            ColorSpace::DeviceRGB => Ok(Primitive::name("DeviceRGB")),
-            _ => unimplemented!()
+            ColorSpace::Lab(ref lab) => match i {
+                0 => (0., 100.),
+                _ => {
+                    let range = lab.range();
+                    (range[2 * i - 2], range[2 * i - 1])
+                }
+            },
+            ColorSpace::Icc(ref icc) => match icc.info.info.range {
+                Some(ref range) if range.len() > 2 * i + 1 => (range[2 * i], range[2 * i + 1]),
+                _ => (0., 1.)
+            },
+            _ => (0., 1.)
+        }
+    }
+
+    /// Convert `color` to sRGB, with components from 0 to 1.
+    ///
+    /// Calibrated colours are adapted from their white point to D65, the black point is ignored.
+    /// ICC based colours use the alternate colour space, CMYK is converted naively.
+    pub fn to_rgb(&self, color: &[f32]) -> Result<[f32; 3]> {
+        let n = self.components();
+        if color.len() < n {
+            bail!("{} components for a colour space with {}", color.len(), n);
+        }
+        let c = |i: usize| color[i].clamp(0., 1.);
+        Ok(match *self {
+            ColorSpace::DeviceGray => [c(0); 3],
+            ColorSpace::DeviceRGB => [c(0), c(1), c(2)],
+            ColorSpace::DeviceCMYK => {
+                let k = 1. - c(3);
+                [(1. - c(0)) * k, (1. - c(1)) * k, (1. - c(2)) * k]
+            }
+            ColorSpace::CalGray(ref cal) => {
+                let white = t!(white_point(&cal.white_point));
+                let y = c(0).powf(cal.gamma);
+                xyz_to_srgb([white[0] * y, white[1] * y, white[2] * y], white)
+            }
+            ColorSpace::CalRGB(ref cal) => {
+                let white = t!(white_point(&cal.white_point));
+                let gamma = |i: usize| cal.gamma.as_ref().and_then(|g| g.get(i).cloned()).unwrap_or(1.);
+                let abc = [c(0).powf(gamma(0)), c(1).powf(gamma(1)), c(2).powf(gamma(2))];
+                let m = match cal.matrix {
+                    Some(ref m) if m.len() == 9 => m.as_slice(),
+                    _ => &[1., 0., 0., 0., 1., 0., 0., 0., 1.]
+                };
+                let xyz = [
+                    m[0] * abc[0] + m[3] * abc[1] + m[6] * abc[2],
+                    m[1] * abc[0] + m[4] * abc[1] + m[7] * abc[2],
+                    m[2] * abc[0] + m[5] * abc[1] + m[8] * abc[2],
+                ];
+                xyz_to_srgb(xyz, white)
+            }
+            ColorSpace::Lab(ref lab) => {
+                let white = t!(white_point(&lab.white_point));
+                let range = lab.range();
+                let l = color[0].clamp(0., 100.);
+                let a = color[1].clamp(range[0], range[1]);
+                let b = color[2].clamp(range[2], range[3]);
+                xyz_to_srgb(lab_to_xyz([l, a, b], white), white)
+            }
+            ColorSpace::Indexed(ref base, ref lookup) => {
+                let n = base.components();
+                let max = (lookup.len() / n.max(1)).saturating_sub(1);
+                let index = (color[0].round().max(0.) as usize).min(max);
+                let entry = match lookup.get(n * index .. n * (index + 1)) {
+                    Some(entry) => entry,
+                    None => bail!("empty lookup table")
+                };
+                let values: Vec<f32> = entry.iter().enumerate().map(|(i, &b)| {
+                    let (low, high) = base.component_range(i);
+                    low + b as f32 / 255. * (high - low)
+                }).collect();
+                return base.to_rgb(&values);
+            }
+            ColorSpace::Separation(_, ref alt, ref tint) => {
+                let mut values = vec![0.; alt.components()];
+                t!(tint.apply(&color[.. 1], &mut values));
+                return alt.to_rgb(&values);
+            }
+            ColorSpace::DeviceN { ref alt, ref tint, .. } => {
+                let mut values = vec![0.; alt.components()];
+                t!(tint.apply(&color[.. n], &mut values));
+                return alt.to_rgb(&values);
+            }
+            ColorSpace::Icc(ref icc) => match icc.info.info.alternate {
+                Some(ref alt) => return alt.to_rgb(color),
+                None => match n {
+                    1 => [c(0); 3],
+                    3 => [c(0), c(1), c(2)],
+                    4 => return ColorSpace::DeviceCMYK.to_rgb(color),
+                    _ => bail!("ICC based colour space with {} components", n)
+                }
+            },
+            ColorSpace::Pattern(Some(ref base)) => return base.to_rgb(color),
+            ColorSpace::Pattern(None) => bail!("colored patterns have no colour components"),
+            ColorSpace::Other(ref p) => bail!("unsupported colour space {:?}", p),
+        })
+    }
+}
+
+/// Converts colours of a colour space to sRGB like [`ColorSpace::to_rgb`], with the embedded ICC
+/// profiles.
+///
+/// Profiles that can't be used fall back to their `Alternate`. Indexed colours are converted once,
+/// when the transform is created.
+#[derive(Debug)]
+pub struct ColorTransform {
+    kind: TransformKind,
+    components: usize,
+}
+
+#[derive(Debug)]
+enum TransformKind {
+    Icc(Box<IccProfile>, RenderingIntent),
+    Indexed(Vec<[f32; 3]>),
+    Tint(Function, usize, Box<ColorTransform>),
+    Plain(ColorSpace),
+}
+
+impl ColorTransform {
+    pub fn new(cs: &ColorSpace, intent: RenderingIntent) -> Result<ColorTransform> {
+        let kind = match *cs {
+            ColorSpace::Icc(ref stream) => {
+                let info = &stream.info.info;
+                match stream.data().and_then(IccProfile::parse) {
+                    Ok(profile) if profile.components() == info.components as usize => TransformKind::Icc(Box::new(profile), intent),
+                    result => {
+                        match result {
+                            Ok(profile) => warn!("ICC profile with {} components for N = {}", profile.components(), info.components),
+                            Err(e) => warn!("can't use ICC profile: {:?}", e),
+                        }
+                        match info.alternate {
+                            Some(ref alt) => return ColorTransform::new(alt, intent),
+                            None => TransformKind::Plain(cs.clone())
+                        }
+                    }
+                }
+            }
+            ColorSpace::Indexed(ref base, ref lookup) => {
+                let base_transform = t!(ColorTransform::new(base, intent));
+                let n = base.components().max(1);
+                let mut palette = Vec::with_capacity(lookup.len() / n);
+                for entry in lookup.chunks_exact(n) {
+                    let values: Vec<f32> = entry.iter().enumerate().map(|(i, &b)| {
+                        let (low, high) = base.component_range(i);
+                        low + b as f32 / 255. * (high - low)
+                    }).collect();
+                    palette.push(t!(base_transform.apply(&values)));
+                }
+                if palette.is_empty() {
+                    bail!("empty lookup table");
+                }
+                TransformKind::Indexed(palette)
+            }
+            ColorSpace::Separation(_, ref alt, ref tint) | ColorSpace::DeviceN { ref alt, ref tint, .. } => {
+                TransformKind::Tint(tint.clone(), alt.components(), Box::new(t!(ColorTransform::new(alt, intent))))
+            }
+            ColorSpace::Pattern(Some(ref base)) => return ColorTransform::new(base, intent),
+            _ => TransformKind::Plain(cs.clone())
+        };
+        Ok(ColorTransform { kind, components: cs.components() })
+    }
+
+    /// The number of components of a colour.
+    pub fn components(&self) -> usize {
+        self.components
+    }
+
+    /// Convert `color` to sRGB, with components from 0 to 1.
+    pub fn apply(&self, color: &[f32]) -> Result<[f32; 3]> {
+        if color.len() < self.components {
+            bail!("{} components for a colour space with {}", color.len(), self.components);
+        }
+        match self.kind {
+            TransformKind::Icc(ref profile, intent) => profile.to_rgb(color, intent),
+            TransformKind::Indexed(ref palette) => {
+                let index = (color[0].round().max(0.) as usize).min(palette.len() - 1);
+                Ok(palette[index])
+            }
+            TransformKind::Tint(ref tint, n, ref alt) => {
+                let mut values = vec![0.; n];
+                t!(tint.apply(&color[.. self.components], &mut values));
+                alt.apply(&values)
+            }
+            TransformKind::Plain(ref cs) => cs.to_rgb(color),
+        }
+    }
+
+    /// Convert 8 bit samples to 8 bit RGB. Each component goes from 0 to 255, except for indexed
+    /// colours, where the samples are the index.
+    pub fn apply_u8(&self, samples: &[u8]) -> Result<Vec<u8>> {
+        let n = self.components.max(1);
+        let indexed = matches!(self.kind, TransformKind::Indexed(_));
+        let mut out = Vec::with_capacity(samples.len() / n * 3);
+        let mut cache: HashMap<&[u8], [u8; 3]> = HashMap::new();
+        let mut color = vec![0.; n];
+        for pixel in samples.chunks_exact(n) {
+            let rgb = match cache.get(pixel) {
+                Some(&rgb) => rgb,
+                None => {
+                    for (c, &s) in color.iter_mut().zip(pixel) {
+                        *c = if indexed { s as f32 } else { s as f32 / 255. };
+                    }
+                    let rgb = t!(self.apply(&color)).map(|v| (v * 255.).round() as u8);
+                    cache.insert(pixel, rgb);
+                    rgb
+                }
+            };
+            out.extend_from_slice(&rgb);
+        }
+        Ok(out)
+    }
+}
+
+impl LabInfo {
+    /// `amin amax bmin bmax`, -100 to 100 by default
+    fn range(&self) -> [f32; 4] {
+        match self.range {
+            Some(ref r) if r.len() == 4 && r.iter().all(|v| v.is_finite()) => {
+                // inverted ranges are swapped, clamping needs min <= max
+                [r[0].min(r[1]), r[0].max(r[1]), r[2].min(r[3]), r[2].max(r[3])]
+            }
+            _ => [-100., 100., -100., 100.]
+        }
+    }
+}
+
+fn white_point(white: &[f32]) -> Result<[f32; 3]> {
+    match *white {
+        [x, y, z] if y > 0. => Ok([x, y, z]),
+        _ => bail!("invalid white point {:?}", white)
+    }
+}
+
+fn lab_to_xyz([l, a, b]: [f32; 3], white: [f32; 3]) -> [f32; 3] {
+    let g = |x: f32| if x >= 6. / 29. { x * x * x } else { 108. / 841. * (x - 4. / 29.) };
+    let m = (l + 16.) / 116.;
+    [white[0] * g(m + a / 500.), white[1] * g(m), white[2] * g(m - b / 200.)]
+}
+
+/// XYZ relative to `white` to sRGB, with the Bradford transform from `white` to D65.
+fn xyz_to_srgb(xyz: [f32; 3], white: [f32; 3]) -> [f32; 3] {
+    const BRADFORD: [[f32; 3]; 3] = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    const BRADFORD_INVERSE: [[f32; 3]; 3] = [
+        [0.9869929, -0.1470543, 0.1599627],
+        [0.4323053, 0.5183603, 0.0492912],
+        [-0.0085287, 0.0400428, 0.9684867],
+    ];
+    const D65: [f32; 3] = [0.9505, 1., 1.089];
+    const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+        [3.2406, -1.5372, -0.4986],
+        [-0.9689, 1.8758, 0.0415],
+        [0.0557, -0.2040, 1.0570],
+    ];
+    let mul = |m: &[[f32; 3]; 3], v: [f32; 3]| [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ];
+    let (source, target) = (mul(&BRADFORD, white), mul(&BRADFORD, D65));
+    let cone = mul(&BRADFORD, xyz);
+    let adapted = [cone[0] * target[0] / source[0], cone[1] * target[1] / source[1], cone[2] * target[2] / source[2]];
+    let linear = mul(&XYZ_TO_SRGB, mul(&BRADFORD_INVERSE, adapted));
+    let encode = |v: f32| {
+        let v = v.clamp(0., 1.);
+        if v <= 0.0031308 { 12.92 * v } else { 1.055 * v.powf(1. / 2.4) - 0.055 }
+    };
+    [encode(linear[0]), encode(linear[1]), encode(linear[2])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn color_space(s: &[u8]) -> ColorSpace {
+        // a name at the end of the input needs a delimiter
+        let s = [s, b"\n"].concat();
+        ColorSpace::from_primitive(parse(&s, &NoResolve).unwrap(), &NoResolve).unwrap()
+    }
+
+    fn assert_rgb(cs: &ColorSpace, color: &[f32], rgb: [f32; 3]) {
+        let out = cs.to_rgb(color).unwrap();
+        assert!(out.iter().zip(&rgb).all(|(a, b)| (a - b).abs() < 2e-3), "{:?} != {:?}", out, rgb);
+    }
+
+    #[test]
+    fn parse_spaces() {
+        assert!(matches!(color_space(b"/Pattern"), ColorSpace::Pattern(None)));
+        assert!(matches!(color_space(b"/CS0"), ColorSpace::Other(_)));
+        match color_space(b"[/Pattern /DeviceRGB]") {
+            ColorSpace::Pattern(Some(base)) => assert!(matches!(*base, ColorSpace::DeviceRGB)),
+            cs => panic!("{:?}", cs)
+        }
+        match color_space(b"[/CalRGB << /WhitePoint [0.9505 1 1.089] /Gamma [2.2 2.2 2.2] >>]") {
+            ColorSpace::CalRGB(cal) => assert_eq!(cal.gamma, Some(vec![2.2; 3])),
+            cs => panic!("{:?}", cs)
+        }
+        match color_space(b"[/CalGray << /WhitePoint [0.9505 1 1.089] >>]") {
+            ColorSpace::CalGray(cal) => assert_eq!(cal.gamma, 1.),
+            cs => panic!("{:?}", cs)
+        }
+        assert_eq!(color_space(b"[/Lab << /WhitePoint [0.9642 1 0.8249] /Range [-128 127 -128 127] >>]").components(), 3);
+    }
+
+    #[test]
+    fn device() {
+        assert_rgb(&ColorSpace::DeviceGray, &[0.5], [0.5; 3]);
+        assert_rgb(&ColorSpace::DeviceRGB, &[0.2, 0.4, 1.5], [0.2, 0.4, 1.]);
+        assert_rgb(&ColorSpace::DeviceCMYK, &[1., 0., 0.5, 0.5], [0., 0.5, 0.25]);
+        assert!(ColorSpace::DeviceCMYK.to_rgb(&[0.]).is_err());
+        assert!(ColorSpace::Pattern(None).to_rgb(&[]).is_err());
+    }
+
+    #[test]
+    fn calibrated() {
+        // white and mid gray in D50 are adapted to D65 white and gray
+        let lab = color_space(b"[/Lab << /WhitePoint [0.9642 1 0.8249] >>]");
+        assert_rgb(&lab, &[100., 0., 0.], [1., 1., 1.]);
+        assert_rgb(&lab, &[0., 0., 0.], [0., 0., 0.]);
+        assert_rgb(&lab, &[53.389, 0., 0.], [0.5; 3]);
+
+        let gray = color_space(b"[/CalGray << /WhitePoint [0.9642 1 0.8249] /Gamma 1 >>]");
+        assert_rgb(&gray, &[1.], [1., 1., 1.]);
+        assert_rgb(&gray, &[0.2140], [0.5, 0.5, 0.5]);
+
+        // the sRGB primaries
+        let rgb = color_space(b"[/CalRGB << /WhitePoint [0.9505 1 1.089] /Gamma [2.2 2.2 2.2] \
+            /Matrix [0.4124 0.2126 0.0193 0.3576 0.7152 0.1192 0.1805 0.0722 0.9505] >>]");
+        assert_rgb(&rgb, &[1., 0., 0.], [1., 0., 0.]);
+        assert_rgb(&rgb, &[0., 0., 1.], [0., 0., 1.]);
+
+        // an inverted range is swapped instead of panicking in clamp
+        let inverted = color_space(b"[/Lab << /WhitePoint [0.9642 1 0.8249] /Range [127 -128 10 -10] >>]");
+        assert_eq!(inverted.component_range(1), (-128., 127.));
+        assert_eq!(inverted.component_range(2), (-10., 10.));
+        assert_rgb(&inverted, &[100., 0., 0.], [1., 1., 1.]);
+    }
+
+    #[test]
+    fn write() {
+        let spaces: &[&[u8]] = &[
+            b"/DeviceRGB",
+            b"/Pattern",
+            b"[/Pattern /DeviceGray]",
+            b"[/CalGray << /WhitePoint [0.9505 1 1.089] /Gamma 2.2 >>]",
+            b"[/CalRGB << /WhitePoint [0.9505 1 1.089] /Gamma [2.2 2.2 2.2] >>]",
+            b"[/Lab << /WhitePoint [0.9642 1 0.8249] /Range [-128 127 -128 127] >>]",
+            b"[/Indexed /DeviceRGB 1 <FF000000FF00>]",
+        ];
+        for &s in spaces {
+            let cs = color_space(s);
+            let p = cs.to_primitive(&mut NoUpdate).unwrap();
+            let written = ColorSpace::from_primitive(p, &NoResolve).unwrap();
+            assert_eq!(format!("{:?}", written), format!("{:?}", cs));
         }
+        let separation = color_space(b"[/Separation /Spot /DeviceCMYK \
+            << /FunctionType 2 /Domain [0 1] /C0 [0 0 0 0] /C1 [0 1 1 0] /N 1 >>]");
+        assert!(separation.to_primitive(&mut NoUpdate).is_err());
+    }
+
+    #[test]
+    fn indexed_and_tint() {
+        let indexed = color_space(b"[/Indexed /DeviceRGB 1 <FF000000FF00>]");
+        assert_rgb(&indexed, &[0.], [1., 0., 0.]);
+        assert_rgb(&indexed, &[1.], [0., 1., 0.]);
+        // out of range indices are clamped
+        assert_rgb(&indexed, &[5.], [0., 1., 0.]);
+
+        let separation = color_space(b"[/Separation /Spot /DeviceCMYK \
+            << /FunctionType 2 /Domain [0 1] /C0 [0 0 0 0] /C1 [0 1 1 0] /N 1 >>]");
+        assert_eq!(separation.components(), 1);
+        assert_rgb(&separation, &[1.], [1., 0., 0.]);
+        assert_rgb(&separation, &[0.5], [1., 0.5, 0.5]);
+
+        let device_n = color_space(b"[/DeviceN [/A /B] /DeviceGray \
+            << /FunctionType 2 /Domain [0 1] /C0 [1] /C1 [0] /N 1 >>]");
+        assert_eq!(device_n.components(), 2);
+        assert_rgb(&device_n, &[0.25, 0.], [0.75; 3]);
+    }
+
+    #[test]
+    fn transform() {
+        use super::icc::tests::{profile, xyz, gamma};
+        use std::sync::Arc;
+
+        let icc = |alternate: Option<ColorSpace>, data: Vec<u8>| {
+            let info = IccInfo { components: 1, alternate: alternate.map(Box::new), range: None, metadata: None };
+            ColorSpace::Icc(RcRef::new(PlainRef { id: 1, gen: 0 }, Arc::new(Stream::new(info, data))))
+        };
+        let intent = RenderingIntent::RelativeColorimetric;
+
+        // a linear gray profile, where the naive conversion would be gamma encoded
+        let linear = icc(None, profile(b"GRAY", b"XYZ ", &[(b"wtpt", xyz([0.9642, 1., 0.8249])), (b"kTRC", gamma(1.))]));
+        assert_rgb(&linear, &[0.214], [0.214; 3]);
+        let transform = ColorTransform::new(&linear, intent).unwrap();
+        let rgb = transform.apply(&[0.214]).unwrap();
+        assert!(rgb.iter().all(|v| (v - 0.5).abs() < 2e-3), "{:?}", rgb);
+
+        // an invalid profile uses the alternate
+        let broken = icc(Some(ColorSpace::DeviceCMYK), b"junk".to_vec());
+        let transform = ColorTransform::new(&broken, intent).unwrap();
+        assert_eq!(transform.components(), 4);
+        assert_eq!(transform.apply_u8(&[0, 0, 0, 255, 0, 255, 255, 0]).unwrap(), [0, 0, 0, 255, 0, 0]);
+
+        let indexed = ColorSpace::Indexed(Box::new(linear), vec![0, 255]);
+        let transform = ColorTransform::new(&indexed, intent).unwrap();
+        assert_eq!(transform.apply_u8(&[1, 0, 1, 7]).unwrap(), [255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255, 255]);
     }
 }
@@ -0,0 +1,629 @@
+//! Parsing and evaluation of ICC profiles
+//!
+//! Only the direction from device colours to the profile connection space is implemented, with
+//! matrix/TRC profiles and the `lut8`, `lut16` and `lutAtoB` tables.
+
+use std::fmt;
+
+use crate::error::*;
+use crate::object::RenderingIntent;
+
+use super::{lab_to_xyz, xyz_to_srgb};
+
+/// The illuminant of the profile connection space
+const D50: [f32; 3] = [0.9642, 1., 0.8249];
+
+/// A parsed ICC profile, converting device colours to XYZ relative to D50.
+pub struct IccProfile {
+    components: usize,
+    lab_pcs: bool,
+    intent: RenderingIntent,
+    white_point: [f32; 3],
+    /// `A2B0`, `A2B1` and `A2B2`: perceptual, colorimetric and saturation
+    luts: [Option<Lut>; 3],
+    matrix: Option<MatrixTrc>,
+}
+
+impl fmt::Debug for IccProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IccProfile")
+            .field("components", &self.components)
+            .field("lab_pcs", &self.lab_pcs)
+            .field("intent", &self.intent)
+            .finish()
+    }
+}
+
+impl IccProfile {
+    pub fn parse(data: &[u8]) -> Result<IccProfile> {
+        if data.len() < 132 || &data[36 .. 40] != b"acsp" {
+            bail!("not an ICC profile");
+        }
+        if &data[12 .. 16] == b"link" || &data[12 .. 16] == b"abst" {
+            bail!("unsupported profile class {:?}", String::from_utf8_lossy(&data[12 .. 16]));
+        }
+        let components = match &data[16 .. 20] {
+            b"GRAY" => 1,
+            b"RGB " | b"Lab " | b"XYZ " | b"Luv " | b"YCbr" | b"Yxy " | b"HSV " | b"HLS " | b"CMY " => 3,
+            b"CMYK" => 4,
+            &[n, b'C', b'L', b'R'] if n.is_ascii_hexdigit() => (n as char).to_digit(16).unwrap() as usize,
+            cs => bail!("unsupported data colour space {:?}", String::from_utf8_lossy(cs))
+        };
+        let lab_pcs = match &data[20 .. 24] {
+            b"XYZ " => false,
+            b"Lab " => true,
+            pcs => bail!("invalid connection space {:?}", String::from_utf8_lossy(pcs))
+        };
+        let intent = match t!(read_u32(data, 64)) {
+            1 => RenderingIntent::RelativeColorimetric,
+            2 => RenderingIntent::Saturation,
+            3 => RenderingIntent::AbsoluteColorimetric,
+            _ => RenderingIntent::Perceptual,
+        };
+
+        let count = t!(read_u32(data, 128)) as usize;
+        let mut tags = Vec::with_capacity(count.min(100));
+        for i in 0 .. count {
+            let pos = 132 + 12 * i;
+            let sig = try_opt!(data.get(pos .. pos + 4));
+            let offset = t!(read_u32(data, pos + 4)) as usize;
+            let len = t!(read_u32(data, pos + 8)) as usize;
+            let tag = match offset.checked_add(len).and_then(|end| data.get(offset .. end)) {
+                Some(tag) => tag,
+                None => bail!("tag {:?} out of bounds", String::from_utf8_lossy(sig))
+            };
+            tags.push((sig, tag));
+        }
+        let tag = |sig: &[u8; 4]| tags.iter().find(|&&(s, _)| s == sig).map(|&(_, data)| data);
+
+        let white_point = match tag(b"wtpt") {
+            Some(data) => t!(read_xyz(data)),
+            None => D50
+        };
+        let mut luts = [None, None, None];
+        for (lut, sig) in luts.iter_mut().zip(&[b"A2B0", b"A2B1", b"A2B2"]) {
+            if let Some(data) = tag(sig) {
+                let parsed = t!(Lut::parse(data));
+                if parsed.inputs != components || parsed.outputs != 3 {
+                    bail!("{} has {} inputs and {} outputs", String::from_utf8_lossy(&sig[..]), parsed.inputs, parsed.outputs);
+                }
+                *lut = Some(parsed);
+            }
+        }
+        let matrix = match (components, tag(b"kTRC"), tag(b"rXYZ"), tag(b"gXYZ"), tag(b"bXYZ"), tag(b"rTRC"), tag(b"gTRC"), tag(b"bTRC")) {
+            (1, Some(k), ..) => Some(MatrixTrc::Gray(t!(Curve::parse(k)).0)),
+            (3, _, Some(r), Some(g), Some(b), Some(rc), Some(gc), Some(bc)) => {
+                let (r, g, b) = (t!(read_xyz(r)), t!(read_xyz(g)), t!(read_xyz(b)));
+                Some(MatrixTrc::Rgb {
+                    matrix: [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]],
+                    curves: [t!(Curve::parse(rc)).0, t!(Curve::parse(gc)).0, t!(Curve::parse(bc)).0],
+                })
+            }
+            _ => None
+        };
+        if matrix.is_none() && luts.iter().all(Option::is_none) {
+            bail!("no device to connection space transform");
+        }
+
+        Ok(IccProfile { components, lab_pcs, intent, white_point, luts, matrix })
+    }
+
+    /// The number of components of a device colour.
+    pub fn components(&self) -> usize {
+        self.components
+    }
+
+    /// The intent from the header, used when there is no other.
+    pub fn rendering_intent(&self) -> RenderingIntent {
+        self.intent
+    }
+
+    /// The colour as XYZ relative to D50, or to the media white for `AbsoluteColorimetric`.
+    ///
+    /// The components go from 0 to 1.
+    pub fn to_xyz(&self, color: &[f32], intent: RenderingIntent) -> Result<[f32; 3]> {
+        if color.len() < self.components {
+            bail!("{} components for a profile with {}", color.len(), self.components);
+        }
+        let preferred = match intent {
+            RenderingIntent::Perceptual => 0,
+            RenderingIntent::RelativeColorimetric | RenderingIntent::AbsoluteColorimetric => 1,
+            RenderingIntent::Saturation => 2,
+        };
+        // profiles only need one of the tables, any present one is better than the matrix
+        let lut = self.luts[preferred].as_ref().or_else(|| self.luts.iter().flatten().next());
+        let xyz = match (lut, &self.matrix) {
+            (Some(lut), _) => {
+                let mut pcs = [0.; 3];
+                lut.eval(&color[.. self.components], &mut pcs);
+                if self.lab_pcs {
+                    let lab = match lut.legacy_lab {
+                        true => [pcs[0] * 100. * 65535. / 65280., pcs[1] * 255. * 65535. / 65280. - 128., pcs[2] * 255. * 65535. / 65280. - 128.],
+                        false => [pcs[0] * 100., pcs[1] * 255. - 128., pcs[2] * 255. - 128.],
+                    };
+                    lab_to_xyz(lab, D50)
+                } else {
+                    // u1Fixed15
+                    let scale = 65535. / 32768.;
+                    [pcs[0] * scale, pcs[1] * scale, pcs[2] * scale]
+                }
+            }
+            (None, Some(MatrixTrc::Gray(curve))) => {
+                let y = curve.eval(color[0].clamp(0., 1.));
+                [D50[0] * y, y, D50[2] * y]
+            }
+            (None, Some(MatrixTrc::Rgb { matrix, curves })) => {
+                let linear = [0, 1, 2].map(|i| curves[i].eval(color[i].clamp(0., 1.)));
+                matrix.map(|row| row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2])
+            }
+            (None, None) => bail!("no device to connection space transform")
+        };
+        Ok(match intent {
+            RenderingIntent::AbsoluteColorimetric => [0, 1, 2].map(|i| xyz[i] * self.white_point[i] / D50[i]),
+            _ => xyz
+        })
+    }
+
+    /// The colour in sRGB, with components from 0 to 1.
+    pub fn to_rgb(&self, color: &[f32], intent: RenderingIntent) -> Result<[f32; 3]> {
+        Ok(xyz_to_srgb(t!(self.to_xyz(color, intent)), D50))
+    }
+}
+
+enum MatrixTrc {
+    Gray(Curve),
+    Rgb { matrix: [[f32; 3]; 3], curves: [Curve; 3] },
+}
+
+#[derive(Debug, Clone)]
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// function type and `g a b c d e f`
+    Parametric(u16, [f32; 7]),
+}
+
+impl Curve {
+    /// A `curv` or `para` curve and its length, padded to four bytes.
+    fn parse(data: &[u8]) -> Result<(Curve, usize)> {
+        match try_opt!(data.get(.. 4)) {
+            b"curv" => {
+                let n = t!(read_u32(data, 8)) as usize;
+                let curve = match n {
+                    0 => Curve::Gamma(1.),
+                    1 => Curve::Gamma(t!(read_u16(data, 12)) as f32 / 256.),
+                    _ => {
+                        let mut table = Vec::with_capacity(n.min(data.len() / 2));
+                        for i in 0 .. n {
+                            table.push(t!(read_u16(data, 12 + 2 * i)) as f32 / 65535.);
+                        }
+                        Curve::Table(table)
+                    }
+                };
+                Ok((curve, (12 + 2 * n).div_ceil(4) * 4))
+            }
+            b"para" => {
+                let typ = t!(read_u16(data, 8));
+                let n = match typ {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => bail!("invalid parametric curve type {}", typ)
+                };
+                let mut params = [0.; 7];
+                for (i, p) in params.iter_mut().take(n).enumerate() {
+                    *p = t!(read_fixed(data, 12 + 4 * i));
+                }
+                Ok((Curve::Parametric(typ, params), 12 + 4 * n))
+            }
+            sig => bail!("invalid curve type {:?}", String::from_utf8_lossy(sig))
+        }
+    }
+
+    fn eval(&self, x: f32) -> f32 {
+        match *self {
+            Curve::Gamma(g) => x.powf(g),
+            Curve::Table(ref table) => interpolate(table, x),
+            Curve::Parametric(typ, [g, a, b, c, d, e, f]) => {
+                let y = match typ {
+                    0 => x.powf(g),
+                    1 if x >= -b / a => (a * x + b).powf(g),
+                    1 => 0.,
+                    2 if x >= -b / a => (a * x + b).powf(g) + c,
+                    2 => c,
+                    3 if x >= d => (a * x + b).powf(g),
+                    3 => c * x,
+                    _ if x >= d => (a * x + b).powf(g) + e,
+                    _ => c * x + f,
+                };
+                y.clamp(0., 1.)
+            }
+        }
+    }
+}
+
+/// Linear interpolation in a table spanning 0 to 1.
+fn interpolate(table: &[f32], x: f32) -> f32 {
+    let p = x.clamp(0., 1.) * (table.len() - 1) as f32;
+    let i = (p as usize).min(table.len().saturating_sub(2));
+    match table.get(i + 1) {
+        Some(&next) => table[i] + (p - i as f32) * (next - table[i]),
+        None => table[i]
+    }
+}
+
+struct Clut {
+    grid: Vec<usize>,
+    outputs: usize,
+    data: Vec<f32>,
+}
+
+impl Clut {
+    fn read(data: &[u8], grid: Vec<usize>, outputs: usize, bytes: usize) -> Result<Clut> {
+        let len = grid.iter().try_fold(outputs, |n, &g| n.checked_mul(g));
+        let values = match len.and_then(|n| n.checked_mul(bytes)).and_then(|n| data.get(.. n)) {
+            Some(values) => values,
+            None => bail!("colour lookup table out of bounds")
+        };
+        let data = match bytes {
+            1 => values.iter().map(|&b| b as f32 / 255.).collect(),
+            _ => values.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as f32 / 65535.).collect(),
+        };
+        Ok(Clut { grid, outputs, data })
+    }
+
+    /// Multilinear interpolation, the first input varies slowest.
+    fn eval(&self, x: &[f32], out: &mut [f32]) {
+        let n = self.grid.len();
+        let mut index = Vec::with_capacity(n);
+        let mut frac = Vec::with_capacity(n);
+        let mut strides = vec![self.outputs; n];
+        for d in (0 .. n).rev() {
+            let g = self.grid[d];
+            let p = x[d].clamp(0., 1.) * (g - 1) as f32;
+            let i = (p as usize).min(g.saturating_sub(2));
+            index.push(i);
+            frac.push(p - i as f32);
+            if d + 1 < n {
+                strides[d] = strides[d + 1] * self.grid[d + 1];
+            }
+        }
+        index.reverse();
+        frac.reverse();
+
+        out.iter_mut().for_each(|o| *o = 0.);
+        for corner in 0 .. 1usize << n {
+            let mut weight = 1.;
+            let mut offset = 0;
+            for d in 0 .. n {
+                let high = corner >> d & 1;
+                weight *= if high == 1 { frac[d] } else { 1. - frac[d] };
+                offset += (index[d] + high) * strides[d];
+            }
+            if weight == 0. {
+                continue;
+            }
+            for (o, &v) in out.iter_mut().zip(&self.data[offset .. offset + self.outputs]) {
+                *o += weight * v;
+            }
+        }
+    }
+}
+
+/// `lut8`, `lut16` and `lutAtoB` tables, as the stages of `lutAtoB`.
+struct Lut {
+    inputs: usize,
+    outputs: usize,
+    a: Vec<Curve>,
+    clut: Option<Clut>,
+    m: Vec<Curve>,
+    /// 3x3 matrix and offset
+    matrix: Option<[f32; 12]>,
+    b: Vec<Curve>,
+    /// the Lab encoding of ICC version 2, used by `lut16`
+    legacy_lab: bool,
+}
+
+impl Lut {
+    fn parse(data: &[u8]) -> Result<Lut> {
+        let inputs = t!(read_u8(data, 8)) as usize;
+        let outputs = t!(read_u8(data, 9)) as usize;
+        if inputs == 0 || inputs > 15 || outputs == 0 {
+            bail!("lookup table with {} inputs and {} outputs", inputs, outputs);
+        }
+        match try_opt!(data.get(.. 4)) {
+            b"mft1" => Lut::parse_legacy(data, inputs, outputs, 1, 256, 256, 48),
+            b"mft2" => {
+                let n = t!(read_u16(data, 48)) as usize;
+                let m = t!(read_u16(data, 50)) as usize;
+                Lut::parse_legacy(data, inputs, outputs, 2, n, m, 52)
+            }
+            b"mAB " => {
+                let offset = |pos| read_u32(data, pos).map(|o| o as usize);
+                let curves = |pos, n| -> Result<Vec<Curve>> {
+                    let mut pos = match t!(offset(pos)) {
+                        0 => return Ok(vec![]),
+                        o => o
+                    };
+                    let mut curves = Vec::with_capacity(n);
+                    for _ in 0 .. n {
+                        let (curve, len) = t!(Curve::parse(try_opt!(data.get(pos ..))));
+                        curves.push(curve);
+                        pos += len;
+                    }
+                    Ok(curves)
+                };
+                let b = t!(curves(12, outputs));
+                let m = t!(curves(20, outputs));
+                let a = t!(curves(28, inputs));
+                let matrix = match t!(offset(16)) {
+                    0 => None,
+                    o => {
+                        let mut matrix = [0.; 12];
+                        for (i, v) in matrix.iter_mut().enumerate() {
+                            *v = t!(read_fixed(data, o + 4 * i));
+                        }
+                        Some(matrix)
+                    }
+                };
+                let clut = match t!(offset(24)) {
+                    0 => None,
+                    o => {
+                        let grid = try_opt!(data.get(o .. o + inputs)).iter().map(|&g| g as usize).collect::<Vec<_>>();
+                        if grid.contains(&0) {
+                            bail!("empty colour lookup table");
+                        }
+                        let bytes = t!(read_u8(data, o + 16)) as usize;
+                        if bytes != 1 && bytes != 2 {
+                            bail!("colour lookup table with {} byte precision", bytes);
+                        }
+                        Some(t!(Clut::read(try_opt!(data.get(o + 20 ..)), grid, outputs, bytes)))
+                    }
+                };
+                Ok(Lut { inputs, outputs, a, clut, m, matrix, b, legacy_lab: false })
+            }
+            sig => bail!("invalid lookup table type {:?}", String::from_utf8_lossy(sig))
+        }
+    }
+
+    /// `lut8` and `lut16`. The matrix only applies to XYZ input and is ignored.
+    fn parse_legacy(data: &[u8], inputs: usize, outputs: usize, bytes: usize, n: usize, m: usize, start: usize) -> Result<Lut> {
+        let grid = t!(read_u8(data, 10)) as usize;
+        if grid < 2 || n < 2 || m < 2 {
+            bail!("invalid lookup table size");
+        }
+        let read_table = |pos: usize, len: usize| -> Result<Curve> {
+            let mut table = Vec::with_capacity(len);
+            for i in 0 .. len {
+                table.push(match bytes {
+                    1 => t!(read_u8(data, pos + i)) as f32 / 255.,
+                    _ => t!(read_u16(data, pos + 2 * i)) as f32 / 65535.,
+                });
+            }
+            Ok(Curve::Table(table))
+        };
+        let mut pos = start;
+        let mut a = Vec::with_capacity(inputs);
+        for _ in 0 .. inputs {
+            a.push(t!(read_table(pos, n)));
+            pos += n * bytes;
+        }
+        let clut = t!(Clut::read(try_opt!(data.get(pos ..)), vec![grid; inputs], outputs, bytes));
+        pos += clut.data.len() * bytes;
+        let mut b = Vec::with_capacity(outputs);
+        for _ in 0 .. outputs {
+            b.push(t!(read_table(pos, m)));
+            pos += m * bytes;
+        }
+        Ok(Lut { inputs, outputs, a, clut: Some(clut), m: vec![], matrix: None, b, legacy_lab: bytes == 2 })
+    }
+
+    fn eval(&self, x: &[f32], out: &mut [f32; 3]) {
+        let mut values: Vec<f32> = x.iter().map(|v| v.clamp(0., 1.)).collect();
+        apply_curves(&self.a, &mut values);
+        if let Some(ref clut) = self.clut {
+            let mut next = vec![0.; clut.outputs];
+            clut.eval(&values, &mut next);
+            values = next;
+        }
+        values.resize(3, 0.);
+        apply_curves(&self.m, &mut values);
+        if let Some(m) = self.matrix {
+            let v = [values[0], values[1], values[2]];
+            for (i, value) in values.iter_mut().enumerate() {
+                *value = (m[3 * i] * v[0] + m[3 * i + 1] * v[1] + m[3 * i + 2] * v[2] + m[9 + i]).clamp(0., 1.);
+            }
+        }
+        apply_curves(&self.b, &mut values);
+        out.copy_from_slice(&values[.. 3]);
+    }
+}
+
+fn apply_curves(curves: &[Curve], values: &mut [f32]) {
+    for (v, curve) in values.iter_mut().zip(curves) {
+        *v = curve.eval(*v);
+    }
+}
+
+fn read_u8(data: &[u8], pos: usize) -> Result<u8> {
+    data.get(pos).cloned().ok_or(PdfError::EOF)
+}
+fn read_u16(data: &[u8], pos: usize) -> Result<u16> {
+    match data.get(pos .. pos + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err(PdfError::EOF)
+    }
+}
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    match data.get(pos .. pos + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(PdfError::EOF)
+    }
+}
+/// s15Fixed16
+fn read_fixed(data: &[u8], pos: usize) -> Result<f32> {
+    read_u32(data, pos).map(|v| v as i32 as f32 / 65536.)
+}
+/// The value of an `XYZ ` tag.
+fn read_xyz(data: &[u8]) -> Result<[f32; 3]> {
+    if data.get(.. 4) != Some(b"XYZ ") {
+        bail!("not an XYZ tag");
+    }
+    Ok([t!(read_fixed(data, 8)), t!(read_fixed(data, 12)), t!(read_fixed(data, 16))])
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A profile with `tags`.
+    pub fn profile(color_space: &[u8; 4], pcs: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[12 .. 16].copy_from_slice(b"mntr");
+        data[16 .. 20].copy_from_slice(color_space);
+        data[20 .. 24].copy_from_slice(pcs);
+        data[36 .. 40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = data.len() + 12 * tags.len();
+        for &(sig, ref tag) in tags {
+            data.extend_from_slice(sig);
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+        }
+        for (_, tag) in tags {
+            data.extend_from_slice(tag);
+        }
+        let len = data.len() as u32;
+        data[.. 4].copy_from_slice(&len.to_be_bytes());
+        data
+    }
+
+    fn fixed(v: f32) -> [u8; 4] {
+        ((v * 65536.).round() as i32).to_be_bytes()
+    }
+
+    pub fn xyz(v: [f32; 3]) -> Vec<u8> {
+        [&b"XYZ \0\0\0\0"[..], &fixed(v[0]), &fixed(v[1]), &fixed(v[2])].concat()
+    }
+
+    /// `curv` with a gamma
+    pub fn gamma(g: f32) -> Vec<u8> {
+        [&b"curv\0\0\0\0\0\0\0\x01"[..], &((g * 256.) as u16).to_be_bytes(), &[0, 0]].concat()
+    }
+
+    /// The sRGB primaries adapted to D50
+    const PRIMARIES: [[f32; 3]; 3] = [[0.4361, 0.2225, 0.0139], [0.3851, 0.7169, 0.0971], [0.1431, 0.0606, 0.7141]];
+
+    fn srgb_curve() -> Vec<u8> {
+        let mut data = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for &v in &[2.4, 1. / 1.055, 0.055 / 1.055, 1. / 12.92, 0.04045] {
+            data.extend_from_slice(&fixed(v));
+        }
+        data
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 5e-3), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn matrix_trc() {
+        let data = profile(b"RGB ", b"XYZ ", &[
+            (b"wtpt", xyz(D50)),
+            (b"rXYZ", xyz(PRIMARIES[0])), (b"gXYZ", xyz(PRIMARIES[1])), (b"bXYZ", xyz(PRIMARIES[2])),
+            (b"rTRC", srgb_curve()), (b"gTRC", srgb_curve()), (b"bTRC", srgb_curve()),
+        ]);
+        let profile = IccProfile::parse(&data).unwrap();
+        assert_eq!(profile.components(), 3);
+        for &color in &[[1., 1., 1.], [0.2, 0.5, 0.8], [1., 0., 0.], [0., 0., 0.]] {
+            assert_close(profile.to_rgb(&color, RenderingIntent::Perceptual).unwrap(), color);
+        }
+    }
+
+    #[test]
+    fn gray_intent() {
+        // paper that is darker than the connection space white
+        let data = profile(b"GRAY", b"XYZ ", &[(b"wtpt", xyz(D50.map(|v| v * 0.9))), (b"kTRC", gamma(1.))]);
+        let profile = IccProfile::parse(&data).unwrap();
+        assert_close(profile.to_rgb(&[0.214], RenderingIntent::RelativeColorimetric).unwrap(), [0.5; 3]);
+        assert_close(profile.to_rgb(&[1.], RenderingIntent::RelativeColorimetric).unwrap(), [1.; 3]);
+        assert_close(profile.to_rgb(&[1.], RenderingIntent::AbsoluteColorimetric).unwrap(), [0.9547; 3]);
+    }
+
+    #[test]
+    fn lut_a_to_b() {
+        // identity A and B curves around a 2x2x2 table of linear sRGB in XYZ
+        let mut clut = vec![2, 2, 2];
+        clut.resize(16, 0);
+        clut.extend_from_slice(&[2, 0, 0, 0]);
+        for i in 0 .. 8 {
+            let rgb = [i >> 2 & 1, i >> 1 & 1, i & 1].map(|c| c as f32);
+            let xyz = [0, 1, 2].map(|x| (0 .. 3).map(|c| PRIMARIES[c][x] * rgb[c]).sum::<f32>());
+            for v in xyz {
+                clut.extend_from_slice(&((v * 32768.).round() as u16).to_be_bytes());
+            }
+        }
+        let curves = [gamma(1.), gamma(1.), gamma(1.)].concat();
+        let b = 32;
+        let a = b + curves.len();
+        let c = a + curves.len();
+        let mut lut = b"mAB \0\0\0\0\x03\x03\0\0".to_vec();
+        for &offset in &[b, 0, 0, c, a] {
+            lut.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+        lut.extend_from_slice(&curves);
+        lut.extend_from_slice(&curves);
+        lut.extend_from_slice(&clut);
+
+        let rgb = IccProfile::parse(&profile(b"RGB ", b"XYZ ", &[(b"A2B0", lut.clone())])).unwrap();
+        let intent = rgb.rendering_intent();
+        assert_close(rgb.to_rgb(&[1., 0., 0.], intent).unwrap(), [1., 0., 0.]);
+        assert_close(rgb.to_rgb(&[0.5; 3], intent).unwrap(), [0.7354; 3]);
+        assert_close(rgb.to_rgb(&[1.; 3], intent).unwrap(), [1.; 3]);
+
+        // without A2B0 the perceptual intent uses any other table
+        let other = IccProfile::parse(&profile(b"RGB ", b"XYZ ", &[(b"A2B1", lut.clone())])).unwrap();
+        assert_close(other.to_rgb(&[1., 0., 0.], RenderingIntent::Perceptual).unwrap(), [1., 0., 0.]);
+
+        // the table precision is 1 or 2 bytes
+        lut[c + 16] = 3;
+        assert!(IccProfile::parse(&profile(b"RGB ", b"XYZ ", &[(b"A2B0", lut)])).is_err());
+    }
+
+    #[test]
+    fn lut16_lab() {
+        // CMYK to Lab with identity tables: white without ink, black with any
+        let mut lut = b"mft2\0\0\0\0\x04\x03\x02\0".to_vec();
+        lut.resize(48, 0);
+        lut.extend_from_slice(&[0, 2, 0, 2]);
+        for _ in 0 .. 4 {
+            lut.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        }
+        for i in 0 .. 16 {
+            let l: u16 = if i == 0 { 0xFF00 } else { 0 };
+            for v in [l, 0x8000, 0x8000] {
+                lut.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        for _ in 0 .. 3 {
+            lut.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        }
+        let profile = IccProfile::parse(&profile(b"CMYK", b"Lab ", &[(b"A2B0", lut)])).unwrap();
+        assert_eq!(profile.components(), 4);
+        // the colorimetric table falls back to the perceptual one
+        let intent = RenderingIntent::RelativeColorimetric;
+        assert_close(profile.to_rgb(&[0.; 4], intent).unwrap(), [1.; 3]);
+        assert_close(profile.to_rgb(&[0., 0., 0., 1.], intent).unwrap(), [0.; 3]);
+        assert!(profile.to_rgb(&[0.; 3], intent).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(IccProfile::parse(b"not a profile").is_err());
+        // no transform
+        assert!(IccProfile::parse(&profile(b"RGB ", b"XYZ ", &[(b"wtpt", xyz(D50))])).is_err());
+    }
+}
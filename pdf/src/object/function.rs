@@ -29,11 +29,41 @@ struct Function2 {
     exponent: f32,
 }
 
+#[derive(Object, Debug, Clone)]
+struct Function0 {
+    #[pdf(key="Size")]
+    size: Vec<u32>,
+
+    #[pdf(key="BitsPerSample")]
+    bits_per_sample: u32,
+
+    #[pdf(key="Order", default="1")]
+    order: u32,
+
+    #[pdf(key="Encode")]
+    encode: Option<Vec<f32>>,
+
+    #[pdf(key="Decode")]
+    decode: Option<Vec<f32>>,
+}
+
+#[derive(Object, Debug, Clone)]
+struct Function3 {
+    #[pdf(key="Functions")]
+    functions: Vec<Function>,
+
+    #[pdf(key="Bounds")]
+    bounds: Vec<f32>,
+
+    #[pdf(key="Encode")]
+    encode: Vec<f32>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Function {
     Sampled(SampledFunction),
     Interpolated(Vec<InterpolatedFunctionDim>),
-    Stiching,
+    Stitching(StitchingFunction),
     Calculator,
     PostScript { func: PsFunc, domain: Vec<f32>, range: Vec<f32> },
 }
@@ -52,18 +82,24 @@ impl Function {
                 }
                 Ok(())
             }
+            Function::Stitching(ref func) => func.apply(x[0], out),
             Function::PostScript { ref func, .. } => func.exec(x, out),
             _ => bail!("unimplemted function {:?}", self)
         }
     }
     pub fn input_dim(&self) -> usize {
         match *self {
+            Function::Sampled(ref func) => func.input.len(),
+            Function::Interpolated(_) | Function::Stitching(_) => 1,
             Function::PostScript { ref domain, .. } => domain.len() / 2,
             _ => panic!()
         }
     }
     pub fn output_dim(&self) -> usize {
         match *self {
+            Function::Sampled(ref func) => func.output.len(),
+            Function::Interpolated(ref parts) => parts.len(),
+            Function::Stitching(ref func) => func.functions[0].output_dim(),
             Function::PostScript { ref range, .. } => range.len() / 2,
             _ => panic!()
         }
@@ -99,10 +135,24 @@ impl FromDict for Function {
                 }
                 Ok(Function::Interpolated(parts))
             },
-            i => {
-                dbg!(raw);
-                bail!("unsupported function type {}", i)
+            3 => {
+                let f3 = Function3::from_dict(raw.other, resolve)?;
+                let k = f3.functions.len();
+                if k == 0 || f3.bounds.len() != k - 1 || f3.encode.len() != 2 * k {
+                    bail!("stitching function with {} functions, {} bounds and {} encode values", k, f3.bounds.len(), f3.encode.len());
+                }
+                if raw.domain.len() < 2 {
+                    bail!("stitching function without domain");
+                }
+                t!(check_ranges("Domain", &raw.domain[.. 2]));
+                Ok(Function::Stitching(StitchingFunction {
+                    domain: (raw.domain[0], raw.domain[1]),
+                    functions: f3.functions,
+                    bounds: f3.bounds,
+                    encode: f3.encode,
+                }))
             }
+            i => bail!("unsupported function type {}", i)
         }
     }
 }
@@ -121,11 +171,9 @@ impl Object for Function {
                         Ok(Function::PostScript { func, domain: info.domain, range: info.range.unwrap() })
                     },
                     0 => {
-                        Ok(Function::Sampled(SampledFunction {
-                            input: vec![],
-                            data: vec![],
-                            order: Interpolation::Linear
-                        }))
+                        let info = stream.info.info.clone();
+                        let f0 = Function0::from_dict(info.other, resolve)?;
+                        Ok(Function::Sampled(SampledFunction::new(&info.domain, info.range.as_deref(), &f0, &data)?))
                     }
                     ref p => bail!("found a function stream with type {:?}", p)
                 }
@@ -145,17 +193,44 @@ struct SampledFunctionInput {
     size: u32,
 }
 impl SampledFunctionInput {
+    /// The position of `x` in the samples.
     fn map(&self, x: f32) -> f32 {
         let x = x.clamp(self.domain.0, self.domain.1);
-        x.mul_add(self.encode_scale, self.encode_offset)
+        x.mul_add(self.encode_scale, self.encode_offset).clamp(0., (self.size - 1) as f32)
+    }
+    /// The samples around the position `e` and their weights.
+    fn taps(&self, e: f32, order: &Interpolation) -> Vec<(usize, f32)> {
+        let last = self.size as usize - 1;
+        let i = (e.floor() as usize).min(last);
+        let t = e - i as f32;
+        match order {
+            Interpolation::Linear if i == last => vec![(i, 1.)],
+            Interpolation::Linear => vec![(i, 1. - t), (i + 1, t)],
+            Interpolation::Cubic => {
+                // Catmull-Rom, repeating the samples at the edges
+                let at = |k: isize| (i as isize + k).clamp(0, last as isize) as usize;
+                let (t2, t3) = (t * t, t * t * t);
+                vec![
+                    (at(-1), (-t3 + 2. * t2 - t) / 2.),
+                    (at(0), (3. * t3 - 5. * t2 + 2.) / 2.),
+                    (at(1), (-3. * t3 + 4. * t2 + t) / 2.),
+                    (at(2), (t3 - t2) / 2.),
+                ]
+            }
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 struct SampledFunctionOutput {
     output_offset: f32,
-    output_scale: f32
-
+    output_scale: f32,
+    range: (f32, f32),
+}
+impl SampledFunctionOutput {
+    fn map(&self, sample: f32) -> f32 {
+        sample.mul_add(self.output_scale, self.output_offset).clamp(self.range.0, self.range.1)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -167,18 +242,149 @@ enum Interpolation {
 #[derive(Debug, Clone)]
 pub struct SampledFunction {
     input: Vec<SampledFunctionInput>,
-    data: Vec<u8>,
+    output: Vec<SampledFunctionOutput>,
+    /// the first input varies fastest, the outputs of each sample are next to each other
+    samples: Vec<f32>,
     order: Interpolation,
 }
 impl SampledFunction {
+    fn new(domain: &[f32], range: Option<&[f32]>, f0: &Function0, data: &[u8]) -> Result<SampledFunction> {
+        let range = match range {
+            Some(range) => range,
+            None => bail!("sampled function without range")
+        };
+        t!(check_ranges("Domain", domain));
+        t!(check_ranges("Range", range));
+        let (m, n) = (domain.len() / 2, range.len() / 2);
+        if f0.size.len() != m || f0.size.contains(&0) {
+            bail!("invalid size {:?} of sampled function with {} inputs", f0.size, m);
+        }
+        if !matches!(f0.bits_per_sample, 1 | 2 | 4 | 8 | 12 | 16 | 24 | 32) {
+            bail!("invalid BitsPerSample {}", f0.bits_per_sample);
+        }
+        let order = match f0.order {
+            1 => Interpolation::Linear,
+            3 => Interpolation::Cubic,
+            o => bail!("invalid order {} of sampled function", o)
+        };
+        let input = (0 .. m).map(|i| {
+            let (d0, d1) = (domain[2 * i], domain[2 * i + 1]);
+            let (e0, e1) = match f0.encode {
+                Some(ref encode) if encode.len() >= 2 * m => (encode[2 * i], encode[2 * i + 1]),
+                _ => (0., (f0.size[i] - 1) as f32)
+            };
+            let encode_scale = if d1 != d0 { (e1 - e0) / (d1 - d0) } else { 0. };
+            SampledFunctionInput {
+                domain: (d0, d1),
+                encode_offset: e0 - d0 * encode_scale,
+                encode_scale,
+                size: f0.size[i],
+            }
+        }).collect();
+        let max = ((1u64 << f0.bits_per_sample) - 1) as f32;
+        let output = (0 .. n).map(|j| {
+            let (r0, r1) = (range[2 * j], range[2 * j + 1]);
+            let (y0, y1) = match f0.decode {
+                Some(ref decode) if decode.len() >= 2 * n => (decode[2 * j], decode[2 * j + 1]),
+                _ => (r0, r1)
+            };
+            SampledFunctionOutput {
+                output_offset: y0,
+                output_scale: (y1 - y0) / max,
+                range: (r0, r1),
+            }
+        }).collect();
+        let count = match f0.size.iter().try_fold(n, |count, &s| count.checked_mul(s as usize)) {
+            Some(count) => count,
+            None => bail!("sampled function of size {:?} is too large", f0.size)
+        };
+        let samples = t!(read_samples(data, f0.bits_per_sample, count));
+        Ok(SampledFunction { input, output, samples, order })
+    }
+
     fn apply(&self, x: &[f32], out: &mut [f32]) -> Result<()> {
-        let idx: Vec<f32> = x.iter().zip(self.input.iter()).map(|(&x, dim)| dim.map(x)).collect();
-        match self.order {
-            Interpolation::Linear => {
-                unimplemented!()
+        if x.len() != self.input.len() || out.len() != self.output.len() {
+            bail!("sampled function with {} inputs and {} outputs called with {} and {}",
+                self.input.len(), self.output.len(), x.len(), out.len());
+        }
+        let taps: Vec<_> = x.iter().zip(&self.input).map(|(&x, dim)| dim.taps(dim.map(x), &self.order)).collect();
+        out.iter_mut().for_each(|y| *y = 0.);
+
+        // sum over all combinations of the taps of each input
+        let mut combination = vec![0; taps.len()];
+        'outer: loop {
+            let (mut weight, mut offset, mut stride) = (1., 0, out.len());
+            for ((dim, taps), &k) in self.input.iter().zip(&taps).zip(&combination) {
+                let (i, w) = taps[k];
+                weight *= w;
+                offset += i * stride;
+                stride *= dim.size as usize;
+            }
+            for (y, &sample) in out.iter_mut().zip(&self.samples[offset ..]) {
+                *y += weight * sample;
+            }
+            for (k, taps) in combination.iter_mut().zip(&taps) {
+                *k += 1;
+                if *k < taps.len() {
+                    continue 'outer;
+                }
+                *k = 0;
             }
-            _ => unimplemented!()
+            break;
         }
+        for (y, dim) in out.iter_mut().zip(&self.output) {
+            *y = dim.map(*y);
+        }
+        Ok(())
+    }
+}
+
+/// `count` samples of `bits` bits each, without padding between them.
+fn read_samples(data: &[u8], bits: u32, count: usize) -> Result<Vec<f32>> {
+    if (data.len() as u64) * 8 < count as u64 * bits as u64 {
+        bail!("sampled function needs {} samples, found {} bytes", count, data.len());
+    }
+    let mut samples = Vec::with_capacity(count);
+    let (mut acc, mut n) = (0u64, 0);
+    for &byte in data {
+        acc = acc << 8 | byte as u64;
+        n += 8;
+        while n >= bits && samples.len() < count {
+            n -= bits;
+            samples.push((acc >> n) as u32 as f32);
+            acc &= (1 << n) - 1;
+        }
+    }
+    Ok(samples)
+}
+
+/// Check that the pairs of `values` (the `Domain` or `Range` array) are ordered intervals.
+fn check_ranges(key: &str, values: &[f32]) -> Result<()> {
+    for r in values.chunks_exact(2) {
+        if r[0].is_nan() || r[1].is_nan() || r[0] > r[1] {
+            bail!("invalid {} {:?}", key, values);
+        }
+    }
+    Ok(())
+}
+
+/// Type 3 functions, which select one of several functions depending on the input.
+#[derive(Debug, Clone)]
+pub struct StitchingFunction {
+    domain: (f32, f32),
+    functions: Vec<Function>,
+    bounds: Vec<f32>,
+    encode: Vec<f32>,
+}
+impl StitchingFunction {
+    fn apply(&self, x: f32, out: &mut [f32]) -> Result<()> {
+        let x = x.clamp(self.domain.0, self.domain.1);
+        let i = self.bounds.iter().position(|&b| x < b).unwrap_or(self.bounds.len());
+        let low = if i == 0 { self.domain.0 } else { self.bounds[i - 1] };
+        let high = self.bounds.get(i).cloned().unwrap_or(self.domain.1);
+        let (e0, e1) = (self.encode[2 * i], self.encode[2 * i + 1]);
+        let x = if high > low { e0 + (x - low) * (e1 - e0) / (high - low) } else { e0 };
+        self.functions[i].apply(&[x], out)
     }
 }
 
@@ -313,4 +519,89 @@ impl PsOp {
             })
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::primitive::PdfStream;
+
+    fn try_sampled(dict: &[u8], data: &[u8]) -> Result<Function> {
+        let mut info = parse(dict, &NoResolve).unwrap().into_dictionary(&NoResolve).unwrap();
+        info.insert("Length", Primitive::Integer(data.len() as i32));
+        Function::from_primitive(Primitive::Stream(PdfStream { info, data: data.into() }), &NoResolve)
+    }
+
+    fn sampled(dict: &[u8], data: &[u8]) -> Function {
+        try_sampled(dict, data).unwrap()
+    }
+
+    fn eval(f: &Function, x: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.; f.output_dim()];
+        f.apply(x, &mut out).unwrap();
+        out
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert!(a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn sampled_linear() {
+        let f = sampled(b"<< /FunctionType 0 /Domain [0 1] /Range [0 1] /Size [3] /BitsPerSample 8 >>", &[0, 255, 0]);
+        assert_eq!((f.input_dim(), f.output_dim()), (1, 1));
+        assert_close(&eval(&f, &[0.25]), &[0.5]);
+        assert_close(&eval(&f, &[0.5]), &[1.]);
+        assert_close(&eval(&f, &[1.]), &[0.]);
+        assert_close(&eval(&f, &[-1.]), &[0.]);
+
+        // two inputs, four bit samples
+        let f = sampled(b"<< /FunctionType 0 /Domain [0 1 0 1] /Range [0 15] /Decode [0 15] /Size [2 2] /BitsPerSample 4 >>", &[0x0F, 0xF0]);
+        assert_close(&eval(&f, &[1., 0.]), &[15.]);
+        assert_close(&eval(&f, &[0.25, 0.]), &[3.75]);
+        assert_close(&eval(&f, &[0.5, 0.5]), &[7.5]);
+        assert_close(&eval(&f, &[0., 1.]), &[15.]);
+    }
+
+    #[test]
+    fn sampled_cubic() {
+        let f = sampled(b"<< /FunctionType 0 /Domain [0 3] /Range [0 255 0 255] /Decode [0 255 0 255] /Size [4] \
+            /BitsPerSample 8 /Order 3 /Encode [0 3] >>", &[0, 0, 10, 40, 20, 10, 30, 0]);
+        assert_eq!(f.output_dim(), 2);
+        assert_close(&eval(&f, &[2.]), &[20., 10.]);
+        // linear data stays linear, the second output is (-0 + 9 * 40 + 9 * 10 - 0) / 16
+        assert_close(&eval(&f, &[1.5]), &[15., 28.125]);
+    }
+
+    #[test]
+    fn stitching() {
+        let dict = parse(b"<< /FunctionType 3 /Domain [0 1] /Bounds [0.5] /Encode [0 1 0 1] /Functions [
+            << /FunctionType 2 /Domain [0 1] /C0 [0] /C1 [1] /N 1 >>
+            << /FunctionType 2 /Domain [0 1] /C0 [1] /C1 [0] /N 1 >>
+        ] >>", &NoResolve).unwrap();
+        let f = Function::from_primitive(dict, &NoResolve).unwrap();
+        assert_eq!((f.input_dim(), f.output_dim()), (1, 1));
+        assert_close(&eval(&f, &[0.25]), &[0.5]);
+        assert_close(&eval(&f, &[0.5]), &[1.]);
+        assert_close(&eval(&f, &[0.75]), &[0.5]);
+        assert_close(&eval(&f, &[2.]), &[0.]);
+    }
+
+    #[test]
+    fn invalid() {
+        let bits = try_sampled(b"<< /FunctionType 0 /Domain [0 1] /Range [0 1] /Size [2] /BitsPerSample 64 >>", &[0; 16]);
+        assert!(bits.is_err());
+        let domain = try_sampled(b"<< /FunctionType 0 /Domain [1 0] /Range [0 1] /Size [2] /BitsPerSample 8 >>", &[0, 255]);
+        assert!(domain.is_err());
+        let range = try_sampled(b"<< /FunctionType 0 /Domain [0 1] /Range [1 0] /Size [2] /BitsPerSample 8 >>", &[0, 255]);
+        assert!(range.is_err());
+        let size = try_sampled(b"<< /FunctionType 0 /Domain [0 1 0 1 0 1] /Range [0 1] /Size [2147483647 2147483647 2147483647] \
+            /BitsPerSample 8 >>", &[0, 255]);
+        assert!(size.is_err());
+
+        let dict = parse(b"<< /FunctionType 3 /Domain [1 0] /Bounds [] /Encode [0 1] /Functions [
+            << /FunctionType 2 /Domain [0 1] /C0 [0] /C1 [1] /N 1 >>
+        ] >>", &NoResolve).unwrap();
+        assert!(Function::from_primitive(dict, &NoResolve).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Decoding of image XObjects to pixels
+
+use std::collections::HashMap;
+
+use crate::error::*;
+use crate::object::*;
+use crate::primitive::Primitive;
+use crate::enc::StreamFilter;
+
+/// The unpacked samples of an image, one `u16` per component.
+struct Samples {
+    width: usize,
+    height: usize,
+    components: usize,
+    bits: u32,
+    data: Vec<u16>,
+}
+
+impl Samples {
+    fn max(&self) -> f32 {
+        ((1u32 << self.bits) - 1) as f32
+    }
+}
+
+impl ImageXObject {
+    /// The pixels as 8 bit RGBA, row by row from the top.
+    ///
+    /// Samples are unpacked, mapped by the `Decode` array and converted to sRGB with the embedded
+    /// ICC profiles and the image's rendering intent. Alpha comes from the `SMask`, or else from
+    /// a `Mask` image or colour key. Image masks are black where they paint.
+    pub fn to_rgba8(&self, resolve: &impl Resolve) -> Result<Vec<u8>> {
+        let image = t!(self.samples());
+        let decode = self.decode_ranges(&image);
+        let mut rgba = Vec::with_capacity(4 * image.width * image.height);
+        if self.image_mask {
+            for &s in &image.data {
+                let painted = decode_sample(s, decode[0], image.max()) < 0.5;
+                rgba.extend_from_slice(&[0, 0, 0, if painted { 255 } else { 0 }]);
+            }
+            return Ok(rgba);
+        }
+
+        let cs = image_color_space(self.color_space.as_ref(), image.components);
+        let intent = self.intent.unwrap_or(RenderingIntent::RelativeColorimetric);
+        let transform = t!(ColorTransform::new(&cs, intent));
+        let mut cache: HashMap<&[u16], [u8; 3]> = HashMap::new();
+        let mut color = vec![0.; image.components];
+        for pixel in image.data.chunks_exact(image.components) {
+            let rgb = match cache.get(pixel) {
+                Some(&rgb) => rgb,
+                None => {
+                    for ((c, &s), &range) in color.iter_mut().zip(pixel).zip(&decode) {
+                        *c = decode_sample(s, range, image.max());
+                    }
+                    let rgb = t!(transform.apply(&color)).map(|v| (v * 255.).round() as u8);
+                    cache.insert(pixel, rgb);
+                    rgb
+                }
+            };
+            rgba.extend_from_slice(&rgb);
+            rgba.push(255);
+        }
+
+        if let Some(alpha) = t!(self.alpha(&image, resolve)) {
+            for (pixel, a) in rgba.chunks_exact_mut(4).zip(alpha) {
+                pixel[3] = a;
+            }
+        }
+        Ok(rgba)
+    }
+
+    /// The samples of a gray image as 8 bits, mapped by the `Decode` array but not converted.
+    ///
+    /// For image masks, 0 is painted and 255 is not.
+    pub fn to_gray8(&self) -> Result<Vec<u8>> {
+        self.raw8(1)
+    }
+
+    /// The samples of a CMYK image as 8 bits, mapped by the `Decode` array but not converted.
+    pub fn to_cmyk8(&self) -> Result<Vec<u8>> {
+        self.raw8(4)
+    }
+
+    fn raw8(&self, components: usize) -> Result<Vec<u8>> {
+        let image = t!(self.samples());
+        let matches = match self.color_space {
+            _ if self.image_mask => components == 1,
+            None => true,
+            Some(ColorSpace::DeviceGray) | Some(ColorSpace::CalGray(_)) => components == 1,
+            Some(ColorSpace::DeviceCMYK) => components == 4,
+            Some(ColorSpace::Icc(_)) => true,
+            Some(_) => false,
+        };
+        if !matches || image.components != components {
+            bail!("image with {} components in {:?}", image.components, self.color_space);
+        }
+        let decode = self.decode_ranges(&image);
+        let out = image.data.chunks_exact(components)
+            .flat_map(|pixel| pixel.iter().zip(&decode).map(|(&s, &range)| {
+                (decode_sample(s, range, image.max()).clamp(0., 1.) * 255.).round() as u8
+            }))
+            .collect();
+        Ok(out)
+    }
+
+    /// Unpack the samples. Images decoded by `DCTDecode`, `JPXDecode` and `CCITTFaxDecode` have
+    /// 8 bits per component.
+    fn samples(&self) -> Result<Samples> {
+        if self.width <= 0 || self.height <= 0 {
+            bail!("image of size {}x{}", self.width, self.height);
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        let pixels = match width.checked_mul(height) {
+            Some(n) if n > 0 => n,
+            _ => bail!("image of size {}x{}", width, height)
+        };
+        for filter in self.info.get_filters() {
+            if let StreamFilter::CCITTFaxDecode(ref params) = *filter {
+                if params.k >= 0 {
+                    bail!("CCITT group 3 images (K = {}) are not supported", params.k);
+                }
+            }
+        }
+        let data = t!(self.data());
+        let (bits, components) = match self.info.get_filters().last() {
+            Some(StreamFilter::DCTDecode(_)) | Some(StreamFilter::JPXDecode) => {
+                let components = data.len() / pixels;
+                if components == 0 {
+                    bail!("{} bytes for a {}x{} image", data.len(), width, height);
+                }
+                (8, components)
+            }
+            Some(StreamFilter::CCITTFaxDecode(_)) => (8, 1),
+            _ => {
+                let components = match self.color_space {
+                    _ if self.image_mask => 1,
+                    Some(ref cs) => cs.components(),
+                    None => bail!("image without colour space")
+                };
+                let bits = match self.bits_per_component {
+                    Some(bits) => bits,
+                    None if self.image_mask => 1,
+                    None => bail!("image without BitsPerComponent")
+                };
+                (bits as u32, components)
+            }
+        };
+        if ![1, 2, 4, 8, 16].contains(&bits) || components == 0 {
+            bail!("image with {} bits per component and {} components", bits, components);
+        }
+
+        let sizes = width.checked_mul(components).and_then(|row_len| {
+            let row_bytes = row_len.checked_mul(bits as usize)?.div_ceil(8);
+            Some((row_len, row_bytes, row_bytes.checked_mul(height)?, row_len.checked_mul(height)?))
+        });
+        let (row_len, row_bytes, len, count) = match sizes {
+            Some(sizes) => sizes,
+            None => bail!("image of size {}x{} with {} components is too large", width, height, components)
+        };
+        // only the end of the last row may be missing
+        let padded;
+        let data = if data.len() < len {
+            if len - data.len() > row_bytes {
+                bail!("image data is {} bytes instead of {}", data.len(), len);
+            }
+            warn!("image data is {} bytes instead of {}", data.len(), len);
+            padded = [data, &vec![0; len - data.len()]].concat();
+            &padded[..]
+        } else {
+            data
+        };
+
+        let mut samples = Vec::with_capacity(count);
+        for row in data.chunks_exact(row_bytes).take(height) {
+            match bits {
+                8 => samples.extend(row[.. row_len].iter().map(|&b| b as u16)),
+                16 => samples.extend(row.chunks_exact(2).take(row_len).map(|b| u16::from_be_bytes([b[0], b[1]]))),
+                _ => {
+                    let per_byte = 8 / bits as usize;
+                    let mask = (1u16 << bits) - 1;
+                    samples.extend((0 .. row_len).map(|i| {
+                        let shift = 8 - bits as usize * (i % per_byte + 1);
+                        (row[i / per_byte] as u16 >> shift) & mask
+                    }));
+                }
+            }
+        }
+        Ok(Samples { width, height, components, bits, data: samples })
+    }
+
+    /// The `Decode` array as one range per component, or the default for the colour space.
+    fn decode_ranges(&self, image: &Samples) -> Vec<(f32, f32)> {
+        let n = image.components;
+        match self.decode {
+            Some(ref d) if d.len() >= 2 * n => return d.chunks_exact(2).take(n).map(|r| (r[0], r[1])).collect(),
+            Some(ref d) => warn!("Decode array {:?} for {} components", d, n),
+            None => {}
+        }
+        match self.color_space {
+            Some(ColorSpace::Indexed(..)) if !self.image_mask => vec![(0., image.max())],
+            Some(ref cs) if !self.image_mask => (0 .. n).map(|i| cs.component_range(i)).collect(),
+            _ => vec![(0., 1.); n]
+        }
+    }
+
+    /// Alpha from the soft mask, a stencil mask or a colour key.
+    fn alpha(&self, image: &Samples, resolve: &impl Resolve) -> Result<Option<Vec<u8>>> {
+        if let Some(smask) = self.smask {
+            let smask = t!(resolve.get(smask));
+            let alpha = t!(smask.to_gray8());
+            return Ok(Some(resample(&alpha, &smask, image)));
+        }
+        let mask = match self.mask {
+            Some(Primitive::Reference(r)) => t!(resolve.resolve(r)),
+            Some(ref p) => p.clone(),
+            None => return Ok(None)
+        };
+        match mask {
+            Primitive::Stream(s) => {
+                let mask = t!(ImageXObject::from_stream(s, resolve));
+                if !mask.image_mask {
+                    bail!("Mask is not an image mask");
+                }
+                let painted = t!(mask.to_gray8());
+                let alpha: Vec<u8> = painted.iter().map(|&v| 255 - v).collect();
+                Ok(Some(resample(&alpha, &mask, image)))
+            }
+            Primitive::Array(key) => {
+                let key = t!(key.iter().map(|p| p.as_integer()).collect::<Result<Vec<i32>>>());
+                if key.len() < 2 * image.components {
+                    bail!("colour key mask {:?} for {} components", key, image.components);
+                }
+                let alpha = image.data.chunks_exact(image.components).map(|pixel| {
+                    let masked = pixel.iter().zip(key.chunks_exact(2)).all(|(&s, r)| r[0] <= s as i32 && s as i32 <= r[1]);
+                    if masked { 0 } else { 255 }
+                }).collect();
+                Ok(Some(alpha))
+            }
+            p => bail!("invalid Mask {:?}", p)
+        }
+    }
+}
+
+fn decode_sample(s: u16, (low, high): (f32, f32), max: f32) -> f32 {
+    low + s as f32 * (high - low) / max
+}
+
+/// The colour space, or one matching the number of components for JPX images without one.
+fn image_color_space(cs: Option<&ColorSpace>, components: usize) -> ColorSpace {
+    match (cs, components) {
+        (Some(cs), _) => cs.clone(),
+        (None, 1) => ColorSpace::DeviceGray,
+        (None, 4) => ColorSpace::DeviceCMYK,
+        (None, _) => ColorSpace::DeviceRGB,
+    }
+}
+
+/// Scale a mask to the size of the image, picking the nearest sample.
+fn resample(mask: &[u8], dict: &ImageDict, image: &Samples) -> Vec<u8> {
+    let (w, h) = (dict.width as usize, dict.height as usize);
+    if (w, h) == (image.width, image.height) {
+        return mask.to_vec();
+    }
+    let mut out = Vec::with_capacity(image.width * image.height);
+    for y in 0 .. image.height {
+        let row = y * h / image.height * w;
+        out.extend((0 .. image.width).map(|x| mask.get(row + x * w / image.width).cloned().unwrap_or(255)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(color_space: Option<ColorSpace>, bits: i32, width: i32, data: &[u8]) -> ImageXObject {
+        let dict = ImageDict {
+            width,
+            height: 1,
+            color_space,
+            bits_per_component: Some(bits),
+            intent: None,
+            image_mask: false,
+            mask: None,
+            decode: None,
+            interpolate: false,
+            struct_parent: None,
+            id: None,
+            smask: None,
+            other: Dictionary::new(),
+        };
+        Stream::new(dict, data.to_vec())
+    }
+
+    #[test]
+    fn unpack() {
+        let gray = image(Some(ColorSpace::DeviceGray), 2, 4, &[0b00_01_10_11]);
+        assert_eq!(gray.to_gray8().unwrap(), [0, 85, 170, 255]);
+        assert_eq!(gray.to_rgba8(&NoResolve).unwrap()[4 .. 8], [85, 85, 85, 255]);
+
+        let rgb = image(Some(ColorSpace::DeviceRGB), 16, 1, &[0xFF, 0xFF, 0x80, 0x00, 0, 0]);
+        assert_eq!(rgb.to_rgba8(&NoResolve).unwrap(), [255, 128, 0, 255]);
+        assert!(rgb.to_gray8().is_err());
+
+        // rows are padded to whole bytes
+        let mut mask = image(None, 1, 3, &[0b0100_0000]);
+        mask.info.info.image_mask = true;
+        assert_eq!(mask.to_gray8().unwrap(), [0, 255, 0]);
+        assert_eq!(mask.to_rgba8(&NoResolve).unwrap(), [0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 255]);
+        // and may leave out the bits per component
+        mask.info.info.bits_per_component = None;
+        assert_eq!(mask.to_gray8().unwrap(), [0, 255, 0]);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut gray = image(Some(ColorSpace::DeviceGray), 8, 4, &[0, 1, 2, 3]);
+        gray.info.info.bits_per_component = None;
+        assert!(gray.to_gray8().is_err());
+
+        // a missing end of the last row is padded, missing rows are not
+        let mut gray = image(Some(ColorSpace::DeviceGray), 8, 4, &[0xFF, 0xFF]);
+        assert_eq!(gray.to_gray8().unwrap(), [255, 255, 0, 0]);
+        gray.info.info.height = 2;
+        assert!(gray.to_gray8().is_err());
+
+        let mut huge = image(Some(ColorSpace::DeviceCMYK), 16, i32::MAX, &[]);
+        huge.info.info.height = i32::MAX;
+        assert!(huge.to_gray8().is_err());
+        assert!(huge.to_cmyk8().is_err());
+
+        // only group 4 fax data can be decoded
+        let mut params = Dictionary::new();
+        params.insert("K", Primitive::Integer(0));
+        params.insert("Columns", Primitive::Integer(8));
+        let filter = StreamFilter::from_kind_and_params("CCITTFaxDecode", params, &NoResolve).unwrap();
+        let mut fax = image(None, 1, 8, &[0]);
+        fax.info.info.image_mask = true;
+        fax.info.filters = vec![filter];
+        assert!(fax.to_gray8().is_err());
+        assert!(fax.to_rgba8(&NoResolve).is_err());
+    }
+
+    #[test]
+    fn decode_and_color_key() {
+        let mut cmyk = image(Some(ColorSpace::DeviceCMYK), 8, 1, &[0, 51, 255, 0]);
+        cmyk.info.info.decode = Some(vec![1., 0., 0., 1., 0., 1., 0., 1.]);
+        assert_eq!(cmyk.to_cmyk8().unwrap(), [255, 51, 255, 0]);
+        assert_eq!(cmyk.to_rgba8(&NoResolve).unwrap(), [0, 204, 0, 255]);
+
+        let palette = ColorSpace::Indexed(Box::new(ColorSpace::DeviceRGB), vec![255, 0, 0, 0, 0, 255]);
+        let mut indexed = image(Some(palette), 4, 3, &[0x01, 0x70]);
+        indexed.info.info.mask = Some(Primitive::Array(vec![Primitive::Integer(1), Primitive::Integer(1)]));
+        assert_eq!(indexed.to_rgba8(&NoResolve).unwrap(), [255, 0, 0, 255, 0, 0, 255, 0, 0, 0, 255, 255]);
+    }
+}
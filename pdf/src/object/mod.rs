@@ -6,6 +6,7 @@ mod types;
 mod stream;
 mod color;
 mod function;
+mod image;
 
 pub use self::types::*;
 pub use self::stream::*;
@@ -20,7 +21,7 @@ use crate::enc::*;
 use std::fmt;
 use std::marker::PhantomData;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::ops::Deref;
 use std::hash::{Hash, Hasher};
 use std::convert::TryInto;
@@ -44,7 +45,7 @@ impl Resolve for NoResolve {
 }
 
 /// A PDF Object
-pub trait Object: Sized + 'static {
+pub trait Object: Sized + Send + Sync + 'static {
     /// Convert primitive to Self
     fn from_primitive(p: Primitive, resolve: &impl Resolve) -> Result<Self>;
 }
@@ -173,11 +174,11 @@ impl<T> Eq for Ref<T> {}
 #[derive(Debug)]
 pub struct RcRef<T> {
     inner: PlainRef,
-    data: Rc<T>
+    data: Arc<T>
 }
 
 impl<T> RcRef<T> {
-    pub fn new(inner: PlainRef, data: Rc<T>) -> RcRef<T> {
+    pub fn new(inner: PlainRef, data: Arc<T>) -> RcRef<T> {
         RcRef { inner, data }
     }
     pub fn get_ref(&self) -> Ref<T> {
@@ -235,7 +236,7 @@ impl<T> Eq for RcRef<T> {}
 
 #[derive(Debug)]
 pub enum MaybeRef<T> {
-    Direct(Rc<T>),
+    Direct(Arc<T>),
     Indirect(RcRef<T>),
 }
 impl<T> MaybeRef<T> {
@@ -250,7 +251,7 @@ impl<T: Object> Object for MaybeRef<T> {
     fn from_primitive(p: Primitive, resolve: &impl Resolve) -> Result<Self> {
         Ok(match p {
             Primitive::Reference(r) => MaybeRef::Indirect(resolve.get(Ref::new(r))?),
-            p => MaybeRef::Direct(Rc::new(T::from_primitive(p, resolve)?))
+            p => MaybeRef::Direct(Arc::new(T::from_primitive(p, resolve)?))
         })
     }
 }
@@ -287,21 +288,21 @@ impl<T> Trace for MaybeRef<T> {
         }
     }
 }
-impl<T> From<Rc<T>> for MaybeRef<T> {
-    fn from(r: Rc<T>) -> MaybeRef<T> {
+impl<T> From<Arc<T>> for MaybeRef<T> {
+    fn from(r: Arc<T>) -> MaybeRef<T> {
         MaybeRef::Direct(r)
     }
 }
-impl<T> From<MaybeRef<T>> for Rc<T> {
-    fn from(r: MaybeRef<T>) -> Rc<T> {
+impl<T> From<MaybeRef<T>> for Arc<T> {
+    fn from(r: MaybeRef<T>) -> Arc<T> {
         match r {
             MaybeRef::Direct(rc) => rc,
             MaybeRef::Indirect(r) => r.data
         }
     }
 }
-impl<'a, T> From<&'a MaybeRef<T>> for Rc<T> {
-    fn from(r: &'a MaybeRef<T>) -> Rc<T> {
+impl<'a, T> From<&'a MaybeRef<T>> for Arc<T> {
+    fn from(r: &'a MaybeRef<T>) -> Arc<T> {
         match r {
             MaybeRef::Direct(ref rc) => rc.clone(),
             MaybeRef::Indirect(ref r) => r.data.clone()
@@ -487,6 +488,7 @@ impl Trace for Primitive {
             Primitive::Reference(r) => cb(r),
             Primitive::Array(ref parts) => parts.iter().for_each(|p| p.trace(cb)),
             Primitive::Dictionary(ref dict) => dict.values().for_each(|p| p.trace(cb)),
+            Primitive::Stream(ref stream) => stream.info.values().for_each(|p| p.trace(cb)),
             _ => ()
         }
     }
@@ -5,11 +5,12 @@ use crate::error::*;
 use crate::parser::Lexer;
 use crate::enc::{self, decode};
 
-use once_cell::unsync::OnceCell;
+use once_cell::sync::OnceCell;
 
 use std::borrow::Cow;
 use std::ops::Deref;
 use std::fmt;
+use std::io::Write;
 
 
 
@@ -84,11 +85,35 @@ impl<I: Object + fmt::Debug> Stream<I> {
         }
     }
 
+    /// If this is contains JPX encoded data, return the compressed data as is
+    pub fn as_jpx(&self) -> Option<&[u8]> {
+        match *self.info.filters.as_slice() {
+            [StreamFilter::JPXDecode] => Some(self.raw_data.as_slice()),
+            _ => None
+        }
+    }
+
+    /// If this is contains CCITT fax encoded data, return the compressed data as is and the parameters
+    pub fn as_ccitt(&self) -> Option<(&[u8], &enc::CCITTFaxDecodeParams)> {
+        match *self.info.filters.as_slice() {
+            [StreamFilter::CCITTFaxDecode(ref params)] => Some((self.raw_data.as_slice(), params)),
+            _ => None
+        }
+    }
+
     pub fn hexencode(mut self) -> Self {
         self.raw_data = enc::encode_hex(&self.raw_data);
         self.info.filters.push(StreamFilter::ASCIIHexDecode);
         self
     }
+
+    /// Compress the data with `FlateDecode`, which becomes the first filter to be undone.
+    pub fn flate_encode(mut self) -> Result<Self> {
+        let filter = StreamFilter::FlateDecode(LZWFlateParams::default());
+        self.raw_data = t!(enc::encode(&self.raw_data, &filter));
+        self.info.filters.insert(0, filter);
+        Ok(self)
+    }
 }
 
 impl<I: Object + fmt::Debug> fmt::Debug for Stream<I> {
@@ -118,6 +143,7 @@ impl<I: ObjectWrite> Stream<I> {
                     StreamFilter::LZWDecode(ref p) => Some(p.to_primitive(update)?),
                     StreamFilter::FlateDecode(ref p) => Some(p.to_primitive(update)?),
                     StreamFilter::DCTDecode(ref p) => Some(p.to_primitive(update)?),
+                    StreamFilter::Crypt(ref p) => Some(p.to_primitive(update)?),
                     _ => None
                 } {
                     if params.is_some() {
@@ -134,7 +160,7 @@ impl<I: ObjectWrite> Stream<I> {
                 StreamFilter::JPXDecode => "JPXDecode",
                 StreamFilter::DCTDecode(ref _p) => "DCTDecode",
                 StreamFilter::CCITTFaxDecode(ref _p) => "CCITTFaxDecode",
-                StreamFilter::Crypt => "Crypt",
+                StreamFilter::Crypt(ref _p) => "Crypt",
             })
             .map(|s| Primitive::Name(s.into()));
             match self.info.filters.len() {
@@ -288,7 +314,7 @@ impl<T: Object> Object for StreamInfo<T> {
     }
 }
 
-#[derive(Object, Default, Debug)]
+#[derive(Object, ObjectWrite, Default, Debug)]
 #[pdf(Type = "ObjStm")]
 pub struct ObjStmInfo {
     #[pdf(key = "N")]
@@ -309,6 +335,8 @@ pub struct ObjStmInfo {
 pub struct ObjectStream {
     /// Byte offset of each object. Index is the object number.
     offsets:    Vec<usize>,
+    /// Object number of each object.
+    numbers:    Vec<ObjNr>,
     /// The object number of this object.
     _id:         ObjNr,
     
@@ -320,25 +348,67 @@ impl Object for ObjectStream {
         let stream: Stream<ObjStmInfo> = Stream::from_primitive(p, resolve)?;
 
         let mut offsets = Vec::new();
+        let mut numbers = Vec::new();
         {
             debug!("parsing stream");
             let mut lexer = Lexer::new(stream.data()?);
             for _ in 0..(stream.info.num_objects as ObjNr) {
-                let _obj_nr = lexer.next()?.to::<ObjNr>()?;
+                let obj_nr = lexer.next()?.to::<ObjNr>()?;
                 let offset = lexer.next()?.to::<usize>()?;
+                numbers.push(obj_nr);
                 offsets.push(offset);
             }
         }
 
         Ok(ObjectStream {
             offsets,
+            numbers,
             _id: 0, // TODO
             inner: stream
         })
     }
 }
 
+impl ObjectWrite for ObjectStream {
+    fn to_primitive(&self, update: &mut impl Updater) -> Result<Primitive> {
+        self.inner.to_primitive(update)
+    }
+}
+
 impl ObjectStream {
+    /// Pack `objects`, given as pairs of object number and object, into a compressed object stream.
+    /// The objects must not be streams themselves.
+    pub fn new(objects: &[(ObjNr, Primitive)]) -> Result<ObjectStream> {
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (id, p) in objects {
+            if let Primitive::Stream(_) = p {
+                bail!("object {} is a stream and can not be put into an object stream", id);
+            }
+            offsets.push(body.len());
+            write!(header, "{} {} ", id, body.len())?;
+            t!(p.serialize(&mut body, 0));
+            body.push(b'\n');
+        }
+        header.push(b'\n');
+
+        let info = ObjStmInfo {
+            num_objects: objects.len() as i32,
+            first: header.len() as i32,
+            extends: None,
+        };
+        header.extend_from_slice(&body);
+        let inner = t!(Stream::new(info, header).flate_encode());
+
+        Ok(ObjectStream {
+            offsets,
+            numbers: objects.iter().map(|&(id, _)| id).collect(),
+            _id: 0,
+            inner
+        })
+    }
+
     pub fn get_object_slice(&self, index: usize) -> Result<&[u8]> {
         if index >= self.offsets.len() {
             err!(PdfError::ObjStmOutOfBounds {index, max: self.offsets.len()});
@@ -357,4 +427,8 @@ impl ObjectStream {
     pub fn n_objects(&self) -> usize {
         self.offsets.len()
     }
+    /// Object numbers of the contained objects, in the order they are stored
+    pub fn object_numbers(&self) -> &[ObjNr] {
+        &self.numbers
+    }
 }
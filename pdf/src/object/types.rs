@@ -312,7 +312,7 @@ pub struct PageLabel {
     pub start:  Option<usize>
 }
 
-#[derive(Object, ObjectWrite, Debug)]
+#[derive(Object, ObjectWrite, Debug, Clone, Default)]
 pub struct Resources {
     #[pdf(key="ExtGState")]
     pub graphics_states: HashMap<String, GraphicsStateParameters>,
@@ -338,20 +338,20 @@ impl Resources {
 }
 
 
-#[derive(Object, ObjectWrite, Debug)]
+#[derive(Object, ObjectWrite, Debug, Clone, Copy)]
 pub enum LineCap {
     Butt = 0,
     Round = 1,
     Square = 2
 }
-#[derive(Object, ObjectWrite, Debug)]
+#[derive(Object, ObjectWrite, Debug, Clone, Copy)]
 pub enum LineJoin {
     Miter = 0,
     Round = 1,
     Bevel = 2
 }
 
-#[derive(Object, ObjectWrite, Debug)]
+#[derive(Object, ObjectWrite, Debug, Clone)]
 #[pdf(Type = "ExtGState?")]
 /// `ExtGState`
 pub struct GraphicsStateParameters {
@@ -451,8 +451,9 @@ pub struct ImageDict {
     #[pdf(key="ColorSpace")]
     pub color_space: Option<ColorSpace>,
 
+    /// Optional for image masks, which have 1, and JPX images
     #[pdf(key="BitsPerComponent")]
-    pub bits_per_component: i32,
+    pub bits_per_component: Option<i32>,
     // Note: only allowed values are 1, 2, 4, 8, 16. Enum?
     
     #[pdf(key="Intent")]
@@ -30,6 +30,13 @@ impl<'a> Context<'a> {
             Ok(data)
         }
     }
+    pub fn decrypt_stream<'buf>(&self, info: &Dictionary, data: &'buf mut [u8]) -> Result<&'buf [u8]> {
+        if let Some(ref decoder) = self.decoder {
+            decoder.decrypt_stream(self.obj_nr, self.gen_nr, info, data)
+        } else {
+            Ok(data)
+        }
+    }
 }
 
 /// Can parse stream but only if its dictionary does not contain indirect references.
@@ -72,6 +79,9 @@ fn parse_stream_object(dict: Dictionary, lexer: &mut Lexer, r: &impl Resolve, ct
         None => err!(PdfError::MissingEntry { typ: "<Stream>", field: "Length".into() }),
     };
 
+    if length as usize > lexer.get_remaining_slice().len() {
+        err!(PdfError::EOF);
+    }
     let stream_substr = lexer.read_n(length as usize);
 
     // Finish
@@ -80,7 +90,7 @@ fn parse_stream_object(dict: Dictionary, lexer: &mut Lexer, r: &impl Resolve, ct
 
     // decrypt it
     if let Some(ctx) = ctx {
-        data = t!(ctx.decrypt(&mut data)).to_vec();
+        data = t!(ctx.decrypt_stream(&dict, &mut data)).to_vec();
     }
 
     Ok(PdfStream {
@@ -111,10 +121,13 @@ pub fn parse_with_lexer_ctx(lexer: &mut Lexer, r: &impl Resolve, ctx: Option<&Co
         // First backup position
         let pos_bk = lexer.get_pos();
 
-        let second_lexeme = t!(lexer.next());
+        // (peek, so that a number at the very end of the input is not an error)
+        let second_lexeme = t!(lexer.peek());
         if second_lexeme.is_integer() {
-            let third_lexeme = t!(lexer.next());
+            t!(lexer.next());
+            let third_lexeme = t!(lexer.peek());
             if third_lexeme.equals(b"R") {
+                t!(lexer.next());
                 // It is indeed a reference to an indirect object
                 Primitive::Reference (PlainRef {
                     id: t!(first_lexeme.to::<ObjNr>()),
@@ -295,7 +295,8 @@ impl ObjectWrite for PdfString {
 
 impl PdfString {
     pub fn serialize(&self, out: &mut impl io::Write) -> Result<()> {
-        if self.data.iter().any(|&b| b >= 0x80) {
+        // a raw \r in a literal string would be read back as \n
+        if self.data.iter().any(|&b| b >= 0x80 || b == b'\r') {
             write!(out, "<")?;
             for &b in &self.data {
                 write!(out, "{:02x}", b)?;
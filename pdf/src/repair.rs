@@ -1,42 +1,199 @@
+//! Reconstruction of the cross-reference table for files where it is missing or damaged.
 
-fn build_xref_table() {
-    warn!("can't read xref table: {:?}", e);
-    let start_offset = t!(backend.locate_start_offset());
-    let mut lexer = Lexer::new(t!(backend.read(..)));
-    let mut objects = Vec::new();
+use std::collections::HashMap;
+
+use crate::error::*;
+use crate::object::*;
+use crate::primitive::{Primitive, Dictionary};
+use crate::backend::{Backend, MAX_ID};
+use crate::parser::{Lexer, parse_with_lexer};
+use crate::xref::{XRef, XRefTable};
+use crate::file::Storage;
+
+/// Rebuild the xref table by scanning the whole file for `N G obj` headers.
+///
+/// When an object number appears more than once, the last definition in the file wins, as it
+/// would in an incremental update. Objects inside object streams are recovered as well.
+/// The trailer is the last `trailer` dictionary (or xref stream dictionary) naming a `/Root`;
+/// if there is none, one is synthesized around the last `/Type /Catalog` object.
+pub fn build_xref_table(backend: &impl Backend, start_offset: usize) -> Result<(XRefTable, Dictionary)> {
+    let data = t!(backend.read(start_offset ..));
+    let data = &*data;
+
+    // object number -> (position of the definition, entry)
+    let mut entries: HashMap<ObjNr, (usize, XRef)> = HashMap::new();
+    for (id, gen_nr, pos) in scan_objects(data) {
+        entries.insert(id, (pos, XRef::Raw { pos, gen_nr }));
+    }
+    if entries.is_empty() {
+        bail!("no objects found");
+    }
+
+    let mut storage = Storage::new(data, table(&entries), 0);
+    let mut raw: Vec<_> = entries.iter().map(|(&id, &(pos, xref))| (pos, id, xref)).collect();
+    raw.sort_unstable_by_key(|&(pos, _, _)| pos);
+
+    let mut trailer = None;
+    let mut catalog = None;
+    let mut compressed = Vec::new();
+    for (pos, id, xref) in raw {
+        let r = PlainRef { id, gen: xref.get_gen_nr() };
+        let dict = match storage.resolve(r) {
+            Ok(Primitive::Dictionary(dict)) => dict,
+            Ok(Primitive::Stream(stream)) => {
+                match stream.info.get("Type") {
+                    Some(Primitive::Name(ref ty)) if ty == "ObjStm" => {
+                        match ObjectStream::from_primitive(Primitive::Stream(stream), &storage) {
+                            Ok(obj_stm) => {
+                                for (index, &nr) in obj_stm.object_numbers().iter().enumerate() {
+                                    compressed.push((pos, nr, XRef::Stream { stream_id: id, index }));
+                                }
+                            }
+                            Err(e) => warn!("can't read object stream {}: {}", id, e)
+                        }
+                        continue;
+                    }
+                    _ => stream.info
+                }
+            }
+            _ => continue
+        };
+        match dict.get("Type") {
+            Some(Primitive::Name(ref ty)) if ty == "Catalog" => catalog = Some(r),
+            Some(Primitive::Name(ref ty)) if ty == "XRef" && dict.get("Root").is_some() => trailer = Some(dict),
+            _ => {}
+        }
+    }
 
-    (|| -> Result<()> { loop {
-        let offset = lexer.get_pos();
-        let w1 = t!(lexer.next());
-        let w2 = t!(lexer.next());
-        let w3 = t!(lexer.next_expect("obj"));
-        try_opt!(lexer.seek_substr("endobj"));
-
-        objects.push((t!(w1.to::<ObjNr>()), t!(w2.to::<GenNr>()), offset));
-    }})();
-
-    objects.sort_unstable();
-    let mut first_id = objects.first().map(|&(n, _, _)| n).unwrap_or(0);
-    let mut last_id = objects.last().map(|&(n, _, _)| n).unwrap_or(0);
-    let mut xref = XRefTable::new(1 + last_id - first_id);
-    for &(obj_nr, gen_nr, offset) in objects.iter() {
-        for n in first_id + 1 .. obj_nr {
-            xref.push(XRef::Free { next_obj_nr: obj_nr, gen_nr: 0 });
+    // objects in a stream are as recent as the stream itself
+    for (pos, id, xref) in compressed {
+        match entries.get(&id) {
+            Some(&(other, _)) if other > pos => {}
+            _ => {
+                entries.insert(id, (pos, xref));
+            }
         }
-        if obj_nr == last_id {
-            warn!("duplicate obj_nr {}", obj_nr);
+    }
+    storage = Storage::new(data, table(&entries), 0);
+
+    // look for catalogs that were only in object streams
+    if catalog.is_none() {
+        let mut ids: Vec<_> = entries.iter()
+            .filter(|(_, (_, xref))| matches!(xref, XRef::Stream { .. }))
+            .map(|(&id, &(pos, _))| (pos, id))
+            .collect();
+        ids.sort_unstable();
+        for (_, id) in ids {
+            if let Ok(Primitive::Dictionary(dict)) = storage.resolve(PlainRef { id, gen: 0 }) {
+                if matches!(dict.get("Type"), Some(Primitive::Name(ref ty)) if ty == "Catalog") {
+                    catalog = Some(PlainRef { id, gen: 0 });
+                }
+            }
+        }
+    }
+
+    if let Some(dict) = last_trailer_dict(data) {
+        trailer = Some(dict);
+    }
+    let valid_root = |trailer: &Dictionary| match trailer.get("Root") {
+        Some(&Primitive::Reference(r)) => matches!(storage.resolve(r), Ok(Primitive::Dictionary(_))),
+        _ => false
+    };
+    let mut trailer = match trailer {
+        Some(trailer) if valid_root(&trailer) => trailer,
+        _ => {
+            let root = t!(catalog.ok_or_else(|| other!("no trailer and no catalog found")));
+            let mut trailer = Dictionary::new();
+            trailer.insert("Root", Primitive::Reference(root));
+            trailer
+        }
+    };
+
+    let refs = table(&entries);
+    for key in &["Prev", "XRefStm", "Type", "Index", "W", "Length", "Filter", "DecodeParms"] {
+        trailer.remove(key);
+    }
+    trailer.insert("Size", Primitive::Integer(refs.len() as i32));
+
+    Ok((refs, trailer))
+}
+
+fn table(entries: &HashMap<ObjNr, (usize, XRef)>) -> XRefTable {
+    let size = entries.keys().max().map(|&id| id + 1).unwrap_or(1);
+    let mut refs = XRefTable::new(size);
+    refs.set(0, XRef::Free { next_obj_nr: 0, gen_nr: 0xffff });
+    for (&id, &(_, xref)) in entries {
+        refs.set(id, xref);
+    }
+    refs
+}
+
+/// Find all `N G obj` headers and return (object number, generation, position of N).
+fn scan_objects(data: &[u8]) -> Vec<(ObjNr, GenNr, usize)> {
+    let mut objects = Vec::new();
+    let mut pos = 0;
+    while let Some(i) = data[pos ..].windows(3).position(|w| w == b"obj") {
+        let obj_pos = pos + i;
+        pos = obj_pos + 3;
+
+        // "obj" has to be a complete token
+        if data.get(pos).map_or(false, |&b| !is_delimiter(b)) {
             continue;
         }
-        xref.push(XRef::Raw {
-            pos: offset - start_offset,
-            gen_nr
-        });
-        last_id = obj_nr;
+        if let Some((id, gen_nr, start)) = object_header_before(data, obj_pos) {
+            if id > 0 && id < MAX_ID as ObjNr {
+                objects.push((id, gen_nr, start));
+            }
+        }
     }
+    objects
+}
 
-    return t!(Err(e));
+// parse `N G ` backwards, ending right before `end`
+fn object_header_before(data: &[u8], end: usize) -> Option<(ObjNr, GenNr, usize)> {
+    let gen_end = skip_whitespace_back(data, end, 1)?;
+    let gen_start = skip_digits_back(data, gen_end)?;
+    let id_end = skip_whitespace_back(data, gen_start, 1)?;
+    let id_start = skip_digits_back(data, id_end)?;
+    if id_start > 0 && !is_delimiter(data[id_start - 1]) {
+        return None;
+    }
+    let id = std::str::from_utf8(&data[id_start .. id_end]).ok()?.parse().ok()?;
+    let gen_nr = std::str::from_utf8(&data[gen_start .. gen_end]).ok()?.parse().ok()?;
+    Some((id, gen_nr, id_start))
+}
+fn skip_whitespace_back(data: &[u8], end: usize, min: usize) -> Option<usize> {
+    let n = data[.. end].iter().rev().take_while(|&&b| is_whitespace(b)).count();
+    if n < min {
+        return None;
+    }
+    Some(end - n)
+}
+fn skip_digits_back(data: &[u8], end: usize) -> Option<usize> {
+    let n = data[.. end].iter().rev().take(10).take_while(|b| b.is_ascii_digit()).count();
+    if n == 0 {
+        return None;
+    }
+    Some(end - n)
+}
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\r' | b'\n' | b'\t' | b'\x0c' | b'\0')
+}
+fn is_delimiter(b: u8) -> bool {
+    is_whitespace(b) || b"()<>[]{}/%".contains(&b)
 }
 
-fn build_catalog() {
-    
+/// The last `trailer` dictionary in the file that has a `/Root` entry.
+fn last_trailer_dict(data: &[u8]) -> Option<Dictionary> {
+    let mut end = data.len();
+    while let Some(pos) = data[.. end].windows(7).rposition(|w| w == b"trailer") {
+        end = pos;
+        let mut lexer = Lexer::new(&data[pos + 7 ..]);
+        if let Ok(Primitive::Dictionary(dict)) = parse_with_lexer(&mut lexer, &NoResolve) {
+            if dict.get("Root").is_some() {
+                return Some(dict);
+            }
+        }
+    }
+    None
 }
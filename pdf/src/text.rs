@@ -0,0 +1,455 @@
+//! Text extraction
+//!
+//! Runs the text operators of a page's content stream and reports where each piece of text
+//! ends up on the page. Every `Tj`/`TJ` operation produces one [`TextSpan`] with its
+//! Unicode text, the bounding box in user space, the font and the effective font size.
+//! [`page_layout`] groups them into words, lines, paragraphs and columns in reading order.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::content::{Matrix, Op, Point, Rect, TextDrawAdjusted};
+use crate::error::Result;
+use crate::cmap::CMap;
+use crate::font::{Font, FontData, ToUnicodeMap, VerticalMetrics, Widths};
+use crate::object::{Page, PlainRef, Ref, Resolve, Resources, XObject};
+use crate::primitive::Primitive;
+
+mod layout;
+pub use self::layout::{page_layout, Layout, Column, Paragraph, Line, Word};
+
+/// Width (in 1/1000 em) assumed for glyphs of fonts without any width information.
+const DEFAULT_WIDTH: f32 = 500.;
+
+/// A negative `TJ` adjustment larger than this (in 1/1000 em) is taken as a word break.
+const SPACE_THRESHOLD: f32 = 200.;
+
+/// Nesting limit for form XObjects, so recursive forms do not loop forever.
+const MAX_FORM_DEPTH: usize = 16;
+
+/// Text drawn by a single text showing operator.
+#[derive(Debug, Clone)]
+pub struct TextSpan {
+    /// The decoded text.
+    pub text: String,
+
+    /// Bounding box in user space, from the font's descent to its ascent.
+    pub bbox: Rect,
+
+    /// The `/BaseFont` of the font, empty if the font could not be loaded.
+    pub font: String,
+
+    /// The font size in user space, i.e. `Tf` scaled by the text matrix and the CTM.
+    pub size: f32,
+
+    /// Maps the text space of this span to user space.
+    ///
+    /// The origin is the start of the baseline, one unit is one unit of text space
+    /// (with `Tfs = 1` being one em).
+    pub transform: Matrix,
+
+    /// Position of each character along the baseline.
+    pub chars: Vec<TextChar>,
+
+    /// Whether the font uses the vertical writing mode. The characters then run down the
+    /// y axis of `transform`, with `TextChar::pos` and `TextChar::width` measured downward.
+    pub vertical: bool,
+}
+
+/// A character of a `TextSpan`.
+#[derive(Debug, Clone, Copy)]
+pub struct TextChar {
+    /// Byte offset of the character in `TextSpan::text`.
+    pub offset: usize,
+
+    /// Start on the baseline, in text space.
+    pub pos: f32,
+
+    /// Advance, in text space. Includes character and word spacing.
+    pub width: f32,
+}
+
+/// Extract the text of `page`, in the order it appears in the content stream.
+pub fn page_spans(page: &Page, resolve: &impl Resolve) -> Result<Vec<TextSpan>> {
+    let contents = match page.contents {
+        Some(ref contents) => contents,
+        None => return Ok(vec![])
+    };
+    let resources = page.resources()?;
+    let mut extractor = Extractor {
+        resolve,
+        fonts: HashMap::new(),
+        spans: vec![],
+    };
+    extractor.run(contents.operations(resolve)?, resources, Matrix::default(), 0);
+    Ok(extractor.spans)
+}
+
+/// What we need to know about a font to place and decode its glyphs.
+struct FontInfo {
+    name: String,
+    widths: Option<Widths>,
+    default_width: f32,
+    /// with the encoding of simple fonts
+    to_unicode: Option<ToUnicodeMap>,
+    /// splits the strings of composite fonts into codes and maps them to CIDs
+    cmap: Option<CMap>,
+    /// set for fonts in the vertical writing mode
+    vertical: Option<VerticalMetrics>,
+    ascent: f32,
+    descent: f32,
+}
+impl FontInfo {
+    fn new(font: &Font, resolve: &impl Resolve) -> FontInfo {
+        let widths = font.widths(resolve).unwrap_or_else(|e| {
+            warn!("can't read widths of {}: {}", font.name, e);
+            None
+        });
+        let to_unicode = font.to_unicode().and_then(|r| r.map_err(|e| warn!("can't read ToUnicode of {}: {}", font.name, e)).ok());
+        let descriptor = font.font_descriptor();
+        let standard = font.standard_metrics();
+        let default_width = match descriptor {
+            Some(d) if d.missing_width > 0. => d.missing_width,
+            _ => DEFAULT_WIDTH
+        };
+        let vertical = if font.is_vertical() {
+            let metrics = font.vertical_metrics(resolve).unwrap_or_else(|e| {
+                warn!("can't read vertical metrics of {}: {}", font.name, e);
+                None
+            });
+            Some(metrics.unwrap_or_default())
+        } else {
+            None
+        };
+        FontInfo {
+            name: font.name.clone(),
+            widths,
+            default_width,
+            to_unicode,
+            cmap: match font.data {
+                Ok(FontData::Type0(_)) => Some(font.cmap().cloned().unwrap_or_else(|| CMap::identity(false))),
+                _ => None
+            },
+            vertical,
+            ascent: descriptor.and_then(|d| d.ascent).filter(|&a| a != 0.)
+                .or_else(|| standard.map(|m| m.ascent)).unwrap_or(800.) / 1000.,
+            descent: descriptor.and_then(|d| d.descent).filter(|&d| d != 0.)
+                .or_else(|| standard.map(|m| m.descent)).unwrap_or(-200.) / 1000.,
+        }
+    }
+
+    /// The codes in `data` and their length in bytes.
+    fn codes<'a>(&'a self, data: &'a [u8]) -> Box<dyn Iterator<Item=(u32, usize)> + 'a> {
+        match self.cmap {
+            Some(ref cmap) => Box::new(cmap.codes(data)),
+            None => Box::new(data.iter().map(|&b| (b as u32, 1)))
+        }
+    }
+
+    /// The CID of a code of a composite font, or the code itself for simple fonts.
+    fn cid(&self, code: u32, len: usize) -> Option<u32> {
+        match self.cmap {
+            // a missing CMap was reported when it was loaded
+            Some(ref cmap) => cmap.cid(code, len).ok().flatten(),
+            None => Some(code)
+        }
+    }
+
+    /// Glyph width in 1/1000 em.
+    fn width(&self, code: u32, len: usize) -> f32 {
+        let widths = match self.widths {
+            Some(ref widths) => widths,
+            None => return self.default_width
+        };
+        match self.cid(code, len) {
+            Some(cid) => widths.get(cid as usize),
+            None => widths.default_width()
+        }
+    }
+
+    fn decode(&self, code: u32, len: usize, out: &mut String) {
+        if let Some(s) = self.to_unicode.as_ref().and_then(|map| map.get_code(code)) {
+            out.push_str(s);
+        } else if let Some(ref cmap) = self.cmap {
+            match cmap.unicode(code, len) {
+                Some(s) => out.push_str(&s),
+                None => out.push(char::REPLACEMENT_CHARACTER)
+            }
+        } else {
+            // Latin-1 is the best guess for codes without a glyph name
+            out.push(code as u8 as char);
+        }
+    }
+}
+
+/// The parts of the graphics state that affect text.
+#[derive(Clone)]
+struct State {
+    ctm: Matrix,
+    char_space: f32,
+    word_space: f32,
+    horiz_scale: f32,
+    leading: f32,
+    font: Option<Arc<FontInfo>>,
+    font_size: f32,
+    rise: f32,
+}
+
+struct Extractor<'a, R> {
+    resolve: &'a R,
+    fonts: HashMap<PlainRef, Arc<FontInfo>>,
+    spans: Vec<TextSpan>,
+}
+impl<'a, R: Resolve> Extractor<'a, R> {
+    fn font(&mut self, font: Ref<Font>) -> Option<Arc<FontInfo>> {
+        let key = font.get_inner();
+        if let Some(info) = self.fonts.get(&key) {
+            return Some(info.clone());
+        }
+        let font = match self.resolve.get(font) {
+            Ok(font) => font,
+            Err(e) => {
+                warn!("can't load font {:?}: {}", key, e);
+                return None;
+            }
+        };
+        let info = Arc::new(FontInfo::new(&font, self.resolve));
+        self.fonts.insert(key, info.clone());
+        Some(info)
+    }
+
+    fn run(&mut self, ops: &[Op], resources: &Resources, ctm: Matrix, depth: usize) {
+        let mut state = State {
+            ctm,
+            char_space: 0.,
+            word_space: 0.,
+            horiz_scale: 1.,
+            leading: 0.,
+            font: None,
+            font_size: 0.,
+            rise: 0.,
+        };
+        let mut stack = vec![];
+        // text matrix and text line matrix
+        let mut tm = Matrix::default();
+        let mut tlm = Matrix::default();
+
+        for op in ops {
+            match *op {
+                Op::Save => stack.push(state.clone()),
+                Op::Restore => if let Some(s) = stack.pop() {
+                    state = s;
+                }
+                Op::Transform { matrix } => state.ctm = concat(&matrix, &state.ctm),
+                Op::GraphicsState { ref name } => {
+                    if let Some((font, size)) = resources.graphics_states.get(name).and_then(|gs| gs.font) {
+                        state.font = self.font(font);
+                        state.font_size = size;
+                    }
+                }
+                Op::BeginText => {
+                    tm = Matrix::default();
+                    tlm = tm;
+                }
+                Op::CharSpacing { char_space } => state.char_space = char_space,
+                Op::WordSpacing { word_space } => state.word_space = word_space,
+                Op::TextScaling { horiz_scale } => state.horiz_scale = horiz_scale / 100.,
+                Op::Leading { leading } => state.leading = leading,
+                Op::TextRise { rise } => state.rise = rise,
+                Op::TextFont { ref name, size } => {
+                    state.font = match resources.fonts.get(name) {
+                        Some(&font) => self.font(font),
+                        None => {
+                            warn!("font {:?} not in resources", name);
+                            None
+                        }
+                    };
+                    state.font_size = size;
+                }
+                Op::MoveTextPosition { translation } => {
+                    tlm = concat(&translate(translation), &tlm);
+                    tm = tlm;
+                }
+                Op::SetTextMatrix { matrix } => {
+                    tm = matrix;
+                    tlm = matrix;
+                }
+                Op::TextNewline => {
+                    tlm = concat(&translate(Point { x: 0., y: -state.leading }), &tlm);
+                    tm = tlm;
+                }
+                Op::TextDraw { ref text } => {
+                    self.show(&state, &mut tm, std::iter::once(TextDrawAdjusted::Text(text.clone())));
+                }
+                Op::TextDrawAdjusted { ref array } => {
+                    self.show(&state, &mut tm, array.iter().cloned());
+                }
+                Op::XObject { ref name } if depth < MAX_FORM_DEPTH => {
+                    let xobject = match resources.xobjects.get(name).map(|&r| self.resolve.get(r)) {
+                        Some(Ok(xobject)) => xobject,
+                        Some(Err(e)) => {
+                            warn!("can't read XObject {:?}: {}", name, e);
+                            continue;
+                        }
+                        None => {
+                            warn!("XObject {:?} not in resources", name);
+                            continue;
+                        }
+                    };
+                    if let XObject::Form(ref form) = *xobject {
+                        let dict = form.dict();
+                        let matrix = match dict.matrix.as_ref().map(|p| matrix(p, self.resolve)) {
+                            Some(Ok(matrix)) => matrix,
+                            Some(Err(e)) => {
+                                warn!("invalid Matrix of form {:?}: {}", name, e);
+                                continue;
+                            }
+                            None => Matrix::default()
+                        };
+                        let resources = dict.resources.as_deref().unwrap_or(resources);
+                        self.run(&form.operations, resources, concat(&matrix, &state.ctm), depth + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Show the strings in `parts`, advancing the text matrix `tm`.
+    fn show(&mut self, state: &State, tm: &mut Matrix, parts: impl Iterator<Item=TextDrawAdjusted>) {
+        let font = match state.font {
+            Some(ref font) => font.clone(),
+            None => return
+        };
+        let size = state.font_size;
+        let scale = state.horiz_scale;
+        let transform = concat(&concat(&translate(Point { x: 0., y: state.rise }), tm), &state.ctm);
+
+        let mut text = String::new();
+        let mut chars = vec![];
+        // advance since the start of the span, in text space: to the right, or down for vertical fonts
+        let mut x = 0.;
+        // the glyph boxes in text space
+        let (mut low, mut high) = match font.vertical {
+            Some(_) => (Point { x: f32::INFINITY, y: f32::INFINITY }, Point { x: f32::NEG_INFINITY, y: f32::NEG_INFINITY }),
+            None => (Point { x: 0., y: font.descent * size }, Point { x: 0., y: font.ascent * size })
+        };
+        for part in parts {
+            match part {
+                TextDrawAdjusted::Text(s) => {
+                    let data = s.as_bytes();
+                    for (code, len) in font.codes(data) {
+                        let width = font.width(code, len);
+                        let mut advance = match font.vertical {
+                            Some(ref metrics) => {
+                                let cid = font.cid(code, len).unwrap_or(0);
+                                let metric = metrics.get(cid as usize, width);
+                                // the vertical origin is placed at the current position
+                                let (v_x, v_y) = (metric.position.0 / 1000. * size, metric.position.1 / 1000. * size);
+                                low.x = low.x.min(-v_x);
+                                high.x = high.x.max(width / 1000. * size - v_x);
+                                low.y = low.y.min(-x + font.descent * size - v_y);
+                                high.y = high.y.max(-x + font.ascent * size - v_y);
+                                -metric.advance / 1000. * size
+                            }
+                            None => width / 1000. * size
+                        };
+                        advance += state.char_space;
+                        if code == 32 && len == 1 {
+                            advance += state.word_space;
+                        }
+                        if font.vertical.is_none() {
+                            advance *= scale;
+                        }
+                        let offset = text.len();
+                        font.decode(code, len, &mut text);
+                        chars.push(TextChar { offset, pos: x, width: advance });
+                        x += advance;
+                    }
+                }
+                TextDrawAdjusted::Spacing(n) => {
+                    let advance = match font.vertical {
+                        Some(_) => n / 1000. * size,
+                        None => -n / 1000. * size * scale
+                    };
+                    let gap = match font.vertical {
+                        Some(_) => n > SPACE_THRESHOLD,
+                        None => n < -SPACE_THRESHOLD
+                    };
+                    if gap && !text.is_empty() && !text.ends_with(char::is_whitespace) {
+                        chars.push(TextChar { offset: text.len(), pos: x, width: advance });
+                        text.push(' ');
+                    }
+                    x += advance;
+                }
+            }
+        }
+        *tm = match font.vertical {
+            Some(_) => concat(&translate(Point { x: 0., y: -x }), tm),
+            None => concat(&translate(Point { x, y: 0. }), tm)
+        };
+
+        if text.is_empty() {
+            return;
+        }
+        if font.vertical.is_none() {
+            high.x = x;
+        }
+        let corners = [
+            Point { x: low.x, y: low.y },
+            Point { x: high.x, y: low.y },
+            Point { x: low.x, y: high.y },
+            Point { x: high.x, y: high.y },
+        ];
+        let (mut min, mut max) = (Point { x: f32::INFINITY, y: f32::INFINITY }, Point { x: f32::NEG_INFINITY, y: f32::NEG_INFINITY });
+        for &p in corners.iter() {
+            let p = apply(&transform, p);
+            min.x = min.x.min(p.x);
+            min.y = min.y.min(p.y);
+            max.x = max.x.max(p.x);
+            max.y = max.y.max(p.y);
+        }
+
+        self.spans.push(TextSpan {
+            text,
+            bbox: Rect { x: min.x, y: min.y, width: max.x - min.x, height: max.y - min.y },
+            font: font.name.clone(),
+            size: size * transform.c.hypot(transform.d),
+            transform,
+            chars,
+            vertical: font.vertical.is_some(),
+        });
+    }
+}
+
+fn translate(p: Point) -> Matrix {
+    Matrix { e: p.x, f: p.y, ..Matrix::default() }
+}
+
+/// The transformation that applies `a` first, then `b`.
+fn concat(a: &Matrix, b: &Matrix) -> Matrix {
+    Matrix {
+        a: a.a * b.a + a.b * b.c,
+        b: a.a * b.b + a.b * b.d,
+        c: a.c * b.a + a.d * b.c,
+        d: a.c * b.b + a.d * b.d,
+        e: a.e * b.a + a.f * b.c + b.e,
+        f: a.e * b.b + a.f * b.d + b.f,
+    }
+}
+
+fn apply(m: &Matrix, p: Point) -> Point {
+    Point {
+        x: m.a * p.x + m.c * p.y + m.e,
+        y: m.b * p.x + m.d * p.y + m.f,
+    }
+}
+
+fn matrix(p: &Primitive, resolve: &impl Resolve) -> Result<Matrix> {
+    let arr = p.clone().into_array(resolve)?;
+    if arr.len() != 6 {
+        bail!("matrix has {} entries instead of 6", arr.len());
+    }
+    let n = |i: usize| arr[i].as_number();
+    Ok(Matrix { a: n(0)?, b: n(1)?, c: n(2)?, d: n(3)?, e: n(4)?, f: n(5)? })
+}
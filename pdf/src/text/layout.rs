@@ -0,0 +1,357 @@
+//! Grouping of text spans into words, lines, paragraphs and columns.
+//!
+//! The heuristics assume horizontal text that runs left to right; spans of vertical text are kept
+//! as single words. Lines are built in content stream order, because most producers draw a line
+//! (and a column) in one go, and the columns are put into reading order at the end: top to
+//! bottom, and side by side columns left to right.
+
+use super::{page_spans, TextSpan};
+use crate::content::Rect;
+use crate::error::Result;
+use crate::object::{Page, Resolve};
+
+/// Spans closer than this (in em) continue the word of the previous span.
+const MAX_CHAR_GAP: f32 = 0.15;
+
+/// Words further apart than this (in em) are not put on the same line.
+const MAX_WORD_GAP: f32 = 2.0;
+
+/// Vertical space between lines (relative to the line height) that separates paragraphs.
+const PARAGRAPH_GAP: f32 = 0.5;
+
+/// A line that starts further right than this (in em) after a short line begins a new paragraph.
+const INDENT: f32 = 1.0;
+
+/// Characters between whitespace, possibly from several spans.
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub text: String,
+    pub bbox: Rect,
+
+    /// Font and size of the first character
+    pub font: String,
+    pub size: f32,
+}
+
+/// Words on one baseline.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub words: Vec<Word>,
+    pub bbox: Rect,
+}
+impl Line {
+    /// The words, separated by single spaces.
+    pub fn text(&self) -> String {
+        let words: Vec<&str> = self.words.iter().map(|w| w.text.as_str()).collect();
+        words.join(" ")
+    }
+    fn size(&self) -> f32 {
+        self.words.iter().map(|w| w.size).fold(0., f32::max)
+    }
+}
+
+/// Lines of similar size without a gap between them.
+#[derive(Debug, Clone)]
+pub struct Paragraph {
+    pub lines: Vec<Line>,
+    pub bbox: Rect,
+}
+impl Paragraph {
+    /// The lines joined by spaces. Words that are hyphenated at the end of a line are joined again.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            let text = line.text();
+            if out.is_empty() {
+                out = text;
+                continue;
+            }
+            let starts_lower = text.chars().next().is_some_and(char::is_lowercase);
+            if out.ends_with('\u{AD}') {
+                // soft hyphens are only visible at line breaks
+                out.pop();
+            } else if starts_lower && ends_with_hyphenated_word(&out) {
+                out.pop();
+            } else {
+                out.push(' ');
+            }
+            out.push_str(&text);
+        }
+        out
+    }
+}
+
+fn ends_with_hyphenated_word(s: &str) -> bool {
+    let mut chars = s.chars().rev();
+    matches!((chars.next(), chars.next()), (Some('-'), Some(c)) if c.is_alphabetic())
+}
+
+/// Paragraphs stacked on top of each other, top to bottom.
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub paragraphs: Vec<Paragraph>,
+    pub bbox: Rect,
+}
+
+/// The text of a page, grouped into columns, paragraphs, lines and words.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// In reading order.
+    pub columns: Vec<Column>,
+}
+impl Layout {
+    /// Analyse the spans of a page, as returned by `page_spans`.
+    pub fn from_spans(spans: &[TextSpan]) -> Layout {
+        let lines = lines(words(spans));
+        let columns = order_columns(columns(lines))
+            .into_iter()
+            .map(|lines| {
+                let paragraphs = paragraphs(lines);
+                let bbox = paragraphs.iter().map(|p| p.bbox).reduce(union).unwrap();
+                Column { paragraphs, bbox }
+            })
+            .collect();
+        Layout { columns }
+    }
+
+    /// Plain text, with one paragraph per line and a blank line between columns.
+    pub fn text(&self) -> String {
+        let columns: Vec<String> = self.columns.iter().map(|column| {
+            let paragraphs: Vec<String> = column.paragraphs.iter().map(|p| p.text()).collect();
+            paragraphs.join("\n")
+        }).collect();
+        columns.join("\n\n")
+    }
+}
+
+/// Extract the text of `page` and group it into a `Layout`.
+pub fn page_layout(page: &Page, resolve: &impl Resolve) -> Result<Layout> {
+    Ok(Layout::from_spans(&page_spans(page, resolve)?))
+}
+
+fn right(r: &Rect) -> f32 {
+    r.x + r.width
+}
+fn top(r: &Rect) -> f32 {
+    r.y + r.height
+}
+fn union(a: Rect, b: Rect) -> Rect {
+    let x = a.x.min(b.x);
+    let y = a.y.min(b.y);
+    Rect { x, y, width: right(&a).max(right(&b)) - x, height: top(&a).max(top(&b)) - y }
+}
+fn vertical_overlap(a: &Rect, b: &Rect) -> f32 {
+    top(a).min(top(b)) - a.y.max(b.y)
+}
+fn horizontal_overlap(a: &Rect, b: &Rect) -> f32 {
+    right(a).min(right(b)) - a.x.max(b.x)
+}
+/// Whether `a` and `b` share most of their height.
+fn same_line(a: &Rect, b: &Rect) -> bool {
+    vertical_overlap(a, b) > 0.5 * a.height.min(b.height)
+}
+
+/// Split the spans at whitespace and join adjacent spans without a gap.
+fn words(spans: &[TextSpan]) -> Vec<Word> {
+    let mut words = vec![];
+    let mut current: Option<Word> = None;
+
+    for span in spans {
+        if span.vertical {
+            // not split any further, a vertical span becomes one word
+            words.extend(current.take());
+            let text = span.text.trim();
+            if !text.is_empty() {
+                words.push(Word { text: text.into(), bbox: span.bbox, font: span.font.clone(), size: span.size });
+            }
+            continue;
+        }
+        let x = |pos: f32| span.transform.a * pos + span.transform.e;
+        let start = span.chars.first().map_or(0., |c| c.pos);
+        if let Some(ref word) = current {
+            let gap = x(start) - right(&word.bbox);
+            if !same_line(&word.bbox, &span.bbox) || gap.abs() > MAX_CHAR_GAP * span.size {
+                words.extend(current.take());
+            }
+        }
+
+        for (i, c) in span.chars.iter().enumerate() {
+            let end = span.chars.get(i + 1).map_or(span.text.len(), |next| next.offset);
+            let s = &span.text[c.offset .. end];
+            if s.chars().all(char::is_whitespace) {
+                words.extend(current.take());
+                continue;
+            }
+            let (x0, x1) = (x(c.pos), x(c.pos + c.width));
+            let bbox = Rect { x: x0.min(x1), y: span.bbox.y, width: (x1 - x0).abs(), height: span.bbox.height };
+            match current {
+                Some(ref mut word) => {
+                    word.text.push_str(s);
+                    word.bbox = union(word.bbox, bbox);
+                }
+                None => current = Some(Word {
+                    text: s.into(),
+                    bbox,
+                    font: span.font.clone(),
+                    size: span.size,
+                })
+            }
+        }
+    }
+    words.extend(current);
+    words
+}
+
+/// Put consecutive words on one baseline into lines.
+fn lines(words: Vec<Word>) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    for word in words {
+        if let Some(line) = lines.last_mut() {
+            let last = &line.words[line.words.len() - 1].bbox;
+            let gap = word.bbox.x - right(last);
+            let size = word.size.max(line.size());
+            if same_line(&line.bbox, &word.bbox) && gap > -0.5 * size && gap < MAX_WORD_GAP * size {
+                line.bbox = union(line.bbox, word.bbox);
+                line.words.push(word);
+                continue;
+            }
+        }
+        lines.push(Line { bbox: word.bbox, words: vec![word] });
+    }
+    lines
+}
+
+/// The lines of a column and their bounding box.
+type ColumnLines = (Rect, Vec<Line>);
+
+/// Stack lines that share horizontal space and follow each other closely into columns.
+fn columns(lines: Vec<Line>) -> Vec<Vec<Line>> {
+    let mut columns: Vec<ColumnLines> = vec![];
+    for line in lines {
+        let height = line.bbox.height;
+        let column = columns.iter_mut().rev().find(|(bbox, lines)| {
+            let above = &lines[lines.len() - 1].bbox;
+            horizontal_overlap(bbox, &line.bbox) > 0.
+                && top(&line.bbox) < top(above)
+                && above.y - top(&line.bbox) < 2.5 * height
+        });
+        match column {
+            Some((bbox, lines)) => {
+                *bbox = union(*bbox, line.bbox);
+                lines.push(line);
+            }
+            None => columns.push((line.bbox, vec![line]))
+        }
+    }
+    columns.into_iter().map(|(_, lines)| lines).collect()
+}
+
+/// Sort columns into bands of vertically overlapping columns, read top to bottom, and each band
+/// left to right.
+fn order_columns(columns: Vec<Vec<Line>>) -> Vec<Vec<Line>> {
+    let bbox = |lines: &[Line]| lines.iter().map(|l| l.bbox).reduce(union).unwrap();
+    let mut columns: Vec<ColumnLines> = columns.into_iter().map(|c| (bbox(&c), c)).collect();
+    columns.sort_by(|a, b| top(&b.0).total_cmp(&top(&a.0)));
+
+    // the bottom of each band and its columns
+    let mut bands: Vec<(f32, Vec<ColumnLines>)> = vec![];
+    for column in columns {
+        match bands.last_mut() {
+            Some((bottom, band)) if top(&column.0) > *bottom => {
+                *bottom = bottom.min(column.0.y);
+                band.push(column);
+            }
+            _ => bands.push((column.0.y, vec![column]))
+        }
+    }
+    bands.into_iter().flat_map(|(_, mut band)| {
+        band.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+        band.into_iter().map(|(_, lines)| lines)
+    }).collect()
+}
+
+/// Split the lines of a column at larger gaps, changes of the font size and indented lines.
+fn paragraphs(mut lines: Vec<Line>) -> Vec<Paragraph> {
+    lines.sort_by(|a, b| top(&b.bbox).total_cmp(&top(&a.bbox)));
+    let left = lines.iter().map(|l| l.bbox.x).fold(f32::INFINITY, f32::min);
+    let right_edge = lines.iter().map(|l| right(&l.bbox)).fold(f32::NEG_INFINITY, f32::max);
+
+    let mut paragraphs: Vec<Paragraph> = vec![];
+    for line in lines {
+        if let Some(p) = paragraphs.last_mut() {
+            let prev = &p.lines[p.lines.len() - 1];
+            let gap = prev.bbox.y - top(&line.bbox);
+            let (size, prev_size) = (line.size(), prev.size());
+            let similar_size = size.max(prev_size) <= 1.2 * size.min(prev_size);
+            // a first line indent follows a short last line, a hanging indent (as in lists) does not
+            let indented = line.bbox.x - left > INDENT * size && line.bbox.x - prev.bbox.x > INDENT * size
+                && right_edge - right(&prev.bbox) > INDENT * size;
+            if gap < PARAGRAPH_GAP * prev.bbox.height && similar_size && !indented {
+                p.bbox = union(p.bbox, line.bbox);
+                p.lines.push(line);
+                continue;
+            }
+        }
+        paragraphs.push(Paragraph { bbox: line.bbox, lines: vec![line] });
+    }
+    paragraphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::Matrix;
+    use crate::text::TextChar;
+
+    /// A span with 10pt monospaced glyphs, 6pt wide.
+    fn span(text: &str, x: f32, y: f32) -> TextSpan {
+        let chars = text.char_indices().enumerate()
+            .map(|(i, (offset, _))| TextChar { offset, pos: i as f32 * 6., width: 6. })
+            .collect();
+        TextSpan {
+            text: text.into(),
+            bbox: Rect { x, y: y - 2., width: text.chars().count() as f32 * 6., height: 10. },
+            font: "Mono".into(),
+            size: 10.,
+            transform: Matrix { e: x, f: y, ..Matrix::default() },
+            chars,
+            vertical: false,
+        }
+    }
+
+    #[test]
+    fn words_across_spans() {
+        let spans = [span("Hel", 0., 100.), span("lo world", 18., 100.)];
+        let layout = Layout::from_spans(&spans);
+        let line = &layout.columns[0].paragraphs[0].lines[0];
+        let words: Vec<&str> = line.words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(words, ["Hello", "world"]);
+        assert_eq!(line.words[1].bbox.x, 36.);
+    }
+
+    #[test]
+    fn two_columns() {
+        // drawn line by line across both columns
+        let spans = [
+            span("left one", 0., 100.), span("right one", 200., 100.),
+            span("left two", 0., 88.), span("right two", 200., 88.),
+            span("Spanning heading", 0., 130.),
+        ];
+        let layout = Layout::from_spans(&spans);
+        let text = layout.text();
+        assert_eq!(text, "Spanning heading\n\nleft one left two\n\nright one right two");
+    }
+
+    #[test]
+    fn paragraphs_and_hyphenation() {
+        let spans = [
+            span("The first para-", 0., 100.),
+            span("graph ends.", 0., 88.),
+            span("Second one.", 12., 76.),
+            span("After a gap.", 0., 50.),
+        ];
+        let layout = Layout::from_spans(&spans);
+        let texts: Vec<String> = layout.columns[0].paragraphs.iter().map(|p| p.text()).collect();
+        assert_eq!(texts, ["The first paragraph ends.", "Second one.", "After a gap."]);
+    }
+}
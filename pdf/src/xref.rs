@@ -1,4 +1,5 @@
 use std::fmt::{Debug, Formatter};
+use std::io;
 use crate::error::*;
 use crate::object::*;
 use crate as pdf;
@@ -43,6 +44,15 @@ impl XRef {
 }
 
 
+/// How the cross-reference section of a revision is stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum XRefKind {
+    /// A classic `xref` table followed by a `trailer` dictionary.
+    Table,
+    /// A cross-reference stream (PDF 1.5).
+    Stream,
+}
+
 /// Runtime lookup table of all objects
 #[derive(Clone)]
 pub struct XRefTable {
@@ -123,30 +133,75 @@ impl XRefTable {
     }
 
     pub fn write_stream(&self, size: usize) -> Result<Stream<XRefInfo>> {
-        let (max_a, max_b) = self.max_field_widths();
-        let a_w = byte_len(max_a);
-        let b_w = byte_len(max_b);
+        let ids: Vec<ObjNr> = (0 .. size as ObjNr).collect();
+        Ok(t!(self.write_stream_sections(&ids, size)).hexencode())
+    }
 
-        let mut data = Vec::with_capacity((1 + a_w + b_w) * size);
-        for &x in self.entries.iter().take(size) {
-            let (t, a, b) = match x {
+    /// Encode the entries of `ids` (sorted, ascending) as an unfiltered cross-reference stream.
+    /// Consecutive ids are grouped into one subsection each.
+    pub fn write_stream_sections(&self, ids: &[ObjNr], size: usize) -> Result<Stream<XRefInfo>> {
+        let mut entries = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let (t, a, b) = match t!(self.get(id)) {
                 XRef::Free { next_obj_nr, gen_nr } => (0, next_obj_nr, gen_nr as u64),
                 XRef::Raw { pos, gen_nr } => (1, pos as u64, gen_nr as u64),
                 XRef::Stream { stream_id, index } => (2, stream_id as u64, index as u64),
-                x => panic!("invalid xref entry: {:?}", x)
+                x => bail!("invalid xref entry {}: {:?}", id, x)
             };
+            entries.push((t, a, b));
+        }
+        let max_a = entries.iter().map(|&(_, a, _)| a).max().unwrap_or(0);
+        let max_b = entries.iter().map(|&(_, _, b)| b).max().unwrap_or(0);
+        let a_w = byte_len(max_a);
+        let b_w = byte_len(max_b);
+
+        let mut data = Vec::with_capacity((1 + a_w + b_w) * entries.len());
+        for (t, a, b) in entries {
             data.push(t);
             data.extend_from_slice(&a.to_be_bytes()[8 - a_w ..]);
             data.extend_from_slice(&b.to_be_bytes()[8 - b_w ..]);
         }
+        let index = subsections(ids).into_iter()
+            .flat_map(|(first, n)| vec![first as i32, n as i32])
+            .collect();
         let info = XRefInfo {
             size: size as i32,
-            index: vec![0, size as i32],
+            index,
             prev: None,
             w: vec![1, a_w as i32, b_w as i32],
         };
-        Ok(Stream::new(info, data).hexencode())
+        Ok(Stream::new(info, data))
+    }
+
+    /// Write the entries of `ids` (sorted, ascending) as a classic `xref` section,
+    /// not including the trailer.
+    pub fn write_table(&self, ids: &[ObjNr], out: &mut impl io::Write) -> Result<()> {
+        writeln!(out, "xref")?;
+        let mut rest = ids.iter();
+        for (first, n) in subsections(ids) {
+            writeln!(out, "{} {}", first, n)?;
+            for &id in rest.by_ref().take(n) {
+                match t!(self.get(id)) {
+                    XRef::Free { next_obj_nr, gen_nr } => write!(out, "{:010} {:05} f\r\n", next_obj_nr, gen_nr)?,
+                    XRef::Raw { pos, gen_nr } => write!(out, "{:010} {:05} n\r\n", pos, gen_nr)?,
+                    x => bail!("xref entry {} can not be written to a table: {:?}", id, x)
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split sorted ids into runs of consecutive numbers, as `(first id, count)`.
+fn subsections(ids: &[ObjNr]) -> Vec<(ObjNr, usize)> {
+    let mut sections: Vec<(ObjNr, usize)> = Vec::new();
+    for &id in ids {
+        match sections.last_mut() {
+            Some((first, n)) if *first + *n as ObjNr == id => *n += 1,
+            _ => sections.push((id, 1))
+        }
     }
+    sections
 }
 
 fn byte_len(n: u64) -> usize {
@@ -219,7 +274,7 @@ pub struct XRefInfo {
     pub index: Vec<i32>,
 
     #[pdf(key = "Prev")]
-    prev: Option<i32>,
+    pub prev: Option<i32>,
 
     #[pdf(key = "W")]
     pub w: Vec<i32>,
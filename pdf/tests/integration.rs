@@ -1,8 +1,8 @@
 use std::str;
-use std::rc::Rc;
 use pdf::file::File;
 use pdf::object::*;
 use pdf::parser::parse;
+use pdf::primitive::Primitive;
 use glob::glob;
 
 macro_rules! file_path {
@@ -62,6 +62,7 @@ fn user_password() {
 
                 let path = path.to_str().unwrap();
                 let file = run!(File::<Vec<u8>>::open_password(path, b"userpassword"));
+                assert_eq!(file.password_type(), Some(pdf::crypt::PasswordType::User));
                 for i in 0 .. file.num_pages() {
                     println!("\nRead page {}", i);
                     let _ = file.get_page(i);
@@ -83,6 +84,7 @@ fn owner_password() {
 
                 let path = path.to_str().unwrap();
                 let file = run!(File::<Vec<u8>>::open_password(path, b"ownerpassword"));
+                assert_eq!(file.password_type(), Some(pdf::crypt::PasswordType::Owner));
                 for i in 0 .. file.num_pages() {
                     println!("\nRead page {}", i);
                     let _ = file.get_page(i);
@@ -93,6 +95,521 @@ fn owner_password() {
     }
 }
 
+#[cfg(feature = "pubsec")]
+#[test]
+fn pubsec_recipient() {
+    use pdf::crypt::{PubSecKey, Permissions};
+
+    let key = std::fs::read_to_string(file_path!("pubsec/key.pem")).unwrap();
+    let cert = std::fs::read_to_string(file_path!("pubsec/cert.pem")).unwrap();
+    let key = run!(PubSecKey::from_pem(&key, &cert));
+    let file = run!(File::<Vec<u8>>::open_pubsec(file_path!("pubsec/encrypted.pdf"), &key));
+
+    let title = file.trailer.info_dict.as_ref().unwrap().get("Title").unwrap();
+    assert_eq!(title.as_string().unwrap().as_bytes(), b"public key encryption");
+
+    match run!(file.resolve(PlainRef { id: 4, gen: 0 })) {
+        Primitive::Stream(stream) => assert_eq!(stream.data, b"BT /F1 24 Tf 72 700 Td (Hello PubSec) Tj ET"),
+        p => panic!("{:?}", p)
+    }
+    assert_eq!(file.permissions(), Permissions::PRINT | Permissions::COPY);
+
+    // not a password-protected file
+    assert!(File::<Vec<u8>>::open(file_path!("pubsec/encrypted.pdf")).is_err());
+}
+
+#[test]
+fn lazy_backend() {
+    use pdf::backend::LazyBackend;
+
+    for entry in glob(file_path!("*.pdf")).expect("Failed to read glob pattern") {
+        let path = entry.unwrap();
+        println!("\n == Now testing `{}` ==", path.to_str().unwrap());
+
+        let file = run!(File::<Vec<u8>>::open(&path));
+        // a single block of cache
+        let lazy = run!(File::from_data(run!(LazyBackend::with_cache_size(std::fs::File::open(&path).unwrap(), 0))));
+        assert_eq!(file.num_pages(), lazy.num_pages());
+        for id in 1 .. file.trailer.highest_id as u64 {
+            let r = PlainRef { id, gen: 0 };
+            if let Ok(p) = file.resolve(r) {
+                assert_eq!(format!("{:?}", p), format!("{:?}", run!(lazy.resolve(r))));
+            }
+        }
+    }
+}
+
+#[test]
+fn parallel_pages() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<File<Vec<u8>>>();
+    assert_send_sync::<File<pdf::backend::LazyBackend<std::fs::File>>>();
+    assert_send_sync::<PageRc>();
+    assert_send_sync::<pdf::any::Any>();
+
+    let file = run!(File::<Vec<u8>>::open(file_path!("ep.pdf")));
+    let expected: Vec<usize> = file.pages()
+        .map(|page| run!(page).contents.as_ref().map_or(0, |c| run!(c.operations(&file)).len()))
+        .collect();
+
+    let file = run!(File::<Vec<u8>>::open(file_path!("ep.pdf")));
+    let counts: Vec<usize> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0 .. file.num_pages()).map(|n| {
+            let file = &file;
+            s.spawn(move || {
+                let page = run!(file.get_page(n));
+                page.contents.as_ref().map_or(0, |c| run!(c.operations(file)).len())
+            })
+        }).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(counts, expected);
+}
+
+#[test]
+fn text_spans() {
+    use pdf::text::page_spans;
+
+    let file = run!(File::<Vec<u8>>::open(file_path!("example.pdf")));
+    let page = run!(file.get_page(0));
+    let spans = run!(page_spans(&page, &file));
+    assert_eq!(spans.len(), 1);
+    let span = &spans[0];
+    assert_eq!(span.text, "Hello World!");
+    assert_eq!(span.font, "Times-Roman");
+    assert_eq!(span.size, 24.);
+    assert_eq!(span.chars.len(), 12);
+    assert!(span.bbox.width > 0. && span.bbox.height > 0.);
+
+    // spans on separate lines are stacked top to bottom
+    let file = run!(File::<Vec<u8>>::open(file_path!("pdf-sample.pdf")));
+    let page = run!(file.get_page(0));
+    let spans = run!(page_spans(&page, &file));
+    assert_eq!(spans[0].text, "Adobe Acrobat PDF Files");
+    assert!(spans[1].text.starts_with("Adobe® Portable Document Format (PDF)"));
+    assert!(spans[1].bbox.y < spans[0].bbox.y);
+    assert!(spans[2].bbox.y < spans[1].bbox.y);
+    assert_eq!(spans[1].bbox.x, spans[2].bbox.x);
+}
+
+#[cfg(feature = "standard-fonts")]
+#[test]
+fn standard_font_widths() {
+    use pdf::text::page_spans;
+
+    // Times-Roman without /Widths
+    let file = run!(File::<Vec<u8>>::open(file_path!("example.pdf")));
+    let page = run!(file.get_page(0));
+    let spans = run!(page_spans(&page, &file));
+    let span = &spans[0];
+    assert!((span.chars[0].width - 0.722 * 24.).abs() < 0.01);
+    assert!((span.bbox.width - 5.360 * 24.).abs() < 0.01);
+}
+
+/// A PDF file with the given objects, numbered from 1, and `1 0 R` as the catalog.
+fn build_pdf(objects: &[&str]) -> Vec<u8> {
+    let mut out = b"%PDF-1.7\n".to_vec();
+    let mut offsets = vec![];
+    for (i, obj) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, obj).as_bytes());
+    }
+    let xref = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).as_bytes());
+    out
+}
+
+#[test]
+fn vertical_text() {
+    use pdf::text::page_spans;
+
+    let content = "BT /F1 10 Tf 100 700 Td <00010002> Tj <0001> Tj ET";
+    let to_unicode = "1 begincodespacerange <0000> <FFFF> endcodespacerange 2 beginbfchar <0001> <7E26> <0002> <66F8> endbfchar\n";
+    let content = format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content);
+    let to_unicode = format!("<< /Length {} >>\nstream\n{}\nendstream", to_unicode.len(), to_unicode);
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 800] /Contents 7 0 R /Resources << /Font << /F1 4 0 R >> >> >>",
+        "<< /Type /Font /Subtype /Type0 /BaseFont /Vertical /Encoding /Identity-V /DescendantFonts [5 0 R] /ToUnicode 8 0 R >>",
+        "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /Vertical /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> \
+            /FontDescriptor 6 0 R /W [1 [1000 1000]] /W2 [2 [-500 500 880]] /DW2 [880 -1000] >>",
+        "<< /Type /FontDescriptor /FontName /Vertical /Flags 4 /FontBBox [0 -120 1000 880] /ItalicAngle 0 /Ascent 880 /Descent -120 >>",
+        &content,
+        &to_unicode,
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let page = run!(file.get_page(0));
+
+    let font = run!(file.get(page.resources().unwrap().fonts["F1"]));
+    assert!(font.is_vertical());
+    let metrics = run!(font.vertical_metrics(&file)).unwrap();
+    assert_eq!(metrics.get(1, 1000.).advance, -1000.);
+    assert_eq!(metrics.get(1, 1000.).position, (500., 880.));
+    assert_eq!(metrics.get(2, 1000.).advance, -500.);
+
+    let spans = run!(page_spans(&page, &file));
+    assert_eq!(spans.len(), 2);
+    assert!(spans[0].vertical);
+    assert_eq!(spans[0].text, "縦書");
+    assert_eq!(spans[0].chars[1].pos, 10.);
+    assert_eq!(spans[0].chars[1].width, 5.);
+    // glyphs are centered below the pen position, the second one only advances half an em
+    let bbox = spans[0].bbox;
+    assert_eq!((bbox.x, bbox.width), (95., 10.));
+    assert_eq!((bbox.y, bbox.y + bbox.height), (680., 700.));
+    assert_eq!(spans[1].bbox.y + spans[1].bbox.height, 685.);
+}
+
+#[test]
+fn simple_font_unicode() {
+    use pdf::font::Font;
+
+    let to_unicode = "1 begincodespacerange <00> <FF> endcodespacerange 1 beginbfchar <41> <005A> endbfchar\n";
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [] /Count 0 >>",
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding << /Differences [65 /Euro /f_f] >> >>",
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /ToUnicode 6 0 R >>",
+        "<< /Type /Font /Subtype /Type0 /BaseFont /Empty /Encoding /Identity-H /DescendantFonts [] >>",
+        &format!("<< /Length {} >>\nstream\n{}\nendstream", to_unicode.len(), to_unicode),
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let font = |id| run!(file.get(Ref::<Font>::new(PlainRef { id, gen: 0 })));
+
+    // the glyph names of the encoding stand in for a missing ToUnicode
+    let map = run!(font(3).to_unicode().unwrap());
+    assert_eq!(map.get(65), Some("€"));
+    assert_eq!(map.get(66), Some("ff"));
+    assert_eq!(map.get(67), Some("C"));
+    // and for codes it leaves out
+    let map = run!(font(4).to_unicode().unwrap());
+    assert_eq!(map.get(65), Some("Z"));
+    assert_eq!(map.get(66), Some("B"));
+    assert!(font(5).to_unicode().is_none());
+}
+
+#[test]
+fn text_with_invalid_xobjects() {
+    use pdf::text::page_spans;
+
+    let stream = |dict: &str, s: &str| format!("<< {} /Length {} >>\nstream\n{}\nendstream", dict, s.len(), s);
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Contents 4 0 R \
+            /Resources << /Font << /F1 5 0 R >> /XObject << /Im1 6 0 R /Fm1 7 0 R /Fm2 8 0 R >> >> >>",
+        &stream("", "BT /F1 12 Tf 10 10 Td (Hi) Tj ET /Im1 Do /Fm1 Do /Fm2 Do\n"),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+        &stream("/Type /XObject /Subtype /Image", ""),
+        &stream("/Type /XObject /Subtype /Form /BBox [0 0 100 100] /Matrix [1 0]", "BT /F1 12 Tf 10 50 Td (No) Tj ET\n"),
+        &stream("/Type /XObject /Subtype /Form /BBox [0 0 100 100] /Resources << /Font << /F1 5 0 R >> >>", "BT /F1 12 Tf 10 80 Td (Yo) Tj ET\n"),
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let page = run!(file.get_page(0));
+    // the image and the form with an invalid matrix are skipped
+    let spans = run!(page_spans(&page, &file));
+    let text: Vec<_> = spans.iter().map(|s| s.text.as_str()).collect();
+    assert_eq!(text, ["Hi", "Yo"]);
+}
+
+#[test]
+fn invalid_cid_fonts() {
+    use pdf::font::Font;
+
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [] /Count 0 >>",
+        "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /Huge /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> \
+            /FontDescriptor 6 0 R /W2 [0 2000000000 -1000 500 880] >>",
+        "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /Reversed /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> \
+            /FontDescriptor 6 0 R /W2 [10 5 -1000 500 880] >>",
+        "<< /Type /Font /Subtype /Type0 /BaseFont /Empty /Encoding /Identity-H /DescendantFonts [] >>",
+        "<< /Type /FontDescriptor /FontName /Vertical /Flags 4 /FontBBox [0 -120 1000 880] /ItalicAngle 0 /Ascent 880 /Descent -120 >>",
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let font = |id| run!(file.get(Ref::<Font>::new(PlainRef { id, gen: 0 })));
+    assert!(font(3).vertical_metrics(&file).is_err());
+    assert!(font(4).vertical_metrics(&file).is_err());
+    assert!(font(5).vertical_metrics(&file).is_err());
+    assert!(font(5).widths(&file).is_err());
+}
+
+#[test]
+fn type3_font() {
+    use pdf::content::Op;
+    use pdf::text::page_spans;
+
+    let content = "BT /F1 12 Tf 10 10 Td <0102> Tj ET";
+    let square = "500 0 0 0 500 500 d1 0 0 500 500 re f\n";
+    let dot = "250 0 d0 100 100 50 50 re f\n";
+    let stream = |s: &str| format!("<< /Length {} >>\nstream\n{}\nendstream", s.len(), s);
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Contents 5 0 R /Resources << /Font << /F1 4 0 R >> >> >>",
+        "<< /Type /Font /Subtype /Type3 /FontBBox [0 0 500 500] /FontMatrix [0.002 0 0 0.002 0 0] \
+            /CharProcs << /A 6 0 R /period 7 0 R >> /Encoding << /Type /Encoding /Differences [1 /A /period] >> \
+            /FirstChar 1 /LastChar 2 /Widths [500 250] >>",
+        &stream(content),
+        &stream(square),
+        &stream(dot),
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let page = run!(file.get_page(0));
+
+    let font = run!(file.get(page.resources().unwrap().fonts["F1"]));
+    let widths = run!(font.widths(&file)).unwrap();
+    assert_eq!(widths.get(1), 1000.);
+    assert_eq!(widths.get(2), 500.);
+
+    let ops = run!(font.type3_glyph(1, &file).unwrap());
+    assert!(matches!(ops[0], Op::CacheDevice { .. }));
+    assert_eq!(ops.len(), 3);
+    let ops = run!(font.type3_glyph(2, &file).unwrap());
+    assert!(matches!(ops[0], Op::CharWidth { width } if width.x == 250.));
+    assert!(font.type3_glyph(3, &file).is_none());
+
+    let spans = run!(page_spans(&page, &file));
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].text, "A.");
+    assert_eq!(spans[0].bbox.width, 18.);
+}
+
+#[test]
+fn text_layout() {
+    let file = run!(File::<Vec<u8>>::open(file_path!("pdf-sample.pdf")));
+    let page = run!(file.get_page(0));
+    let layout = run!(pdf::text::page_layout(&page, &file));
+    assert_eq!(layout.columns.len(), 1);
+    let paragraphs = &layout.columns[0].paragraphs;
+    assert_eq!(paragraphs.len(), 8);
+    assert_eq!(paragraphs[0].text(), "Adobe Acrobat PDF Files");
+    assert_eq!(paragraphs[1].lines.len(), 3);
+    assert!(paragraphs[3].text().starts_with("• Anyone, anywhere can open a PDF file."));
+    assert!(paragraphs[3].text().ends_with("used to create the documents."));
+}
+
+#[test]
+fn font_programs() {
+    use pdf::font::{FontProgram, PathOp};
+
+    for &(name, truetype) in &[("libreoffice.pdf", true), ("xelatex.pdf", false)] {
+        let path = format!("{}{}", file_path!(""), name);
+        let file = run!(File::<Vec<u8>>::open(&path));
+        let page = run!(file.get_page(0));
+        let resources = run!(page.resources());
+        for (_, &font) in resources.fonts() {
+            let font = run!(file.get(font));
+            let program = run!(font.program().expect("no embedded font"));
+            assert_eq!(matches!(program, FontProgram::TrueType(_)), truetype);
+
+            let m = program.font_matrix();
+            let mut drawn = 0;
+            for code in 0 .. 256 {
+                let glyph = match font.glyph(&program, code, 1) {
+                    Some(g) => g,
+                    None => continue
+                };
+                assert!(glyph.advance >= 0.);
+                if let Some(&PathOp::MoveTo(p)) = glyph.path.first() {
+                    // glyphs stay within a couple of ems
+                    assert!((m.a * p.x).abs() < 2. && (m.d * p.y).abs() < 2.);
+                    assert_eq!(glyph.path.last(), Some(&PathOp::Close));
+                    drawn += 1;
+                }
+            }
+            assert!(drawn > 0, "no glyphs drawn for {}", font.name);
+        }
+    }
+}
+
+/// The font embedded in libreoffice.pdf with a Unicode `cmap` that maps "A" to "N" to its glyphs.
+fn unicode_test_font() -> Vec<u8> {
+    unicode_test_font_with(vec![])
+}
+
+/// [`unicode_test_font`] with more tables.
+fn unicode_test_font_with(extra: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    use pdf::font::FontProgram;
+
+    let file = run!(File::<Vec<u8>>::open(file_path!("libreoffice.pdf")));
+    let page = run!(file.get_page(0));
+    let font = run!(file.get(page.resources().unwrap().fonts["F1"]));
+    let program = run!(font.program().unwrap());
+    let font = match program {
+        FontProgram::TrueType(ref font) => font,
+        _ => panic!("not a TrueType font")
+    };
+    // format 6, codes from 0x41, glyphs 1 to 14
+    let mut subtable = vec![0, 6, 0, 38, 0, 0, 0, 0x41, 0, 14];
+    for gid in 1 ..= 14u16 {
+        subtable.extend_from_slice(&gid.to_be_bytes());
+    }
+    let cmap = [&[0, 0, 0, 1, 0, 3, 0, 1, 0, 0, 0, 12][..], &subtable].concat();
+    let mut tables = vec![(*b"cmap", cmap)];
+    for tag in [b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"name"] {
+        tables.push((*tag, font.table(tag).unwrap().to_vec()));
+    }
+    tables.extend(extra);
+
+    let mut out = vec![0, 1, 0, 0, 0, tables.len() as u8, 0, 0, 0, 0, 0, 0];
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, data) in &tables {
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        offset += data.len();
+    }
+    for (_, data) in tables {
+        out.extend_from_slice(&data);
+    }
+    out
+}
+
+#[test]
+fn embed_font() {
+    use pdf::build::*;
+    use pdf::content::*;
+    use pdf::font::{FontProgram, FontType};
+    use pdf::text::page_spans;
+
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [] /Count 0 >>",
+    ]);
+    let mut file = run!(File::<Vec<u8>>::from_data(data));
+
+    let mut font = run!(FontBuilder::from_data(&unicode_test_font()));
+    let text = font.encode("CAB");
+    assert_eq!(text.as_bytes(), &[0, 3, 0, 1, 0, 2]);
+    let font = run!(font.build(&mut file));
+
+    let mut page = PageBuilder::default();
+    page.size(200., 100.);
+    let name = page.add_font(font);
+    assert_eq!(page.add_font(font), name);
+    page.content = Some(Content::from_ops(vec![
+        Op::BeginText,
+        Op::TextFont { name, size: 12. },
+        Op::MoveTextPosition { translation: Point { x: 10., y: 10. } },
+        Op::TextDraw { text },
+        Op::EndText,
+    ]));
+    let catalog = run!(CatalogBuilder::from_pages(vec![page]).build(&mut file));
+    run!(file.update_catalog(catalog));
+
+    let out = std::env::temp_dir().join("pdf-embed-font.pdf");
+    run!(file.save_to(&out));
+    let saved = std::fs::read(&out).unwrap();
+    let _ = std::fs::remove_file(&out);
+
+    let file = run!(File::<Vec<u8>>::from_data(saved));
+    let page = run!(file.get_page(0));
+    let spans = run!(page_spans(&page, &file));
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].text, "CAB");
+
+    let font = run!(file.get(page.resources().unwrap().fonts()
+        .next().map(|(_, &font)| font).unwrap()));
+    assert!(font.name.ends_with("+OpenSans"));
+    assert!(matches!(font.subtype, FontType::Type0));
+    let program = match run!(font.program().unwrap()) {
+        FontProgram::TrueType(font) => font,
+        _ => panic!("not a TrueType font")
+    };
+    let original = run!(pdf::font::TrueTypeFont::parse(&unicode_test_font()));
+    assert_eq!(program.glyph_count(), original.glyph_count());
+    for gid in 1 ..= 3 {
+        assert_eq!(run!(program.glyph(gid)).unwrap().path, run!(original.glyph(gid)).unwrap().path);
+    }
+    assert!(run!(program.glyph(4)).unwrap().path.is_empty());
+
+    let widths = run!(font.widths(&file)).unwrap();
+    let scale = 1000. / original.units_per_em() as f32;
+    assert_eq!(widths.get(2), (original.advance(2).unwrap() as f32 * scale).round());
+
+    // only the glyphs of the subset can be used again
+    let encoded = run!(TextEncoder::new(&font)).encode("BAD");
+    assert_eq!(encoded.missing, ['D']);
+    assert!(matches!(&encoded.array[..], [TextDrawAdjusted::Text(s)] if s.as_bytes() == [0, 2, 0, 1]));
+}
+
+#[test]
+fn encode_text() {
+    use pdf::build::TextEncoder;
+    use pdf::content::TextDrawAdjusted;
+
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> >>",
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let page = run!(file.get_page(0));
+    let font = run!(file.get(page.resources().unwrap().fonts["F1"]));
+
+    let encoder = run!(TextEncoder::new(&font));
+    let encoded = encoder.encode("AVé€ 中x");
+    assert_eq!(encoded.missing, ['中']);
+    let mut bytes = vec![];
+    let mut spacing = vec![];
+    for part in &encoded.array {
+        match part {
+            TextDrawAdjusted::Text(s) => bytes.extend_from_slice(s.as_bytes()),
+            &TextDrawAdjusted::Spacing(n) => spacing.push(n),
+        }
+    }
+    assert_eq!(bytes, b"AV\xe9\x80 x");
+    if cfg!(feature = "standard-fonts") {
+        assert_eq!(spacing, [70.]);
+        assert!(matches!(encoded.array[1], TextDrawAdjusted::Spacing(_)));
+    } else {
+        assert!(spacing.is_empty());
+    }
+}
+
+#[test]
+fn encode_text_embedded() {
+    use pdf::build::TextEncoder;
+    use pdf::content::TextDrawAdjusted;
+    use pdf::font::Font;
+
+    // B A kerned by -205 units
+    let kern = [0, 0, 0, 1, 0, 0, 0, 20, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0xFF, 0x33].to_vec();
+    let program = unicode_test_font_with(vec![(*b"kern", kern)]);
+    let units_per_em = run!(pdf::font::TrueTypeFont::parse(&program)).units_per_em() as f32;
+    let hex: String = program.iter().map(|b| format!("{:02X}", b)).collect();
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [] /Count 0 >>",
+        "<< /Type /Font /Subtype /Type0 /BaseFont /Test /Encoding /Identity-H /DescendantFonts [4 0 R] >>",
+        "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /Test /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> \
+            /FontDescriptor 5 0 R >>",
+        "<< /Type /FontDescriptor /FontName /Test /Flags 4 /FontBBox [0 0 1000 1000] /ItalicAngle 0 /Ascent 800 /Descent -200 /FontFile2 6 0 R >>",
+        &format!("<< /Filter /ASCIIHexDecode /Length {} >>\nstream\n{}>\nendstream", hex.len() + 1, hex),
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let font = run!(file.get(Ref::<Font>::new(PlainRef { id: 3, gen: 0 })));
+
+    // without a ToUnicode map, the characters are looked up in the font
+    let encoded = run!(TextEncoder::new(&font)).encode("BAZ");
+    assert_eq!(encoded.missing, ['Z']);
+    match &encoded.array[..] {
+        [TextDrawAdjusted::Text(b), TextDrawAdjusted::Spacing(n), TextDrawAdjusted::Text(a)] => {
+            assert_eq!((b.as_bytes(), a.as_bytes()), (&[0, 2][..], &[0, 1][..]));
+            assert!((n - 205. * 1000. / units_per_em).abs() < 1e-3);
+        }
+        array => panic!("{:?}", array)
+    }
+}
+
 #[test]
 fn parse_objects_from_stream() {
     use pdf::object::NoResolve;
@@ -107,3 +624,231 @@ fn parse_objects_from_stream() {
 }
 
 // TODO test decoding
+
+#[test]
+fn incremental_update() {
+    for &name in &["example.pdf", "xelatex.pdf", "offset.pdf"] {
+        let path = format!("{}{}", file_path!(""), name);
+        let original = std::fs::read(&path).unwrap();
+        let mut file = run!(File::<Vec<u8>>::open(&path));
+        let num_pages = file.num_pages();
+
+        let root = file.trailer.root.get_ref().get_inner();
+        let catalog = run!(file.resolve(root));
+        run!(file.update(root, catalog));
+        let created = run!(file.create(Primitive::Integer(42))).get_ref().get_inner();
+
+        let out = std::env::temp_dir().join(format!("pdf-incremental-{}", name));
+        run!(file.save_to(&out));
+        let saved = std::fs::read(&out).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert!(saved.starts_with(&original), "{}: original revision was modified", name);
+
+        let file = run!(File::<Vec<u8>>::from_data(saved));
+        assert_eq!(file.num_pages(), num_pages);
+        assert!(file.trailer.prev_trailer_pos.is_some());
+        assert!(matches!(run!(file.resolve(created)), Primitive::Integer(42)));
+    }
+}
+
+#[test]
+fn compacted_save() {
+    for &name in &["example.pdf", "xelatex.pdf", "encrypted_aes_128.pdf", "encrypted_rc4_rev2.pdf"] {
+        let path = format!("{}{}", file_path!(""), name);
+        let mut file = run!(File::<Vec<u8>>::open(&path));
+        let num_pages = file.num_pages();
+        let orphan = run!(file.create(Primitive::Integer(42))).get_ref().get_inner();
+
+        let out = std::env::temp_dir().join(format!("pdf-compacted-{}", name));
+        run!(file.save_compacted(&out));
+        let file = run!(File::<Vec<u8>>::open(&out));
+        let _ = std::fs::remove_file(&out);
+
+        assert_eq!(file.num_pages(), num_pages);
+        assert!(file.trailer.prev_trailer_pos.is_none());
+        assert!(file.trailer.encrypt_dict.is_none());
+        assert!((file.trailer.highest_id as u64) <= orphan.id);
+        for page in file.pages() {
+            let page = run!(page);
+            if let Some(ref contents) = page.contents {
+                run!(contents.operations(&file));
+            }
+        }
+    }
+}
+
+#[test]
+fn object_stream_save() {
+    use pdf::file::SaveOptions;
+
+    for &name in &["example.pdf", "xelatex.pdf"] {
+        let path = format!("{}{}", file_path!(""), name);
+        let original = std::fs::read(&path).unwrap();
+        let mut file = run!(File::<Vec<u8>>::open(&path));
+        let num_pages = file.num_pages();
+
+        let root = file.trailer.root.get_ref().get_inner();
+        let catalog = run!(file.resolve(root));
+        run!(file.update(root, catalog));
+        let created: Vec<_> = (0 .. 150)
+            .map(|i| run!(file.create(Primitive::Integer(i))).get_ref().get_inner())
+            .collect();
+
+        let out = std::env::temp_dir().join(format!("pdf-objstm-{}", name));
+        let options = SaveOptions { object_streams: true, ..Default::default() };
+        run!(file.save_to_with(&out, &options));
+        let saved = std::fs::read(&out).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert!(saved.starts_with(&original));
+        assert!(String::from_utf8_lossy(&saved[original.len() ..]).contains("/ObjStm"));
+
+        let file = run!(File::<Vec<u8>>::from_data(saved));
+        assert_eq!(file.num_pages(), num_pages);
+        for (i, &r) in created.iter().enumerate() {
+            assert!(matches!(run!(file.resolve(r)), Primitive::Integer(n) if n == i as i32));
+        }
+    }
+}
+
+#[test]
+fn encrypted_save() {
+    use pdf::file::SaveOptions;
+    use pdf::crypt::{Encryption, EncryptionMethod, Permissions, PasswordType};
+
+    let methods = [EncryptionMethod::Rc4_40, EncryptionMethod::Rc4_128, EncryptionMethod::Aes128, EncryptionMethod::Aes256];
+    for &method in &methods {
+        let mut file = run!(File::<Vec<u8>>::open(file_path!("example.pdf")));
+        let options = SaveOptions {
+            encryption: Some(Encryption {
+                method,
+                user_password: b"user".to_vec(),
+                owner_password: b"owner".to_vec(),
+                permissions: Permissions::PRINT | Permissions::COPY,
+                encrypt_metadata: true,
+            }),
+            ..Default::default()
+        };
+        let out = std::env::temp_dir().join(format!("pdf-encrypted-{:?}.pdf", method));
+        run!(file.save_compacted_with(&out, &options));
+
+        assert!(File::<Vec<u8>>::open(&out).is_err());
+        for &(password, kind) in &[(&b"user"[..], PasswordType::User), (b"owner", PasswordType::Owner)] {
+            let file = run!(File::<Vec<u8>>::open_password(&out, password));
+            assert_eq!(file.password_type(), Some(kind));
+            match kind {
+                PasswordType::User => assert_eq!(file.permissions(), Permissions::PRINT | Permissions::COPY),
+                PasswordType::Owner => assert_eq!(file.permissions(), Permissions::all()),
+            }
+            for page in file.pages() {
+                let page = run!(page);
+                if let Some(ref contents) = page.contents {
+                    assert!(!run!(contents.operations(&file)).is_empty());
+                }
+            }
+        }
+        let _ = std::fs::remove_file(&out);
+    }
+}
+
+#[test]
+fn encrypted_incremental_update() {
+    use pdf::primitive::PdfString;
+
+    for &name in &["encrypted_rc4_rev2.pdf", "encrypted_aes_128.pdf", "encrypted_aes_256.pdf"] {
+        let path = format!("{}{}", file_path!(""), name);
+        let mut file = run!(File::<Vec<u8>>::open(&path));
+        let text = PdfString::new(b"some (secret) text".to_vec());
+        let r = run!(file.create(Primitive::String(text))).get_ref().get_inner();
+
+        let out = std::env::temp_dir().join(format!("pdf-encrypted-update-{}", name));
+        run!(file.save_to(&out));
+        let saved = std::fs::read(&out).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert!(!saved.windows(6).any(|w| w == b"secret"));
+
+        let file = run!(File::<Vec<u8>>::from_data(saved));
+        match run!(file.resolve(r)) {
+            Primitive::String(s) => assert_eq!(s.as_bytes(), b"some (secret) text"),
+            p => panic!("expected a string, found {:?}", p)
+        }
+    }
+}
+
+#[test]
+fn image_pixels() {
+    let image = |dict: &str, hex: &str| format!("<< /Type /XObject /Subtype /Image {} /Filter /ASCIIHexDecode /Length {} >>\nstream\n{}\nendstream", dict, hex.len(), hex);
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Resources << /XObject << /Im1 4 0 R /Im2 6 0 R >> >> >>",
+        &image("/Width 2 /Height 1 /ColorSpace /DeviceRGB /BitsPerComponent 8 /SMask 5 0 R", "FF000000FF00>"),
+        &image("/Width 1 /Height 1 /ColorSpace /DeviceGray /BitsPerComponent 8", "80>"),
+        &image("/Width 2 /Height 2 /ColorSpace /DeviceGray /BitsPerComponent 8 /Mask 7 0 R", "004080FF>"),
+        &image("/Width 1 /Height 2 /ImageMask true", "0080>"),
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let page = run!(file.get_page(0));
+    let resources = page.resources().unwrap();
+    let pixels = |name: &str| match *run!(file.get(resources.xobjects[name])) {
+        XObject::Image(ref image) => run!(image.to_rgba8(&file)),
+        ref xobject => panic!("not an image: {:?}", xobject)
+    };
+
+    // the soft mask is scaled to the size of the image
+    assert_eq!(pixels("Im1"), [255, 0, 0, 128, 0, 255, 0, 128]);
+    // the stencil mask only paints the top row
+    assert_eq!(pixels("Im2"), [0, 0, 0, 255, 64, 64, 64, 255, 128, 128, 128, 0, 255, 255, 255, 0]);
+}
+
+#[test]
+fn extract_images() {
+    use pdf::images::{document_images, page_images, ImageFormat};
+
+    let stream = |dict: &str, data: &str| format!("<< {} /Length {} >>\nstream\n{}\nendstream", dict, data.len(), data);
+    let content = "q BI /W 2 /H 1 /CS /CS0 /BPC 8 /F /AHx ID 0001> EI Q /Im1 Do\n";
+    let data = build_pdf(&[
+        "<< /Type /Catalog /Pages 2 0 R >>",
+        "<< /Type /Pages /Kids [3 0 R 4 0 R 10 0 R] /Count 3 >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Contents 5 0 R \
+            /Resources << /XObject << /Im1 6 0 R /Im2 7 0 R /Fm1 8 0 R >> /ColorSpace << /CS0 [/Indexed /DeviceRGB 1 <FF000000FF00>] >> >> >>",
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Resources << /XObject << /Im2 7 0 R >> >> >>",
+        &stream("", content),
+        &stream("/Type /XObject /Subtype /Image /Width 3 /Height 2 /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode", "not really a JPEG"),
+        &stream("/Type /XObject /Subtype /Image /Width 1 /Height 1 /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /ASCIIHexDecode", "0080FF>"),
+        &stream("/Type /XObject /Subtype /Form /BBox [0 0 1 1] /Resources << /XObject << /Im3 9 0 R >> >>", "/Im3 Do\n"),
+        &stream("/Type /XObject /Subtype /Image /Width 1728 /Height 4 /ImageMask true \
+            /Filter /CCITTFaxDecode /DecodeParms << /K -1 /Columns 1728 >>", "fax"),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Contents 11 0 R /Resources << /XObject << /Im4 12 0 R /Im5 13 0 R >> >> >>",
+        &stream("/Filter /ASCIIHexDecode", "not hex>"),
+        &stream("/Type /XObject /Subtype /Image /Width 1 /Height 1 /ColorSpace /DeviceGray /BitsPerComponent 8", "x"),
+        &stream("/Type /XObject /Subtype /Image /Width 1728 /Height 4 /ImageMask true \
+            /Filter /CCITTFaxDecode /DecodeParms << /K 0 /Columns 1728 >>", "fax"),
+    ]);
+    let file = run!(File::<Vec<u8>>::from_data(data));
+    let images = run!(document_images(&file));
+    let summary: Vec<_> = images.iter().map(|i| (i.page, i.id.map(|r| r.id), i.width, i.height, i.format)).collect();
+    // inline images first, then the XObjects by name
+    assert_eq!(summary, [
+        (0, None, 2, 1, ImageFormat::Png),
+        (0, Some(9), 1728, 4, ImageFormat::Tiff),
+        (0, Some(6), 3, 2, ImageFormat::Jpeg),
+        (0, Some(7), 1, 1, ImageFormat::Png),
+        // contents that can't be read don't hide the XObjects, group 3 fax images are skipped
+        (2, Some(12), 1, 1, ImageFormat::Png),
+    ]);
+    assert!(images[1].data.starts_with(b"II*\0") && images[1].data.ends_with(b"fax"));
+    assert_eq!(images[2].data, b"not really a JPEG");
+
+    // the inline image uses the palette from the resources
+    let png = &images[0].data;
+    assert_eq!(&png[12 .. 16], b"IHDR");
+    assert_eq!(png[25], 2);
+    let len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+    assert_eq!(inflate::inflate_bytes_zlib(&png[41 .. 41 + len]).unwrap(), [0, 255, 0, 0, 0, 255, 0]);
+
+    // shared images are reported for each page on their own
+    let page = run!(file.get_page(1));
+    let images = run!(page_images(&page, 1, &file));
+    assert_eq!(images.len(), 1);
+    assert_eq!((images[0].page, images[0].id.map(|r| r.id)), (1, Some(7)));
+}
@@ -8,3 +8,83 @@ fn ending_angle_bracket() {
     assert!(pdf::file::File::from_data(b"%PDF-startxref>".as_ref()).is_err());
     assert!(pdf::file::File::from_data(b"%PDF-startxref<".as_ref()).is_err());
 }
+
+fn find_last(data: &[u8], needle: &[u8]) -> usize {
+    data.windows(needle.len()).rposition(|w| w == needle).unwrap()
+}
+
+#[test]
+fn repair_broken_startxref() {
+    for &name in &["../files/example.pdf", "../files/xelatex.pdf"] {
+        let mut data = std::fs::read(name).unwrap();
+        let num_pages = pdf::file::File::from_data(data.clone()).unwrap().num_pages();
+
+        // point startxref into the middle of nowhere
+        let pos = find_last(&data, b"startxref");
+        data.truncate(pos);
+        data.extend_from_slice(b"startxref\n17\n%%EOF\n");
+
+        let file = pdf::file::File::from_data(data).unwrap();
+        assert_eq!(file.num_pages(), num_pages);
+        for page in file.pages() {
+            page.unwrap();
+        }
+    }
+}
+
+#[test]
+fn repair_missing_trailer() {
+    let mut data = std::fs::read("../files/example.pdf").unwrap();
+    let pos = find_last(&data, b"xref");
+    data.truncate(pos);
+
+    let file = pdf::file::File::from_data(data).unwrap();
+    assert_eq!(file.num_pages(), 1);
+}
+
+#[test]
+fn save_repaired() {
+    use pdf::crypt::{Encryption, EncryptionMethod, Permissions};
+    use pdf::file::SaveOptions;
+    use pdf::object::{Resolve, Updater};
+    use pdf::primitive::Primitive;
+
+    let mut data = std::fs::read("../files/xelatex.pdf").unwrap();
+    let pos = find_last(&data, b"startxref");
+    data.truncate(pos);
+    data.extend_from_slice(b"startxref\n17\n%%EOF\n");
+
+    let mut file = pdf::file::File::from_data(data.clone()).unwrap();
+    let num_pages = file.num_pages();
+    let created = file.create(Primitive::Integer(42)).unwrap().get_ref().get_inner();
+
+    // the body of the file is not encrypted, so it can't be encrypted with the update
+    let encryption = Encryption {
+        method: EncryptionMethod::Aes128,
+        user_password: b"user".to_vec(),
+        owner_password: vec![],
+        permissions: Permissions::all(),
+        encrypt_metadata: true,
+    };
+    let options = SaveOptions { encryption: Some(encryption), ..Default::default() };
+    let out = std::env::temp_dir().join("pdf-repaired-encrypted.pdf");
+    assert!(file.save_to_with(&out, &options).is_err());
+
+    let out = std::env::temp_dir().join("pdf-repaired.pdf");
+    file.save_to(&out).unwrap();
+    let saved = std::fs::read(&out).unwrap();
+    let _ = std::fs::remove_file(&out);
+    assert!(saved.starts_with(&data));
+
+    // every object is reachable through the new section
+    let file = pdf::file::File::from_data(saved).unwrap();
+    assert!(file.trailer.prev_trailer_pos.is_none());
+    assert_eq!(file.num_pages(), num_pages);
+    for page in file.pages() {
+        let page = page.unwrap();
+        if let Some(ref contents) = page.contents {
+            contents.operations(&file).unwrap();
+        }
+    }
+    assert!(matches!(file.resolve(created).unwrap(), Primitive::Integer(42)));
+}